@@ -0,0 +1,393 @@
+use std::{
+    fmt,
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    handlers::json_string,
+    request::{HttpVersion, Request},
+};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// How access log lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Apache Common Log Format, for people and classic log tools.
+    #[default]
+    Common,
+    /// One JSON object per line, for log pipelines.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "common" => Some(Self::Common),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// One served request, rendered by `Display` as an Apache Common Log Format
+/// line: `host - - [date] "METHOD target VERSION" status bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLog {
+    pub host: Option<IpAddr>,
+    pub time: SystemTime,
+    pub method: String,
+    /// Path plus `?query`, as the client sent it.
+    pub target: String,
+    pub version: HttpVersion,
+    pub status: u16,
+    /// Body bytes written; `0` is logged as `-` like Apache does.
+    pub bytes: usize,
+    /// Time from receiving the request to finishing the response. Only in
+    /// the JSON format, since CLF has no field for it.
+    pub duration: Duration,
+    pub request_id: Option<String>,
+}
+
+impl RequestLog {
+    /// Record `request` as answered now with `status` and `bytes` of body,
+    /// `duration` after it arrived.
+    pub fn new(request: &Request, status: u16, bytes: usize, duration: Duration) -> Self {
+        let target = match &request.query {
+            Some(query) => format!("{}?{}", request.path, query),
+            None => request.path.clone(),
+        };
+        Self {
+            host: request.peer_addr.map(|addr| addr.ip()),
+            time: SystemTime::now(),
+            method: request.method.clone(),
+            target,
+            version: request.http_version,
+            status,
+            bytes,
+            duration,
+            request_id: request.request_id.clone(),
+        }
+    }
+
+    /// The log line in `format`.
+    pub fn render(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => self.to_string(),
+            LogFormat::Json => self.to_json(),
+        }
+    }
+
+    /// A single-line JSON object; absent values are `null`.
+    fn to_json(&self) -> String {
+        let or_null = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+        format!(
+            "{{\"time\":\"{}\",\"host\":{},\"request_id\":{},\"method\":{},\"path\":{},\"version\":\"{}\",\"status\":{},\"bytes\":{},\"ms\":{:.3}}}",
+            IsoTime(self.time),
+            or_null(self.host.map(|h| json_string(&h.to_string()))),
+            or_null(self.request_id.as_deref().map(json_string)),
+            json_string(&self.method),
+            json_string(&self.target),
+            self.version,
+            self.status,
+            self.bytes,
+            self.duration.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+impl fmt::Display for RequestLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host {
+            Some(host) => write!(f, "{}", host)?,
+            None => f.write_str("-")?,
+        }
+        write!(
+            f,
+            " - - [{}] \"{} {} {}\" {} ",
+            ClfTime(self.time),
+            self.method,
+            self.target,
+            self.version,
+            self.status
+        )?;
+        match self.bytes {
+            0 => f.write_str("-"),
+            n => write!(f, "{}", n),
+        }
+    }
+}
+
+/// A timestamp in CLF's `dd/Mon/yyyy:HH:MM:SS +0000` form, always in UTC.
+struct ClfTime(SystemTime);
+
+impl fmt::Display for ClfTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self
+            .0
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let rem = secs % 86_400;
+        write!(
+            f,
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            day,
+            MONTHS[(month - 1) as usize],
+            year,
+            rem / 3600,
+            rem % 3600 / 60,
+            rem % 60
+        )
+    }
+}
+
+/// A timestamp in RFC 3339 form (`2000-10-10T13:55:36Z`), always in UTC.
+struct IsoTime(SystemTime);
+
+impl fmt::Display for IsoTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self
+            .0
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        let rem = secs % 86_400;
+        write!(
+            f,
+            "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            rem / 3600,
+            rem % 3600 / 60,
+            rem % 60
+        )
+    }
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date in the
+/// proleptic Gregorian calendar (Howard Hinnant's algorithm).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn entry() -> RequestLog {
+        RequestLog {
+            host: Some("127.0.0.1".parse().unwrap()),
+            // 2000-10-10T13:55:36Z, the example from the Apache docs
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            method: "GET".to_string(),
+            target: "/apache_pb.gif".to_string(),
+            version: HttpVersion::Http10,
+            status: 200,
+            bytes: 2326,
+            duration: Duration::from_micros(1500),
+            request_id: Some("abc-1".to_string()),
+        }
+    }
+
+    /// Parse a flat JSON object of strings, numbers and nulls, as the JSON
+    /// format writes, into `(key, value)` pairs. Strings keep their quotes
+    /// stripped and escapes decoded; numbers and `null` are returned raw.
+    fn parse_flat_json(line: &str) -> Vec<(String, String)> {
+        fn string(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+            assert_eq!(chars.next(), Some('"'));
+            let mut out = String::new();
+            loop {
+                match chars.next().expect("unterminated string") {
+                    '"' => return out,
+                    '\\' => match chars.next().unwrap() {
+                        'n' => out.push('\n'),
+                        't' => out.push('\t'),
+                        'r' => out.push('\r'),
+                        'u' => {
+                            let hex: String = chars.by_ref().take(4).collect();
+                            out.push(
+                                char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap(),
+                            );
+                        }
+                        c => out.push(c),
+                    },
+                    c => out.push(c),
+                }
+            }
+        }
+
+        let mut chars = line.chars().peekable();
+        assert_eq!(chars.next(), Some('{'), "{}", line);
+        let mut fields = Vec::new();
+        loop {
+            let key = string(&mut chars);
+            assert_eq!(chars.next(), Some(':'), "{}", line);
+            let value = if chars.peek() == Some(&'"') {
+                string(&mut chars)
+            } else {
+                let mut raw = String::new();
+                while let Some(&c) = chars.peek().filter(|&&c| c != ',' && c != '}') {
+                    raw.push(c);
+                    chars.next();
+                }
+                assert!(raw == "null" || raw.parse::<f64>().is_ok(), "{}", raw);
+                raw
+            };
+            fields.push((key, value));
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                other => panic!("unexpected {:?} in {}", other, line),
+            }
+        }
+        assert_eq!(chars.next(), None, "{}", line);
+        fields
+    }
+
+    fn field<'a>(fields: &'a [(String, String)], key: &str) -> &'a str {
+        &fields.iter().find(|(k, _)| k == key).expect(key).1
+    }
+
+    // ── Display ──────────────────────────────────────────────────────
+
+    #[test]
+    fn test_display_common_log_format() {
+        assert_eq!(
+            entry().to_string(),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326"
+        );
+    }
+
+    #[test]
+    fn test_display_missing_host_and_empty_body_are_dashes() {
+        let log = RequestLog {
+            host: None,
+            status: 304,
+            bytes: 0,
+            ..entry()
+        };
+        let line = log.to_string();
+        assert!(line.starts_with("- - - ["), "{}", line);
+        assert!(line.ends_with("\" 304 -"), "{}", line);
+    }
+
+    #[test]
+    fn test_display_ipv6_host() {
+        let log = RequestLog {
+            host: Some("::1".parse().unwrap()),
+            ..entry()
+        };
+        assert!(log.to_string().starts_with("::1 - - ["));
+    }
+
+    // ── JSON ─────────────────────────────────────────────────────────
+
+    #[test]
+    fn test_log_format_parse() {
+        assert_eq!(LogFormat::parse("common"), Some(LogFormat::Common));
+        assert_eq!(LogFormat::parse("json"), Some(LogFormat::Json));
+        assert_eq!(LogFormat::parse("xml"), None);
+        assert_eq!(LogFormat::default(), LogFormat::Common);
+    }
+
+    #[test]
+    fn test_render_common_matches_display() {
+        assert_eq!(entry().render(LogFormat::Common), entry().to_string());
+    }
+
+    #[test]
+    fn test_render_json_round_trips() {
+        let line = entry().render(LogFormat::Json);
+        assert!(!line.contains('\n'));
+        let fields = parse_flat_json(&line);
+        assert_eq!(field(&fields, "time"), "2000-10-10T13:55:36Z");
+        assert_eq!(field(&fields, "host"), "127.0.0.1");
+        assert_eq!(field(&fields, "request_id"), "abc-1");
+        assert_eq!(field(&fields, "method"), "GET");
+        assert_eq!(field(&fields, "path"), "/apache_pb.gif");
+        assert_eq!(field(&fields, "version"), "HTTP/1.0");
+        assert_eq!(field(&fields, "status").parse::<u16>().unwrap(), 200);
+        assert_eq!(field(&fields, "bytes").parse::<usize>().unwrap(), 2326);
+        assert_eq!(field(&fields, "ms").parse::<f64>().unwrap(), 1.5);
+    }
+
+    #[test]
+    fn test_render_json_nulls_and_escapes() {
+        let log = RequestLog {
+            host: None,
+            request_id: None,
+            target: "/say \"hi\"\\now".to_string(),
+            ..entry()
+        };
+        let fields = parse_flat_json(&log.render(LogFormat::Json));
+        assert_eq!(field(&fields, "host"), "null");
+        assert_eq!(field(&fields, "request_id"), "null");
+        assert_eq!(field(&fields, "path"), "/say \"hi\"\\now");
+    }
+
+    // ── RequestLog::new ──────────────────────────────────────────────
+
+    #[test]
+    fn test_new_includes_query_in_target() {
+        let request = Request {
+            method: "POST".to_string(),
+            path: "/files/a.txt".to_string(),
+            query: Some("x=1".to_string()),
+            http_version: HttpVersion::Http11,
+            headers: Vec::new(),
+            body: None,
+            peer_addr: Some("10.0.0.7:54321".parse().unwrap()),
+            request_id: Some("req-9".to_string()),
+        };
+        let log = RequestLog::new(&request, 201, 0, Duration::from_millis(3));
+        assert_eq!(log.target, "/files/a.txt?x=1");
+        assert_eq!(log.host, Some("10.0.0.7".parse().unwrap()));
+        assert_eq!(log.method, "POST");
+        assert_eq!(log.status, 201);
+        assert_eq!(log.duration, Duration::from_millis(3));
+        assert_eq!(log.request_id.as_deref(), Some("req-9"));
+    }
+
+    // ── Timestamps ───────────────────────────────────────────────────
+
+    #[test]
+    fn test_clf_time_epoch() {
+        assert_eq!(
+            ClfTime(UNIX_EPOCH).to_string(),
+            "01/Jan/1970:00:00:00 +0000"
+        );
+    }
+
+    #[test]
+    fn test_clf_time_leap_day() {
+        // 2024-02-29T23:59:59Z
+        let t = UNIX_EPOCH + Duration::from_secs(1_709_251_199);
+        assert_eq!(ClfTime(t).to_string(), "29/Feb/2024:23:59:59 +0000");
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_723), (2024, 1, 1));
+    }
+}
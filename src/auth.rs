@@ -0,0 +1,372 @@
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::{
+    cors::is_preflight,
+    middleware::{Middleware, Next},
+    request::Request,
+    response::Response,
+    router::BoxFuture,
+};
+
+/// A username and password accepted by [`BasicAuth`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    /// Parse `user:password`. The password may itself contain `:`.
+    pub fn parse(s: &str) -> Result<Self> {
+        let (username, password) = s
+            .split_once(':')
+            .context("credentials must be in the form user:password")?;
+        Ok(Self {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        })
+    }
+
+    /// Compare against another username and password without leaking, through
+    /// timing, how much of either matched.
+    fn matches(&self, username: &str, password: &str) -> bool {
+        let user_ok = self.username.as_bytes().ct_eq(username.as_bytes());
+        let pass_ok = self.password.as_bytes().ct_eq(password.as_bytes());
+        (user_ok & pass_ok).into()
+    }
+}
+
+/// The parameter of an `Authorization: <scheme> <param>` header, if the
+/// scheme matches (case-insensitively).
+fn authorization_param<'a>(request: &'a Request, scheme: &str) -> Option<&'a str> {
+    let (given, param) = request.header_value("Authorization")?.split_once(' ')?;
+    given.eq_ignore_ascii_case(scheme).then(|| param.trim())
+}
+
+/// Decode the `user:password` pair from a Basic `Authorization` header.
+fn basic_credentials(request: &Request) -> Option<(String, String)> {
+    let decoded = STANDARD
+        .decode(authorization_param(request, "Basic")?)
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_owned(), password.to_owned()))
+}
+
+/// HTTP Basic authentication guard.
+///
+/// Requests without valid credentials get `401 Unauthorized` with a
+/// `WWW-Authenticate` challenge for `realm` and never reach the route.
+/// CORS preflights pass through, since browsers send them without
+/// credentials.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    credentials: Credentials,
+    realm: String,
+}
+
+impl BasicAuth {
+    pub fn new(credentials: Credentials, realm: &str) -> Self {
+        Self {
+            credentials,
+            realm: realm.to_owned(),
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        basic_credentials(request)
+            .is_some_and(|(username, password)| self.credentials.matches(&username, &password))
+    }
+
+    fn challenge(&self) -> Response {
+        let mut resp = Response::error(401, "Unauthorized");
+        resp.header(
+            "WWW-Authenticate",
+            &format!("Basic realm=\"{}\"", self.realm),
+        );
+        resp
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>> {
+        if !is_preflight(request) && !self.is_authorized(request) {
+            return Box::pin(async move { Ok(self.challenge()) });
+        }
+        next.run(request)
+    }
+}
+
+/// Bearer token guard, e.g. for API routes (wrap it in
+/// [`Scoped`](crate::middleware::Scoped) to protect only some paths).
+///
+/// Requests without an `Authorization: Bearer <token>` header carrying one
+/// of the configured tokens get `401 Unauthorized`.
+#[derive(Debug, Clone)]
+pub struct BearerAuth {
+    tokens: Vec<String>,
+}
+
+impl BearerAuth {
+    pub fn new<I, S>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            tokens: tokens.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Every configured token is compared, in constant time, so the response
+    /// time doesn't reveal which token (or how much of one) matched.
+    fn is_authorized(&self, request: &Request) -> bool {
+        let Some(given) = authorization_param(request, "Bearer").filter(|t| !t.is_empty()) else {
+            return false;
+        };
+        self.tokens
+            .iter()
+            .fold(Choice::from(0), |found, token| {
+                found | token.as_bytes().ct_eq(given.as_bytes())
+            })
+            .into()
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn handle<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>> {
+        if !self.is_authorized(request) {
+            return Box::pin(async {
+                let mut resp = Response::error(401, "Unauthorized");
+                resp.header("WWW-Authenticate", "Bearer");
+                Ok(resp)
+            });
+        }
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ServerConfig, middleware::Scoped, request::HttpVersion, router::Router};
+
+    fn request(authorization: Option<&str>) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/files/a.txt".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: authorization
+                .map(|v| vec![("Authorization".to_string(), v.to_string())])
+                .unwrap_or_default(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    fn basic(user_pass: &str) -> String {
+        format!("Basic {}", STANDARD.encode(user_pass))
+    }
+
+    fn guard() -> BasicAuth {
+        BasicAuth::new(Credentials::parse("alice:s3cret").unwrap(), "files")
+    }
+
+    // ── Credentials ──────────────────────────────────────────────────
+
+    #[test]
+    fn test_credentials_parse() {
+        let c = Credentials::parse("alice:pa:ss").unwrap();
+        assert_eq!(c.username, "alice");
+        assert_eq!(c.password, "pa:ss");
+        assert!(Credentials::parse("no-colon").is_err());
+    }
+
+    #[test]
+    fn test_credentials_matches() {
+        let c = Credentials::parse("alice:s3cret").unwrap();
+        assert!(c.matches("alice", "s3cret"));
+        assert!(!c.matches("alice", "s3cre"));
+        assert!(!c.matches("alice", "s3cret!"));
+        assert!(!c.matches("bob", "s3cret"));
+    }
+
+    // ── Basic header parsing ─────────────────────────────────────────
+
+    #[test]
+    fn test_basic_credentials_decoded() {
+        let req = request(Some(&basic("alice:s3cret")));
+        assert_eq!(
+            basic_credentials(&req),
+            Some(("alice".to_string(), "s3cret".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_basic_credentials_scheme_case_insensitive() {
+        let header = basic("alice:s3cret").replace("Basic", "bAsIc");
+        assert!(basic_credentials(&request(Some(&header))).is_some());
+    }
+
+    #[test]
+    fn test_basic_credentials_malformed() {
+        assert_eq!(basic_credentials(&request(None)), None);
+        assert_eq!(basic_credentials(&request(Some("Basic"))), None);
+        assert_eq!(basic_credentials(&request(Some("Basic !!!"))), None);
+        assert_eq!(basic_credentials(&request(Some("Bearer abc"))), None);
+        // Valid base64, but no colon separator
+        let no_colon = format!("Basic {}", STANDARD.encode("alice"));
+        assert_eq!(basic_credentials(&request(Some(&no_colon))), None);
+    }
+
+    // ── BasicAuth ────────────────────────────────────────────────────
+
+    #[test]
+    fn test_basic_auth_is_authorized() {
+        let guard = guard();
+        assert!(guard.is_authorized(&request(Some(&basic("alice:s3cret")))));
+        assert!(!guard.is_authorized(&request(Some(&basic("alice:wrong")))));
+        assert!(!guard.is_authorized(&request(None)));
+    }
+
+    #[test]
+    fn test_basic_auth_challenge() {
+        let raw = String::from_utf8(guard().challenge().build_raw()).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+        assert!(raw.contains("WWW-Authenticate: Basic realm=\"files\"\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_basic_auth_skips_preflight() {
+        let mut router = Router::new();
+        router
+            .route("PUT", "/files/*", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("put")) })
+            })
+            .layer(guard());
+        let mut req = request(None);
+        req.method = "OPTIONS".to_string();
+        req.headers = vec![
+            ("Origin".to_string(), "https://a.example".to_string()),
+            (
+                "Access-Control-Request-Method".to_string(),
+                "PUT".to_string(),
+            ),
+        ];
+        let config = ServerConfig::default();
+
+        assert_eq!(
+            router.dispatch(&req, &config).await.unwrap().status_code(),
+            204
+        );
+        req.headers.truncate(1);
+        assert_eq!(
+            router.dispatch(&req, &config).await.unwrap().status_code(),
+            401
+        );
+    }
+
+    // ── BearerAuth ───────────────────────────────────────────────────
+
+    fn bearer_router() -> Router {
+        let mut router = Router::new();
+        router
+            .route("GET", "/api/data", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("data")) })
+            })
+            .route("GET", "/public", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("public")) })
+            })
+            .layer(Scoped::new(
+                "/api/",
+                BearerAuth::new(["token-a", "token-b"]),
+            ));
+        router
+    }
+
+    async fn status_for(path: &str, authorization: Option<&str>) -> u16 {
+        let mut req = request(authorization);
+        req.path = path.to_string();
+        bearer_router()
+            .dispatch(&req, &ServerConfig::default())
+            .await
+            .unwrap()
+            .status_code()
+    }
+
+    #[test]
+    fn test_bearer_is_authorized() {
+        let guard = BearerAuth::new(["token-a", "token-b"]);
+        assert!(guard.is_authorized(&request(Some("Bearer token-a"))));
+        assert!(guard.is_authorized(&request(Some("bearer token-b"))));
+        assert!(!guard.is_authorized(&request(Some("Bearer token-c"))));
+        assert!(!guard.is_authorized(&request(Some("Bearer token-a-and-more"))));
+    }
+
+    #[test]
+    fn test_bearer_no_tokens_rejects_everything() {
+        let guard = BearerAuth::new(Vec::<String>::new());
+        assert!(!guard.is_authorized(&request(Some("Bearer anything"))));
+    }
+
+    #[tokio::test]
+    async fn test_bearer_valid_token_passes_through() {
+        assert_eq!(status_for("/api/data", Some("Bearer token-b")).await, 200);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_invalid_token_is_401() {
+        assert_eq!(status_for("/api/data", Some("Bearer nope")).await, 401);
+        assert_eq!(status_for("/api/data", None).await, 401);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_malformed_authorization_is_401() {
+        for header in [
+            "Bearer",
+            "Bearer ",
+            "Token token-a",
+            "token-a",
+            &basic("a:b"),
+        ] {
+            assert_eq!(
+                status_for("/api/data", Some(header)).await,
+                401,
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bearer_challenge_header() {
+        let resp = bearer_router()
+            .dispatch(
+                &Request {
+                    path: "/api/data".to_string(),
+                    ..request(None)
+                },
+                &ServerConfig::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.headers_all("WWW-Authenticate"), vec!["Bearer"]);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_only_guards_selected_routes() {
+        assert_eq!(status_for("/public", None).await, 200);
+    }
+}
@@ -0,0 +1,1095 @@
+use std::{
+    fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    access_log::LogFormat,
+    auth::Credentials,
+    cors::CorsConfig,
+    error_pages::ErrorPages,
+    forwarded::{ForwardedFor, TrustedProxies},
+    rate_limit::RateLimitConfig,
+    request::RequestLimits,
+    response::{DEFAULT_COMPRESSION_LEVEL, MAX_COMPRESSION_LEVEL},
+};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1";
+pub const DEFAULT_PORT: u16 = 4221;
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(15);
+pub const DEFAULT_OPEN_FILE_WAIT: Duration = Duration::from_secs(5);
+pub const DEFAULT_HEALTH_PATH: &str = "/healthz";
+pub const DEFAULT_MAX_KEEPALIVE_REQUESTS: usize = 100;
+pub const DEFAULT_LISTEN_BACKLOG: u32 = 1024;
+
+/// What to do with a new connection when `max_connections` are already open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverloadPolicy {
+    /// Stop accepting until a connection closes; new clients wait in the
+    /// listen backlog.
+    #[default]
+    Queue,
+    /// Answer `503 Service Unavailable` and close immediately.
+    Reject,
+}
+
+impl OverloadPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "queue" => Some(Self::Queue),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// Whether paths that differ from a route only by a trailing slash are
+/// redirected to the routed form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    /// Leave paths alone; a missing or extra slash is a 404.
+    #[default]
+    Off,
+    /// Redirect `/path/` to `/path`.
+    Strip,
+    /// Redirect `/path` to `/path/`.
+    Append,
+}
+
+impl TrailingSlash {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "off" => Some(Self::Off),
+            "strip" => Some(Self::Strip),
+            "append" => Some(Self::Append),
+            _ => None,
+        }
+    }
+}
+
+/// Server settings, usually built from command-line arguments.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Host name or IP address to bind to.
+    pub address: String,
+    pub port: u16,
+    /// Listen on this Unix domain socket instead of `address:port`.
+    pub unix_socket: Option<PathBuf>,
+    /// Directory served by the `/files/` routes.
+    pub directory: PathBuf,
+    /// Create `directory` (and its parents) at startup if it's missing.
+    pub create_dir: bool,
+    /// Generate an HTML listing for directories without an `index.html`.
+    pub autoindex: bool,
+    /// Also serve `directory` read-only from the site root, for paths no
+    /// built-in route claims: `GET /style.css` serves `style.css`, and
+    /// `GET /` its `index.html` (or the usual empty 200 without one). Any
+    /// path may then name a file, so `basic_auth` guards every route rather
+    /// than just `/files/`.
+    pub serve_root: bool,
+    /// How long to wait for a complete request before answering 408 and
+    /// closing. Restarts for each request on a keep-alive connection.
+    pub read_timeout: Duration,
+    /// How long a keep-alive connection may sit idle after a response
+    /// before it's closed without one.
+    pub idle_timeout: Duration,
+    /// Requests served on one keep-alive connection before it's closed, so
+    /// no single client holds a connection forever.
+    pub max_keepalive_requests: usize,
+    /// Request-line and header size limits applied while parsing requests.
+    pub limits: RequestLimits,
+    /// Origins allowed to make cross-origin requests.
+    pub cors: CorsConfig,
+    /// When set, the `/files/` routes require these Basic credentials (and,
+    /// with `serve_root`, every other route too).
+    pub basic_auth: Option<Credentials>,
+    /// Serve request counters on `/metrics` in the Prometheus text format.
+    pub metrics: bool,
+    /// Upper bound on connections handled at once; `None` is unlimited.
+    pub max_connections: Option<usize>,
+    /// How connections beyond `max_connections` are treated.
+    pub overload: OverloadPolicy,
+    /// Upper bound on files held open at once to serve `/files/` GETs;
+    /// `None` is unlimited. Requests over it wait up to `open_file_wait`
+    /// for one to close, then get `503 Service Unavailable`.
+    pub max_open_files: Option<usize>,
+    /// How long a GET waits for a file slot under `max_open_files`.
+    pub open_file_wait: Duration,
+    /// Per-client-IP request budget; `None` disables rate limiting.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Proxies whose `X-Forwarded-For` names the client for access logs and
+    /// rate limiting.
+    pub trusted_proxies: TrustedProxies,
+    /// Custom bodies for error responses, by status code.
+    pub error_pages: ErrorPages,
+    /// Answer `400 Bad Request` to HTTP/1.1 requests without a `Host`
+    /// header. Turning this off is lenient mode for sloppy clients.
+    pub require_host: bool,
+    /// Answer `TRACE` by echoing the request back. Off by default, since
+    /// reflecting requests can help cross-site tracing attacks.
+    pub trace: bool,
+    /// Set `TCP_NODELAY` on accepted connections, so small responses go out
+    /// without waiting to be coalesced.
+    pub tcp_nodelay: bool,
+    /// Enable TCP keepalive probes after this long idle; `None` leaves the
+    /// system default (usually off).
+    pub tcp_keepalive: Option<Duration>,
+    /// Connections the OS queues for accepting before it starts dropping
+    /// new ones; bursts beyond this see SYNs go unanswered.
+    pub listen_backlog: u32,
+    /// How access log lines are written.
+    pub log_format: LogFormat,
+    /// Report how long each response took to produce in an
+    /// `X-Response-Time` header, for diagnosing slow handlers.
+    pub response_time_header: bool,
+    /// Echo the connecting address back in an `X-Peer-Addr` header, for
+    /// debugging. Off by default so addresses don't leak.
+    pub peer_addr_header: bool,
+    /// Declared on `text/*` responses that don't name a charset, so clients
+    /// don't have to guess; `None` leaves them as the handler set them.
+    pub default_charset: Option<String>,
+    /// gzip and deflate level for compressing response bodies on the fly,
+    /// from 1 (fastest) to 9 (smallest); 0 sends them stored.
+    pub compression_level: u32,
+    /// Answer `301 Moved Permanently` for unrouted paths that only differ
+    /// from a route by a trailing slash.
+    pub trailing_slash: TrailingSlash,
+    /// Path answered with `200 ok` for liveness probes, ahead of the
+    /// router and its middleware; `None` disables it.
+    pub health_path: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: DEFAULT_ADDRESS.to_string(),
+            port: DEFAULT_PORT,
+            unix_socket: None,
+            directory: PathBuf::from("."),
+            create_dir: false,
+            autoindex: false,
+            serve_root: false,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            max_keepalive_requests: DEFAULT_MAX_KEEPALIVE_REQUESTS,
+            limits: RequestLimits::default(),
+            cors: CorsConfig::default(),
+            basic_auth: None,
+            metrics: false,
+            max_connections: None,
+            overload: OverloadPolicy::default(),
+            max_open_files: None,
+            open_file_wait: DEFAULT_OPEN_FILE_WAIT,
+            rate_limit: None,
+            trusted_proxies: TrustedProxies::default(),
+            error_pages: ErrorPages::default(),
+            require_host: true,
+            trace: false,
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            listen_backlog: DEFAULT_LISTEN_BACKLOG,
+            log_format: LogFormat::default(),
+            response_time_header: false,
+            peer_addr_header: false,
+            default_charset: None,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            trailing_slash: TrailingSlash::default(),
+            health_path: Some(DEFAULT_HEALTH_PATH.to_string()),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Parse `--address <host>`, `--port <port>`, `--unix <path>`,
+    /// `--directory <path>`, `--create-dir`, `--autoindex`, `--serve-root`,
+    /// `--read-timeout <secs>`, `--idle-timeout <secs>`,
+    /// `--max-keepalive-requests <n>`, `--max-request-line <n>`,
+    /// `--max-headers <n>`, `--max-header-bytes <n>`,
+    /// `--max-body-size <bytes>`, `--max-path-depth <n>`,
+    /// `--cors-origin <origin>` (repeatable), `--cors-max-age <secs>`,
+    /// `--basic-auth <user:password>`, `--metrics`, `--max-connections <n>`,
+    /// `--overload <queue|reject>`, `--max-open-files <n>`,
+    /// `--open-file-wait <secs>`, `--rate-limit <requests/sec>`,
+    /// `--rate-burst <n>`, `--trusted-proxy <ip>` (repeatable),
+    /// `--forwarded-for <leftmost|rightmost>`, `--error-page <status>=<path>`
+    /// (repeatable), `--allow-missing-host`, `--case-insensitive-methods`,
+    /// `--enable-trace`, `--no-tcp-nodelay`, `--tcp-keepalive <secs>`,
+    /// `--listen-backlog <n>`, `--log-format <common|json>`,
+    /// `--response-time-header`, `--peer-addr-header`, `--charset <name>`,
+    /// `--compression-level <0-9>`, `--trailing-slash <off|strip|append>`,
+    /// `--health-path <path>` and `--no-health-check` from an argument list
+    /// (without the program name). The burst defaults to one second's worth of
+    /// requests. An address of the form `unix:<path>` is the same as
+    /// `--unix <path>`.
+    ///
+    /// Missing flags keep their defaults; unknown flags, missing values and
+    /// invalid numbers are errors.
+    pub fn from_args<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        let mut rate_burst = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("missing value for {}", arg))
+            };
+
+            match arg.as_str() {
+                "--address" => {
+                    let address = value()?;
+                    match address.strip_prefix("unix:") {
+                        Some(path) => config.unix_socket = Some(PathBuf::from(path)),
+                        None => config.address = address,
+                    }
+                }
+                "--unix" => config.unix_socket = Some(PathBuf::from(value()?)),
+                "--port" => {
+                    let port = value()?;
+                    config.port = port
+                        .parse()
+                        .with_context(|| format!("invalid port: {}", port))?;
+                }
+                "--directory" => config.directory = PathBuf::from(value()?),
+                "--create-dir" => config.create_dir = true,
+                "--autoindex" => config.autoindex = true,
+                "--serve-root" => config.serve_root = true,
+                "--read-timeout" => {
+                    let secs = value()?;
+                    config.read_timeout = secs
+                        .parse()
+                        .map(Duration::from_secs)
+                        .with_context(|| format!("invalid read timeout: {}", secs))?;
+                }
+                "--idle-timeout" => {
+                    let secs = value()?;
+                    config.idle_timeout = secs
+                        .parse()
+                        .map(Duration::from_secs)
+                        .with_context(|| format!("invalid idle timeout: {}", secs))?;
+                }
+                "--max-keepalive-requests" => {
+                    let n = value()?;
+                    config.max_keepalive_requests = n
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .with_context(|| format!("invalid keep-alive request limit: {}", n))?;
+                }
+                "--max-request-line" => {
+                    let n = value()?;
+                    config.limits.max_request_line = n
+                        .parse()
+                        .with_context(|| format!("invalid request line length: {}", n))?;
+                }
+                "--max-headers" => {
+                    let n = value()?;
+                    config.limits.max_headers = n
+                        .parse()
+                        .with_context(|| format!("invalid header count: {}", n))?;
+                }
+                "--max-header-bytes" => {
+                    let n = value()?;
+                    config.limits.max_header_bytes = n
+                        .parse()
+                        .with_context(|| format!("invalid header size: {}", n))?;
+                }
+                "--max-body-size" => {
+                    let n = value()?;
+                    config.limits.max_body_size = n
+                        .parse()
+                        .with_context(|| format!("invalid body size: {}", n))?;
+                }
+                "--max-path-depth" => {
+                    let n = value()?;
+                    config.limits.max_path_depth = n
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .with_context(|| format!("invalid path depth: {}", n))?;
+                }
+                "--cors-origin" => config.cors.allowed_origins.push(value()?),
+                "--cors-max-age" => {
+                    let secs = value()?;
+                    config.cors.max_age = Some(
+                        secs.parse()
+                            .map(Duration::from_secs)
+                            .with_context(|| format!("invalid CORS max age: {}", secs))?,
+                    );
+                }
+                "--basic-auth" => config.basic_auth = Some(Credentials::parse(&value()?)?),
+                "--metrics" => config.metrics = true,
+                "--max-connections" => {
+                    let n = value()?;
+                    config.max_connections = Some(
+                        n.parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .with_context(|| format!("invalid connection limit: {}", n))?,
+                    );
+                }
+                "--max-open-files" => {
+                    let n = value()?;
+                    config.max_open_files = Some(
+                        n.parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .with_context(|| format!("invalid open file limit: {}", n))?,
+                    );
+                }
+                "--open-file-wait" => {
+                    let secs = value()?;
+                    config.open_file_wait = secs
+                        .parse()
+                        .map(Duration::from_secs)
+                        .with_context(|| format!("invalid open file wait: {}", secs))?;
+                }
+                "--rate-limit" => {
+                    let rate = value()?;
+                    let per_second: f64 = rate
+                        .parse()
+                        .ok()
+                        .filter(|r: &f64| r.is_finite() && *r > 0.0)
+                        .with_context(|| format!("invalid rate limit: {}", rate))?;
+                    config.rate_limit = Some(RateLimitConfig {
+                        per_second,
+                        burst: (per_second.ceil() as u32).max(1),
+                    });
+                }
+                "--rate-burst" => {
+                    let n = value()?;
+                    rate_burst = Some(
+                        n.parse::<u32>()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .with_context(|| format!("invalid rate burst: {}", n))?,
+                    );
+                }
+                "--allow-missing-host" => config.require_host = false,
+                "--case-insensitive-methods" => config.limits.case_insensitive_methods = true,
+                "--enable-trace" => config.trace = true,
+                "--no-tcp-nodelay" => config.tcp_nodelay = false,
+                "--tcp-keepalive" => {
+                    let secs = value()?;
+                    config.tcp_keepalive = Some(
+                        secs.parse()
+                            .ok()
+                            .filter(|&s| s > 0)
+                            .map(Duration::from_secs)
+                            .with_context(|| format!("invalid keepalive time: {}", secs))?,
+                    );
+                }
+                "--listen-backlog" => {
+                    let n = value()?;
+                    config.listen_backlog = n
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .with_context(|| format!("invalid listen backlog: {}", n))?;
+                }
+                "--error-page" => {
+                    config.error_pages.insert_arg(&value()?)?;
+                }
+                "--response-time-header" => config.response_time_header = true,
+                "--peer-addr-header" => config.peer_addr_header = true,
+                "--charset" => config.default_charset = Some(value()?),
+                "--compression-level" => {
+                    let n = value()?;
+                    config.compression_level = n
+                        .parse()
+                        .ok()
+                        .filter(|&n| n <= MAX_COMPRESSION_LEVEL)
+                        .with_context(|| format!("invalid compression level: {}", n))?;
+                }
+                "--trusted-proxy" => {
+                    let ip = value()?;
+                    config.trusted_proxies.proxies.push(
+                        ip.parse()
+                            .with_context(|| format!("invalid trusted proxy: {}", ip))?,
+                    );
+                }
+                "--forwarded-for" => {
+                    let pick = value()?;
+                    config.trusted_proxies.pick = ForwardedFor::parse(&pick)
+                        .with_context(|| format!("invalid forwarded-for entry: {}", pick))?;
+                }
+                "--log-format" => {
+                    let format = value()?;
+                    config.log_format = LogFormat::parse(&format)
+                        .with_context(|| format!("invalid log format: {}", format))?;
+                }
+                "--health-path" => {
+                    let path = value()?;
+                    if !path.starts_with('/') {
+                        bail!("health check path must start with '/': {}", path);
+                    }
+                    config.health_path = Some(path);
+                }
+                "--no-health-check" => config.health_path = None,
+                "--trailing-slash" => {
+                    let mode = value()?;
+                    config.trailing_slash = TrailingSlash::parse(&mode)
+                        .with_context(|| format!("invalid trailing slash mode: {}", mode))?;
+                }
+                "--overload" => {
+                    let policy = value()?;
+                    config.overload = OverloadPolicy::parse(&policy)
+                        .with_context(|| format!("invalid overload policy: {}", policy))?;
+                }
+                _ => bail!("unknown argument: {}", arg),
+            }
+        }
+
+        if let Some(burst) = rate_burst {
+            config
+                .rate_limit
+                .as_mut()
+                .context("--rate-burst requires --rate-limit")?
+                .burst = burst;
+        }
+
+        Ok(config)
+    }
+
+    /// Make sure the files directory is usable before serving: create it
+    /// when `create_dir` is set, otherwise check that it exists and is a
+    /// directory.
+    pub fn prepare_directory(&self) -> Result<()> {
+        let dir = &self.directory;
+        if self.create_dir {
+            return fs::create_dir_all(dir)
+                .with_context(|| format!("creating files directory {}", dir.display()));
+        }
+        match fs::metadata(dir) {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            Ok(_) => bail!("files directory {} is not a directory", dir.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => bail!(
+                "files directory {} does not exist (pass --create-dir to create it)",
+                dir.display()
+            ),
+            Err(e) => Err(e).with_context(|| format!("checking files directory {}", dir.display())),
+        }
+    }
+
+    /// Resolve `address:port` to the first matching socket address. IPv6
+    /// literals may be bracketed (`[::1]`).
+    pub fn socket_addr(&self) -> Result<SocketAddr> {
+        let host = self
+            .address
+            .strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+            .unwrap_or(&self.address);
+        (host, self.port)
+            .to_socket_addrs()
+            .with_context(|| format!("cannot resolve address: {}", self.address))?
+            .next()
+            .with_context(|| format!("no addresses found for: {}", self.address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args_defaults() {
+        let config = ServerConfig::from_args(args(&[])).unwrap();
+        assert_eq!(config.address, "127.0.0.1");
+        assert_eq!(config.port, 4221);
+        assert_eq!(config.directory, PathBuf::from("."));
+        assert!(!config.autoindex);
+    }
+
+    #[test]
+    fn test_from_args_autoindex_flag() {
+        let config = ServerConfig::from_args(args(&["--autoindex"])).unwrap();
+        assert!(config.autoindex);
+    }
+
+    #[test]
+    fn test_from_args_serve_root() {
+        assert!(!ServerConfig::default().serve_root);
+        let config = ServerConfig::from_args(args(&["--serve-root"])).unwrap();
+        assert!(config.serve_root);
+    }
+
+    #[test]
+    fn test_from_args_read_timeout() {
+        let config = ServerConfig::from_args(args(&["--read-timeout", "5"])).unwrap();
+        assert_eq!(config.read_timeout, Duration::from_secs(5));
+        assert_eq!(ServerConfig::default().read_timeout, DEFAULT_READ_TIMEOUT);
+
+        let err = ServerConfig::from_args(args(&["--read-timeout", "soon"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid read timeout"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_header_limits() {
+        let config =
+            ServerConfig::from_args(args(&["--max-headers", "10", "--max-header-bytes", "2048"]))
+                .unwrap();
+        assert_eq!(config.limits.max_headers, 10);
+        assert_eq!(config.limits.max_header_bytes, 2048);
+        assert_eq!(ServerConfig::default().limits, RequestLimits::default());
+
+        let config = ServerConfig::from_args(args(&["--max-request-line", "1024"])).unwrap();
+        assert_eq!(config.limits.max_request_line, 1024);
+
+        let config = ServerConfig::from_args(args(&["--max-body-size", "4096"])).unwrap();
+        assert_eq!(config.limits.max_body_size, 4096);
+        let err = ServerConfig::from_args(args(&["--max-body-size", "big"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid body size"), "got: {}", err);
+
+        let config = ServerConfig::from_args(args(&["--max-path-depth", "32"])).unwrap();
+        assert_eq!(config.limits.max_path_depth, 32);
+        let err = ServerConfig::from_args(args(&["--max-path-depth", "0"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid path depth"), "got: {}", err);
+
+        let err = ServerConfig::from_args(args(&["--max-headers", "-1"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid header count"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_cors_max_age() {
+        assert_eq!(ServerConfig::default().cors.max_age, None);
+        let config = ServerConfig::from_args(args(&["--cors-max-age", "600"])).unwrap();
+        assert_eq!(config.cors.max_age, Some(Duration::from_secs(600)));
+        let err = ServerConfig::from_args(args(&["--cors-max-age", "forever"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid CORS max age"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_args_cors_origins_repeat() {
+        let config = ServerConfig::from_args(args(&[
+            "--cors-origin",
+            "https://a.example",
+            "--cors-origin",
+            "https://b.example",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.cors.allowed_origins,
+            vec!["https://a.example", "https://b.example"]
+        );
+        assert!(!ServerConfig::default().cors.is_enabled());
+    }
+
+    #[test]
+    fn test_from_args_basic_auth() {
+        let config = ServerConfig::from_args(args(&["--basic-auth", "alice:s3cret"])).unwrap();
+        let creds = config.basic_auth.unwrap();
+        assert_eq!(creds.username, "alice");
+        assert_eq!(creds.password, "s3cret");
+        assert!(ServerConfig::default().basic_auth.is_none());
+
+        let err = ServerConfig::from_args(args(&["--basic-auth", "alice"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("user:password"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_metrics_flag() {
+        assert!(!ServerConfig::default().metrics);
+        let config = ServerConfig::from_args(args(&["--metrics"])).unwrap();
+        assert!(config.metrics);
+    }
+
+    #[test]
+    fn test_from_args_connection_limit() {
+        let config =
+            ServerConfig::from_args(args(&["--max-connections", "64", "--overload", "reject"]))
+                .unwrap();
+        assert_eq!(config.max_connections, Some(64));
+        assert_eq!(config.overload, OverloadPolicy::Reject);
+
+        let defaults = ServerConfig::default();
+        assert_eq!(defaults.max_connections, None);
+        assert_eq!(defaults.overload, OverloadPolicy::Queue);
+    }
+
+    #[test]
+    fn test_from_args_invalid_connection_limit() {
+        for bad in ["0", "-1", "lots"] {
+            let err = ServerConfig::from_args(args(&["--max-connections", bad]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid connection limit"), "got: {}", err);
+        }
+        let err = ServerConfig::from_args(args(&["--overload", "drop"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid overload policy"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_max_open_files() {
+        assert_eq!(ServerConfig::default().max_open_files, None);
+        let config = ServerConfig::from_args(args(&["--max-open-files", "256"])).unwrap();
+        assert_eq!(config.max_open_files, Some(256));
+        for bad in ["0", "-3", "plenty"] {
+            let err = ServerConfig::from_args(args(&["--max-open-files", bad]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid open file limit"), "got: {}", err);
+        }
+    }
+
+    #[test]
+    fn test_from_args_open_file_wait() {
+        assert_eq!(
+            ServerConfig::default().open_file_wait,
+            DEFAULT_OPEN_FILE_WAIT
+        );
+        let config = ServerConfig::from_args(args(&["--open-file-wait", "2"])).unwrap();
+        assert_eq!(config.open_file_wait, Duration::from_secs(2));
+        let err = ServerConfig::from_args(args(&["--open-file-wait", "a bit"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid open file wait"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_rate_limit() {
+        assert_eq!(ServerConfig::default().rate_limit, None);
+
+        let config = ServerConfig::from_args(args(&["--rate-limit", "2.5"])).unwrap();
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimitConfig {
+                per_second: 2.5,
+                burst: 3
+            })
+        );
+
+        let config =
+            ServerConfig::from_args(args(&["--rate-burst", "20", "--rate-limit", "5"])).unwrap();
+        assert_eq!(config.rate_limit.unwrap().burst, 20);
+    }
+
+    #[test]
+    fn test_from_args_invalid_rate_limit() {
+        for bad in ["0", "-1", "fast", "inf"] {
+            let err = ServerConfig::from_args(args(&["--rate-limit", bad]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid rate limit"), "got: {}", err);
+        }
+        let err = ServerConfig::from_args(args(&["--rate-limit", "1", "--rate-burst", "0"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid rate burst"), "got: {}", err);
+        let err = ServerConfig::from_args(args(&["--rate-burst", "5"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("requires --rate-limit"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_error_pages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let not_found = tmp.path().join("404.html");
+        let oops = tmp.path().join("oops.txt");
+        std::fs::write(&not_found, "<h1>gone</h1>").unwrap();
+        std::fs::write(&oops, "oops").unwrap();
+
+        let config = ServerConfig::from_args(args(&[
+            "--error-page",
+            &format!("404={}", not_found.display()),
+            "--error-page",
+            &format!("500={}", oops.display()),
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.error_pages.get(404).unwrap().content_type,
+            "text/html"
+        );
+        assert_eq!(config.error_pages.get(500).unwrap().body, b"oops");
+        assert!(ServerConfig::default().error_pages.get(404).is_none());
+
+        let err = ServerConfig::from_args(args(&["--error-page", "200=/x"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("4xx and 5xx"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_allow_missing_host() {
+        assert!(ServerConfig::default().require_host);
+        let config = ServerConfig::from_args(args(&["--allow-missing-host"])).unwrap();
+        assert!(!config.require_host);
+    }
+
+    #[test]
+    fn test_from_args_case_insensitive_methods() {
+        assert!(!ServerConfig::default().limits.case_insensitive_methods);
+        let config = ServerConfig::from_args(args(&["--case-insensitive-methods"])).unwrap();
+        assert!(config.limits.case_insensitive_methods);
+    }
+
+    #[test]
+    fn test_from_args_unix_socket() {
+        assert_eq!(ServerConfig::default().unix_socket, None);
+        let config = ServerConfig::from_args(args(&["--unix", "/tmp/http.sock"])).unwrap();
+        assert_eq!(config.unix_socket, Some(PathBuf::from("/tmp/http.sock")));
+
+        let config = ServerConfig::from_args(args(&["--address", "unix:/run/h.sock"])).unwrap();
+        assert_eq!(config.unix_socket, Some(PathBuf::from("/run/h.sock")));
+        assert_eq!(config.address, DEFAULT_ADDRESS);
+    }
+
+    #[test]
+    fn test_from_args_enable_trace() {
+        assert!(!ServerConfig::default().trace);
+        let config = ServerConfig::from_args(args(&["--enable-trace"])).unwrap();
+        assert!(config.trace);
+    }
+
+    #[test]
+    fn test_from_args_tcp_options() {
+        let config = ServerConfig::default();
+        assert!(config.tcp_nodelay);
+        assert_eq!(config.tcp_keepalive, None);
+
+        let config =
+            ServerConfig::from_args(args(&["--no-tcp-nodelay", "--tcp-keepalive", "60"])).unwrap();
+        assert!(!config.tcp_nodelay);
+        assert_eq!(config.tcp_keepalive, Some(Duration::from_secs(60)));
+
+        for bad in ["0", "soon"] {
+            let err = ServerConfig::from_args(args(&["--tcp-keepalive", bad]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid keepalive time"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_from_args_listen_backlog() {
+        assert_eq!(ServerConfig::default().listen_backlog, 1024);
+        let config = ServerConfig::from_args(args(&["--listen-backlog", "4096"])).unwrap();
+        assert_eq!(config.listen_backlog, 4096);
+        for bad in ["0", "-1", "lots"] {
+            let err = ServerConfig::from_args(args(&["--listen-backlog", bad]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid listen backlog"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_from_args_log_format() {
+        assert_eq!(ServerConfig::default().log_format, LogFormat::Common);
+        let config = ServerConfig::from_args(args(&["--log-format", "json"])).unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+
+        let err = ServerConfig::from_args(args(&["--log-format", "yaml"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid log format"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_args_trusted_proxies() {
+        assert_eq!(
+            ServerConfig::default().trusted_proxies,
+            TrustedProxies::default()
+        );
+        let config = ServerConfig::from_args(args(&[
+            "--trusted-proxy",
+            "10.0.0.1",
+            "--trusted-proxy",
+            "::1",
+            "--forwarded-for",
+            "rightmost",
+        ]))
+        .unwrap();
+        assert_eq!(
+            config.trusted_proxies.proxies,
+            vec![
+                "10.0.0.1".parse::<std::net::IpAddr>().unwrap(),
+                "::1".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.trusted_proxies.pick, ForwardedFor::Rightmost);
+
+        let err = ServerConfig::from_args(args(&["--trusted-proxy", "proxy.local"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid trusted proxy"), "{}", err);
+        let err = ServerConfig::from_args(args(&["--forwarded-for", "first"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid forwarded-for entry"), "{}", err);
+    }
+
+    #[test]
+    fn test_from_args_response_time_header() {
+        assert!(!ServerConfig::default().response_time_header);
+        let config = ServerConfig::from_args(args(&["--response-time-header"])).unwrap();
+        assert!(config.response_time_header);
+    }
+
+    #[test]
+    fn test_from_args_peer_addr_header() {
+        assert!(!ServerConfig::default().peer_addr_header);
+        let config = ServerConfig::from_args(args(&["--peer-addr-header"])).unwrap();
+        assert!(config.peer_addr_header);
+    }
+
+    #[test]
+    fn test_from_args_charset() {
+        assert_eq!(ServerConfig::default().default_charset, None);
+        let config = ServerConfig::from_args(args(&["--charset", "utf-8"])).unwrap();
+        assert_eq!(config.default_charset.as_deref(), Some("utf-8"));
+    }
+
+    #[test]
+    fn test_from_args_compression_level() {
+        assert_eq!(
+            ServerConfig::default().compression_level,
+            DEFAULT_COMPRESSION_LEVEL
+        );
+        for level in ["0", "1", "9"] {
+            let config = ServerConfig::from_args(args(&["--compression-level", level])).unwrap();
+            assert_eq!(config.compression_level.to_string(), level);
+        }
+        for level in ["10", "-1", "best"] {
+            let err = ServerConfig::from_args(args(&["--compression-level", level]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains("invalid compression level"), "got: {}", err);
+        }
+    }
+
+    #[test]
+    fn test_from_args_idle_timeout() {
+        assert_eq!(
+            ServerConfig::default().idle_timeout,
+            Duration::from_secs(15)
+        );
+        let config = ServerConfig::from_args(args(&["--idle-timeout", "3"])).unwrap();
+        assert_eq!(config.idle_timeout, Duration::from_secs(3));
+        let err = ServerConfig::from_args(args(&["--idle-timeout", "soon"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid idle timeout"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_max_keepalive_requests() {
+        assert_eq!(ServerConfig::default().max_keepalive_requests, 100);
+        let config = ServerConfig::from_args(args(&["--max-keepalive-requests", "5"])).unwrap();
+        assert_eq!(config.max_keepalive_requests, 5);
+        for bad in ["0", "-1", "many"] {
+            let err = ServerConfig::from_args(args(&["--max-keepalive-requests", bad]))
+                .unwrap_err()
+                .to_string();
+            assert!(
+                err.contains("invalid keep-alive request limit"),
+                "got: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_args_health_check() {
+        let defaults = ServerConfig::default();
+        assert_eq!(defaults.health_path.as_deref(), Some("/healthz"));
+        let config = ServerConfig::from_args(args(&["--health-path", "/ping"])).unwrap();
+        assert_eq!(config.health_path.as_deref(), Some("/ping"));
+        let config = ServerConfig::from_args(args(&["--no-health-check"])).unwrap();
+        assert_eq!(config.health_path, None);
+        let err = ServerConfig::from_args(args(&["--health-path", "ping"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("must start with '/'"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_trailing_slash() {
+        assert_eq!(ServerConfig::default().trailing_slash, TrailingSlash::Off);
+        for (arg, mode) in [
+            ("off", TrailingSlash::Off),
+            ("strip", TrailingSlash::Strip),
+            ("append", TrailingSlash::Append),
+        ] {
+            let config = ServerConfig::from_args(args(&["--trailing-slash", arg])).unwrap();
+            assert_eq!(config.trailing_slash, mode);
+        }
+        let err = ServerConfig::from_args(args(&["--trailing-slash", "add"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid trailing slash mode"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_all_flags() {
+        let config = ServerConfig::from_args(args(&[
+            "--address",
+            "0.0.0.0",
+            "--port",
+            "8080",
+            "--directory",
+            "/srv/files",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.address, "0.0.0.0");
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.directory, PathBuf::from("/srv/files"));
+    }
+
+    #[test]
+    fn test_from_args_directory_only() {
+        let config = ServerConfig::from_args(args(&["--directory", "/tmp/data"])).unwrap();
+        assert_eq!(config.directory, PathBuf::from("/tmp/data"));
+        assert_eq!(config.port, DEFAULT_PORT);
+    }
+
+    #[test]
+    fn test_from_args_invalid_port() {
+        let err = ServerConfig::from_args(args(&["--port", "99999"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid port"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_missing_value() {
+        let err = ServerConfig::from_args(args(&["--port"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing value for --port"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_unknown_argument() {
+        let err = ServerConfig::from_args(args(&["--verbose"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown argument"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_from_args_create_dir_flag() {
+        assert!(!ServerConfig::default().create_dir);
+        let config = ServerConfig::from_args(args(&["--create-dir"])).unwrap();
+        assert!(config.create_dir);
+    }
+
+    // ── prepare_directory ────────────────────────────────────────────
+
+    fn with_directory(directory: PathBuf, create_dir: bool) -> ServerConfig {
+        ServerConfig {
+            directory,
+            create_dir,
+            ..ServerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_prepare_directory_accepts_existing_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        with_directory(tmp.path().to_path_buf(), false)
+            .prepare_directory()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_prepare_directory_rejects_regular_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("not-a-dir");
+        fs::write(&file, "x").unwrap();
+
+        for create_dir in [false, true] {
+            let err = with_directory(file.clone(), create_dir)
+                .prepare_directory()
+                .unwrap_err();
+            assert!(format!("{:#}", err).contains("not-a-dir"), "{:#}", err);
+        }
+        let err = with_directory(file, false)
+            .prepare_directory()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is not a directory"), "{}", err);
+    }
+
+    #[test]
+    fn test_prepare_directory_missing_without_create_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let missing = tmp.path().join("missing");
+        let err = with_directory(missing.clone(), false)
+            .prepare_directory()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("does not exist"), "{}", err);
+        assert!(err.contains("--create-dir"), "{}", err);
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_prepare_directory_creates_parents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let nested = tmp.path().join("a/b/c");
+        with_directory(nested.clone(), true)
+            .prepare_directory()
+            .unwrap();
+        assert!(nested.is_dir());
+        // Already there is fine too
+        with_directory(nested, true).prepare_directory().unwrap();
+    }
+
+    #[test]
+    fn test_socket_addr_resolves_ip() {
+        let config = ServerConfig::from_args(args(&["--port", "9000"])).unwrap();
+        assert_eq!(
+            config.socket_addr().unwrap(),
+            "127.0.0.1:9000".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_socket_addr_ipv6_with_or_without_brackets() {
+        for address in ["::", "[::]"] {
+            let config = ServerConfig {
+                address: address.to_string(),
+                port: 8080,
+                ..ServerConfig::default()
+            };
+            assert_eq!(config.socket_addr().unwrap(), "[::]:8080".parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_socket_addr_unresolvable() {
+        let config = ServerConfig {
+            address: "not a valid host!".to_string(),
+            ..ServerConfig::default()
+        };
+        assert!(config.socket_addr().is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::time::Duration;
+
+use crate::{request::Request, response::Response};
+
+/// Cross-origin resource sharing settings.
+///
+/// CORS headers are only added for requests carrying an `Origin` header
+/// whose value is in `allowed_origins`. An empty list disables CORS.
+#[derive(Debug, Clone, Default)]
+pub struct CorsConfig {
+    /// Origins allowed to make cross-origin requests, e.g.
+    /// `https://example.com`. A `*` entry allows any origin.
+    pub allowed_origins: Vec<String>,
+    /// How long browsers may cache a preflight's answer, sent as
+    /// `Access-Control-Max-Age`; `None` leaves it to the browser's default
+    /// (a few seconds).
+    pub max_age: Option<Duration>,
+}
+
+impl CorsConfig {
+    /// Whether any origin may be allowed at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_origins.is_empty()
+    }
+
+    fn allows_any(&self) -> bool {
+        self.allowed_origins.iter().any(|o| o == "*")
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, or `None` if the
+    /// origin isn't allowed.
+    pub fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.allows_any() {
+            Some("*")
+        } else if self.allowed_origins.iter().any(|o| o == origin) {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+
+    /// Add `Access-Control-Allow-Origin` to `resp` when the request comes
+    /// from an allowed origin.
+    ///
+    /// Unless every origin is allowed, the header depends on the request's
+    /// `Origin`, so `Vary: Origin` is added for caches as well.
+    pub fn apply(&self, request: &Request, resp: &mut Response) {
+        if !self.is_enabled() {
+            return;
+        }
+        if !self.allows_any() {
+            resp.header("Vary", "Origin");
+        }
+        if let Some(allowed) = request
+            .header_value("Origin")
+            .and_then(|origin| self.allow_origin(origin))
+        {
+            resp.header("Access-Control-Allow-Origin", allowed);
+        }
+    }
+
+    /// Add the preflight headers to the response for an `OPTIONS` request:
+    /// `Access-Control-Allow-Methods` set to `methods`,
+    /// `Access-Control-Allow-Headers` echoing what the client asked for, and
+    /// `Access-Control-Max-Age` when `max_age` is set.
+    ///
+    /// Does nothing unless `request` is a preflight from an allowed origin.
+    /// `Access-Control-Allow-Origin` itself is added by [`apply`](Self::apply).
+    pub fn apply_preflight(&self, request: &Request, resp: &mut Response, methods: &str) {
+        if !is_preflight(request)
+            || request
+                .header_value("Origin")
+                .and_then(|origin| self.allow_origin(origin))
+                .is_none()
+        {
+            return;
+        }
+
+        resp.header("Access-Control-Allow-Methods", methods);
+        if let Some(headers) = request.header_value("Access-Control-Request-Headers") {
+            resp.header("Access-Control-Allow-Headers", headers);
+        }
+        if let Some(max_age) = self.max_age {
+            resp.header("Access-Control-Max-Age", &max_age.as_secs().to_string());
+        }
+    }
+}
+
+/// A CORS preflight is an `OPTIONS` request with both `Origin` and
+/// `Access-Control-Request-Method` headers.
+pub fn is_preflight(request: &Request) -> bool {
+    request.method == "OPTIONS"
+        && request.header_value("Origin").is_some()
+        && request
+            .header_value("Access-Control-Request-Method")
+            .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::HttpVersion;
+
+    fn cors(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|o| o.to_string()).collect(),
+            max_age: None,
+        }
+    }
+
+    fn request(method: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: method.to_string(),
+            path: "/echo/hi".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    fn header<'a>(resp: &'a Response, name: &str) -> Option<&'a str> {
+        resp.headers_all(name).first().copied()
+    }
+
+    // ── allow_origin ─────────────────────────────────────────────────
+
+    #[test]
+    fn test_allow_origin_listed() {
+        let cors = cors(&["https://a.example", "https://b.example"]);
+        assert_eq!(
+            cors.allow_origin("https://b.example"),
+            Some("https://b.example")
+        );
+        assert_eq!(cors.allow_origin("https://evil.example"), None);
+    }
+
+    #[test]
+    fn test_allow_origin_wildcard() {
+        let cors = cors(&["*"]);
+        assert_eq!(cors.allow_origin("https://anything.example"), Some("*"));
+    }
+
+    #[test]
+    fn test_allow_origin_disabled() {
+        let cors = CorsConfig::default();
+        assert!(!cors.is_enabled());
+        assert_eq!(cors.allow_origin("https://a.example"), None);
+    }
+
+    // ── apply ────────────────────────────────────────────────────────
+
+    #[test]
+    fn test_apply_allowed_origin() {
+        let req = request("GET", &[("Origin", "https://a.example")]);
+        let mut resp = Response::ok_text("hi");
+        cors(&["https://a.example"]).apply(&req, &mut resp);
+
+        assert_eq!(
+            header(&resp, "Access-Control-Allow-Origin"),
+            Some("https://a.example")
+        );
+        assert_eq!(header(&resp, "Vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn test_apply_disallowed_origin() {
+        let req = request("GET", &[("Origin", "https://evil.example")]);
+        let mut resp = Response::ok_text("hi");
+        cors(&["https://a.example"]).apply(&req, &mut resp);
+
+        assert!(resp.headers_all("Access-Control-Allow-Origin").is_empty());
+    }
+
+    #[test]
+    fn test_apply_without_origin_header() {
+        let req = request("GET", &[]);
+        let mut resp = Response::ok_text("hi");
+        cors(&["*"]).apply(&req, &mut resp);
+
+        assert!(resp.headers_all("Access-Control-Allow-Origin").is_empty());
+        assert!(resp.headers_all("Vary").is_empty());
+    }
+
+    #[test]
+    fn test_apply_disabled_adds_nothing() {
+        let req = request("GET", &[("Origin", "https://a.example")]);
+        let mut resp = Response::ok_text("hi");
+        CorsConfig::default().apply(&req, &mut resp);
+
+        assert!(resp.headers_all("Access-Control-Allow-Origin").is_empty());
+        assert!(resp.headers_all("Vary").is_empty());
+    }
+
+    // ── Preflight ────────────────────────────────────────────────────
+
+    #[test]
+    fn test_is_preflight() {
+        let preflight = [
+            ("Origin", "https://a.example"),
+            ("Access-Control-Request-Method", "PUT"),
+        ];
+        assert!(is_preflight(&request("OPTIONS", &preflight)));
+        assert!(!is_preflight(&request("GET", &preflight)));
+        assert!(!is_preflight(&request(
+            "OPTIONS",
+            &[("Origin", "https://a.example")]
+        )));
+    }
+
+    #[test]
+    fn test_apply_preflight_allowed() {
+        let req = request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://a.example"),
+                ("Access-Control-Request-Method", "PUT"),
+                ("Access-Control-Request-Headers", "content-type, x-token"),
+            ],
+        );
+        let mut resp = Response::no_content();
+        cors(&["https://a.example"]).apply_preflight(&req, &mut resp, "GET, PUT");
+
+        assert_eq!(
+            header(&resp, "Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            header(&resp, "Access-Control-Allow-Headers"),
+            Some("content-type, x-token")
+        );
+        assert_eq!(header(&resp, "Access-Control-Max-Age"), None);
+    }
+
+    #[test]
+    fn test_apply_preflight_max_age() {
+        let req = request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://a.example"),
+                ("Access-Control-Request-Method", "PUT"),
+            ],
+        );
+        let cors = CorsConfig {
+            max_age: Some(Duration::from_secs(600)),
+            ..cors(&["https://a.example"])
+        };
+
+        let mut resp = Response::no_content();
+        cors.apply_preflight(&req, &mut resp, "GET, PUT");
+        assert_eq!(header(&resp, "Access-Control-Max-Age"), Some("600"));
+
+        // Only preflights are cached, so other requests don't get it
+        let mut resp = Response::ok_text("hi");
+        cors.apply_preflight(
+            &request("GET", &[("Origin", "https://a.example")]),
+            &mut resp,
+            "GET, PUT",
+        );
+        assert_eq!(header(&resp, "Access-Control-Max-Age"), None);
+    }
+
+    #[test]
+    fn test_apply_preflight_disallowed_origin() {
+        let req = request(
+            "OPTIONS",
+            &[
+                ("Origin", "https://evil.example"),
+                ("Access-Control-Request-Method", "PUT"),
+            ],
+        );
+        let mut resp = Response::no_content();
+        cors(&["https://a.example"]).apply_preflight(&req, &mut resp, "GET, PUT");
+
+        assert!(resp.headers_all("Access-Control-Allow-Methods").is_empty());
+    }
+}
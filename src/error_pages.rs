@@ -0,0 +1,169 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{handlers::mime_for_extension, response::Response};
+
+/// A replacement body for error responses with one status code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorPage {
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl ErrorPage {
+    pub fn new(content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            content_type: content_type.to_owned(),
+            body: body.into(),
+        }
+    }
+
+    /// Load a page from disk, taking its content type from the extension.
+    /// The file is read once, so later edits need a restart.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let body =
+            fs::read(path).with_context(|| format!("reading error page {}", path.display()))?;
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        Ok(Self::new(mime_for_extension(name), body))
+    }
+}
+
+/// Custom bodies for 4xx and 5xx responses, keyed by status code.
+///
+/// Statuses without a page keep the built-in plain-text body.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorPages {
+    pages: HashMap<u16, ErrorPage>,
+}
+
+impl ErrorPages {
+    /// Register `page` for `status`, replacing any earlier page. Only error
+    /// statuses (400–599) can have one.
+    pub fn insert(&mut self, status: u16, page: ErrorPage) -> Result<&mut Self> {
+        check_status(status)?;
+        self.pages.insert(status, page);
+        Ok(self)
+    }
+
+    pub fn get(&self, status: u16) -> Option<&ErrorPage> {
+        self.pages.get(&status)
+    }
+
+    /// Parse a `<status>=<path>` command-line value and load the page.
+    pub fn insert_arg(&mut self, arg: &str) -> Result<&mut Self> {
+        let (status, path) = arg
+            .split_once('=')
+            .context("error page must be in the form status=path")?;
+        let status = status
+            .parse()
+            .with_context(|| format!("invalid error page status: {}", status))?;
+        check_status(status)?;
+        self.insert(status, ErrorPage::from_file(Path::new(path))?)
+    }
+
+    /// Swap in the custom body and content type if one is registered for
+    /// the response's status. Other headers (`Allow`, `WWW-Authenticate`,
+    /// ...) are left untouched.
+    pub fn apply(&self, resp: &mut Response) {
+        if let Some(page) = self.get(resp.status_code()) {
+            resp.set_header("Content-Type", &page.content_type)
+                .body_bytes(page.body.clone());
+        }
+    }
+}
+
+fn check_status(status: u16) -> Result<()> {
+    if !(400..600).contains(&status) {
+        bail!("error pages are only for 4xx and 5xx statuses: {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branded_404() -> ErrorPages {
+        let mut pages = ErrorPages::default();
+        pages
+            .insert(404, ErrorPage::new("text/html", "<h1>Lost?</h1>"))
+            .unwrap();
+        pages
+    }
+
+    #[test]
+    fn test_apply_replaces_body_and_content_type() {
+        let mut resp = Response::not_found();
+        branded_404().apply(&mut resp);
+        let raw = String::from_utf8(resp.build_raw()).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(resp.headers_all("Content-Type"), vec!["text/html"]);
+        assert!(raw.contains("Content-Length: 14\r\n"));
+        assert!(raw.ends_with("\r\n\r\n<h1>Lost?</h1>"));
+    }
+
+    #[test]
+    fn test_apply_leaves_other_statuses_alone() {
+        let mut resp = Response::error(500, "Internal Server Error");
+        branded_404().apply(&mut resp);
+        let raw = String::from_utf8(resp.build_raw()).unwrap();
+        assert!(raw.ends_with("\r\n\r\nInternal Server Error"));
+        assert_eq!(resp.headers_all("Content-Type"), vec!["text/plain"]);
+    }
+
+    #[test]
+    fn test_apply_keeps_other_headers() {
+        let mut pages = ErrorPages::default();
+        pages
+            .insert(405, ErrorPage::new("text/html", "nope"))
+            .unwrap();
+        let mut resp = Response::error(405, "Method Not Allowed");
+        resp.header("Allow", "GET");
+        pages.apply(&mut resp);
+        assert_eq!(resp.headers_all("Allow"), vec!["GET"]);
+    }
+
+    #[test]
+    fn test_insert_rejects_non_error_status() {
+        let mut pages = ErrorPages::default();
+        for status in [200, 304, 399, 600] {
+            assert!(
+                pages
+                    .insert(status, ErrorPage::new("text/plain", ""))
+                    .is_err()
+            );
+        }
+        assert!(pages.insert(599, ErrorPage::new("text/plain", "")).is_ok());
+    }
+
+    #[test]
+    fn test_insert_arg_loads_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("404.html");
+        fs::write(&path, "<p>missing</p>").unwrap();
+
+        let mut pages = ErrorPages::default();
+        pages
+            .insert_arg(&format!("404={}", path.display()))
+            .unwrap();
+        assert_eq!(
+            pages.get(404),
+            Some(&ErrorPage::new("text/html", "<p>missing</p>"))
+        );
+    }
+
+    #[test]
+    fn test_insert_arg_errors() {
+        let mut pages = ErrorPages::default();
+        let err = pages.insert_arg("404").unwrap_err().to_string();
+        assert!(err.contains("status=path"), "{}", err);
+        let err = pages.insert_arg("abc=/x.html").unwrap_err().to_string();
+        assert!(err.contains("invalid error page status"), "{}", err);
+        let err = pages
+            .insert_arg("404=/definitely/not/here.html")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("reading error page"), "{}", err);
+    }
+}
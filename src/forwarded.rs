@@ -0,0 +1,184 @@
+use std::net::{IpAddr, SocketAddr};
+
+use crate::request::Request;
+
+/// Which `X-Forwarded-For` entry is taken as the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ForwardedFor {
+    /// The first entry: the original client, as reported by the first
+    /// proxy. Only trustworthy if every proxy in the chain is.
+    #[default]
+    Leftmost,
+    /// The last entry that isn't itself a trusted proxy: the address the
+    /// outermost trusted proxy saw, which a client can't forge.
+    Rightmost,
+}
+
+impl ForwardedFor {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "leftmost" => Some(Self::Leftmost),
+            "rightmost" => Some(Self::Rightmost),
+            _ => None,
+        }
+    }
+}
+
+/// Proxies whose `X-Forwarded-For` header is believed.
+///
+/// A request arriving directly from one of `proxies` is attributed to the
+/// client named in its `X-Forwarded-For`; any other request is attributed
+/// to its peer, whatever headers it carries. An empty list ignores the
+/// header entirely.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    pub proxies: Vec<IpAddr>,
+    pub pick: ForwardedFor,
+}
+
+impl TrustedProxies {
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.proxies.contains(&ip)
+    }
+
+    /// The address `request` should be attributed to in logs and rate
+    /// limits. Falls back to the peer when it isn't a trusted proxy or the
+    /// header has no usable entry; `None` only if there is no peer address.
+    pub fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        let peer = request.peer_addr?.ip();
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+        // Repeated headers form one list, in order (RFC 9110 §5.3)
+        let entries: Vec<Option<IpAddr>> = request
+            .header_values("X-Forwarded-For")
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(parse_entry)
+            .collect();
+        let picked = match self.pick {
+            ForwardedFor::Leftmost => entries.first().copied().flatten(),
+            // An unparseable entry can't be skipped, since the proxy that
+            // wrote it is unknown
+            ForwardedFor::Rightmost => entries
+                .iter()
+                .rev()
+                .find(|ip| !ip.is_some_and(|ip| self.is_trusted(ip)))
+                .copied()
+                .flatten(),
+        };
+        Some(picked.unwrap_or(peer))
+    }
+}
+
+/// One `X-Forwarded-For` entry: a bare address, or one with a port
+/// (`10.0.0.1:5000`, `[::1]:5000`).
+fn parse_entry(entry: &str) -> Option<IpAddr> {
+    let entry = entry.trim();
+    entry
+        .parse()
+        .ok()
+        .or_else(|| entry.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::HttpVersion;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn request_from(peer: &str, xff: &[&str]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: xff
+                .iter()
+                .map(|v| ("X-Forwarded-For".to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: Some(format!("{}:4000", peer).parse().unwrap()),
+            request_id: None,
+        }
+    }
+
+    fn trusting(proxies: &[&str], pick: ForwardedFor) -> TrustedProxies {
+        TrustedProxies {
+            proxies: proxies.iter().map(|p| ip(p)).collect(),
+            pick,
+        }
+    }
+
+    #[test]
+    fn test_parse_pick() {
+        assert_eq!(
+            ForwardedFor::parse("leftmost"),
+            Some(ForwardedFor::Leftmost)
+        );
+        assert_eq!(
+            ForwardedFor::parse("rightmost"),
+            Some(ForwardedFor::Rightmost)
+        );
+        assert_eq!(ForwardedFor::parse("middle"), None);
+    }
+
+    #[test]
+    fn test_leftmost_from_trusted_proxy() {
+        let proxies = trusting(&["10.0.0.1"], ForwardedFor::Leftmost);
+        let req = request_from("10.0.0.1", &["203.0.113.7, 10.0.0.2"]);
+        assert_eq!(proxies.client_ip(&req), Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn test_rightmost_skips_trusted_proxies() {
+        let proxies = trusting(&["10.0.0.1", "10.0.0.2"], ForwardedFor::Rightmost);
+        // The client claims to be 1.2.3.4; the proxies saw 203.0.113.7
+        let req = request_from("10.0.0.1", &["1.2.3.4, 203.0.113.7", "10.0.0.2"]);
+        assert_eq!(proxies.client_ip(&req), Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn test_entries_with_ports() {
+        let proxies = trusting(&["10.0.0.1"], ForwardedFor::Leftmost);
+        let req = request_from("10.0.0.1", &["[2001:db8::1]:443"]);
+        assert_eq!(proxies.client_ip(&req), Some(ip("2001:db8::1")));
+        let req = request_from("10.0.0.1", &["198.51.100.2:5000"]);
+        assert_eq!(proxies.client_ip(&req), Some(ip("198.51.100.2")));
+    }
+
+    #[test]
+    fn test_untrusted_peer_header_ignored() {
+        let proxies = trusting(&["10.0.0.1"], ForwardedFor::Leftmost);
+        let req = request_from("192.0.2.9", &["203.0.113.7"]);
+        assert_eq!(proxies.client_ip(&req), Some(ip("192.0.2.9")));
+        // No trusted proxies at all: the header never counts
+        let req = request_from("10.0.0.1", &["203.0.113.7"]);
+        assert_eq!(
+            TrustedProxies::default().client_ip(&req),
+            Some(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn test_trusted_peer_without_usable_entry_falls_back() {
+        for pick in [ForwardedFor::Leftmost, ForwardedFor::Rightmost] {
+            let proxies = trusting(&["10.0.0.1"], pick);
+            for xff in [&[][..], &["unknown"], &[""]] {
+                let req = request_from("10.0.0.1", xff);
+                assert_eq!(proxies.client_ip(&req), Some(ip("10.0.0.1")), "{:?}", xff);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_peer_address() {
+        let mut req = request_from("10.0.0.1", &["203.0.113.7"]);
+        req.peer_addr = None;
+        let proxies = trusting(&["10.0.0.1"], ForwardedFor::Leftmost);
+        assert_eq!(proxies.client_ip(&req), None);
+    }
+}
@@ -1,55 +1,206 @@
 use std::{
+    collections::HashMap,
+    fs::Metadata,
+    hash::{BuildHasher, RandomState},
+    io::SeekFrom,
+    net::SocketAddr,
     path::{Component, Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result};
-use log::{debug, error};
+use httpdate::HttpDate;
+use log::{debug, error, info};
 use tokio::{
     fs,
-    io::{self, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time,
 };
 
-use crate::{request::Request, response::Response};
+use crate::{
+    access_log::RequestLog,
+    auth::BasicAuth,
+    config::{ServerConfig, TrailingSlash},
+    headers::{self, ByteRange, RangeRequest, preferred_media_type},
+    metrics::Metrics,
+    middleware::{RequestLogger, Scoped},
+    rate_limit::RateLimit,
+    request::{HttpVersion, Request, RequestError},
+    request_id,
+    response::Response,
+    router::{Params, Router},
+    sse,
+};
+
+/// Events sent by the `/events` demo stream before it closes.
+const DEMO_EVENT_COUNT: u32 = 5;
+/// Gap between `/events` demo events.
+const DEMO_EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Largest multi-range response assembled; bigger requests get the whole
+/// file instead, since the parts are buffered in memory.
+const MAX_MULTIPART_BYTES: u64 = 1024 * 1024;
+
+/// How long to keep draining a rejected request before closing.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+/// Upper bound on the bytes discarded while draining a rejected request.
+const LINGER_MAX_BYTES: u64 = 1024 * 1024;
 
 /// Top-level connection handler: loops to serve multiple requests on a
-/// persistent HTTP/1.1 connection.
-pub async fn handle_request(stream: TcpStream, files_dir: Arc<PathBuf>) -> Result<()> {
+/// persistent HTTP/1.1 connection, dispatching each through `router`. The
+/// response to request number `config.max_keepalive_requests` closes the
+/// connection, as does `config.idle_timeout` passing between a response
+/// and the start of the next request.
+///
+/// Works over any byte stream (TCP, Unix sockets, in-memory pipes);
+/// `peer_addr` is the client's address where the transport has one.
+pub async fn handle_request<S>(
+    stream: S,
+    peer_addr: Option<SocketAddr>,
+    config: Arc<ServerConfig>,
+    router: Arc<Router>,
+    metrics: Arc<Metrics>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
     debug!("accepted new connection");
+    let _connection = metrics.connection_opened();
 
-    let peer_addr = stream.peer_addr().ok();
     let mut reader = tokio::io::BufReader::new(stream);
+    // Reused to parse every request and serialize every response on this
+    // connection
+    let mut read_buf = Vec::new();
+    let mut write_buf = Vec::new();
+    let mut served = 0;
 
     loop {
+        // Between requests the client gets the idle timeout to start the next
+        // one, then the read timeout to finish it. Bytes already buffered
+        // from pipelining count as started.
+        if served > 0 {
+            match time::timeout(config.idle_timeout, reader.fill_buf()).await {
+                Ok(filled) => {
+                    filled.context("waiting for the next request")?;
+                }
+                Err(_) => {
+                    debug!("closing connection idle for {:?}", config.idle_timeout);
+                    break;
+                }
+            }
+        }
+
         // Parse the next request — None means clean EOF (client closed)
-        let request = match Request::from_reader(&mut reader, peer_addr).await? {
-            Some(req) => req,
-            None => {
+        let parsed = time::timeout(
+            config.read_timeout,
+            Request::from_connection_reusing(&mut reader, peer_addr, &config.limits, &mut read_buf),
+        )
+        .await;
+        let Ok(parsed) = parsed else {
+            let id = request_id::generate();
+            debug!(
+                "[{}] request not received within {:?}",
+                id, config.read_timeout
+            );
+            let mut resp = Response::error(408, "Request Timeout");
+            config.error_pages.apply(&mut resp);
+            resp.header("Connection", "close")
+                .header(request_id::HEADER, &id);
+            let bytes = resp
+                .write_to_reusing(reader.get_mut(), &mut write_buf)
+                .await
+                .context("writing timeout response")?;
+            metrics.record(408, bytes as usize);
+            break;
+        };
+
+        // Reject requests that parse but break rules the config enforces
+        let parsed = parsed.and_then(|req| match req {
+            Some(req) if config.require_host && req.missing_host() => {
+                Err(
+                    RequestError::new(400, "Bad Request", "HTTP/1.1 request without a Host header")
+                        .into(),
+                )
+            }
+            other => Ok(other),
+        });
+
+        let mut request = match parsed {
+            Ok(Some(req)) => req,
+            Ok(None) => {
                 debug!("client closed connection");
                 break;
             }
+            Err(e) => {
+                // Requests we can answer with an error status get one before closing
+                let Some(req_err) = e.downcast_ref::<RequestError>() else {
+                    return Err(e);
+                };
+                let id = request_id::generate();
+                debug!("[{}] rejecting request: {}", id, req_err);
+                let mut resp = req_err.to_response();
+                config.error_pages.apply(&mut resp);
+                resp.header("Connection", "close")
+                    .header(request_id::HEADER, &id);
+                let status = resp.status_code();
+                let bytes = resp
+                    .write_to_reusing(reader.get_mut(), &mut write_buf)
+                    .await
+                    .context("writing error response")?;
+                metrics.record(status, bytes as usize);
+                lingering_close(&mut reader).await;
+                break;
+            }
         };
 
+        let id = request_id::for_request(&request);
         debug!(
-            "{} {} from {:?}",
-            request.method, request.path, request.peer_addr
+            "[{}] {} {} from {:?}",
+            id, request.method, request.path, request.peer_addr
         );
-
-        // Check whether the client wants to close after this request
-        let should_close = request
-            .header_value("Connection")
-            .is_some_and(|v| v.eq_ignore_ascii_case("close"));
-
-        let stream = reader.get_mut();
-        let response = route(&request, &files_dir, stream).await?;
-
-        if let Some(resp) = response {
-            resp.write_to(stream).await.context("writing response")?;
+        request.request_id = Some(id);
+        let started = Instant::now();
+
+        // Decide persistence from the version default and the Connection
+        // header, unless this connection has used up its requests
+        served += 1;
+        let keep_alive = request.keep_alive() && served < config.max_keepalive_requests;
+
+        // The whole reader goes in, so a takeover also gets any bytes the
+        // client sent after this request
+        let outcome = route(
+            &request,
+            keep_alive,
+            &config,
+            &router,
+            &mut reader,
+            &mut write_buf,
+        )
+        .await?;
+        let elapsed = started.elapsed();
+        metrics.record(outcome.status, outcome.bytes_written);
+        debug!(
+            "[{}] answered {} in {:?}",
+            request_id::log_id(&request),
+            outcome.status,
+            elapsed
+        );
+        // Probes arrive every few seconds and would drown out real traffic
+        if !is_health_check(&request, &config) {
+            let mut log = RequestLog::new(&request, outcome.status, outcome.bytes_written, elapsed);
+            log.host = config.trusted_proxies.client_ip(&request);
+            info!(target: "access", "{}", log.render(config.log_format));
         }
 
-        if should_close {
-            debug!("closing connection (Connection: close)");
+        if !outcome.keep_alive {
+            debug!(
+                "[{}] closing connection ({} {})",
+                request_id::log_id(&request),
+                request.http_version,
+                outcome.status
+            );
             break;
         }
     }
@@ -57,27 +208,291 @@ pub async fn handle_request(stream: TcpStream, files_dir: Arc<PathBuf>) -> Resul
     Ok(())
 }
 
-/// Routes the request to the matching handler.
+/// What [`route`] sent back for one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteOutcome {
+    pub status: u16,
+    /// Body bytes written, whether buffered or streamed from a file.
+    pub bytes_written: usize,
+    /// Whether the connection stays open for another request.
+    pub keep_alive: bool,
+}
+
+/// Dispatch `request` through `router` and write the response to `stream`,
+/// adding the CORS, `Connection`, `X-Request-Id` and compression headers and
+/// any default charset on the way out, plus `X-Response-Time` if
+/// `config.response_time_header` is set (timing producing the response,
+/// not sending it) and `X-Peer-Addr` if `config.peer_addr_header` is set.
+///
+/// Health checks on `config.health_path` are answered without going
+/// through the router, so its middleware (logging, rate limits, auth)
+/// doesn't apply. With `config.trailing_slash` on, an unrouted path that
+/// only differs from a route by its trailing slash is redirected before
+/// reaching the router.
+///
+/// A response with a [`Takeover`](crate::response::Takeover) gets `stream`
+/// (buffered input included) after its head, and the connection ends with
+/// it. A handler error becomes a `500 Internal Server Error` with a generic
+/// body, and the connection is closed after it. Only failing to write the
+/// response is returned as an error.
 ///
-/// Returns `Some(Response)` for simple responses that should be written in full,
-/// or `None` when the handler has already written directly to the stream (e.g. file streaming).
-async fn route(
+/// The response is serialized into `write_buf`, which the connection reuses
+/// between requests.
+pub async fn route<S: AsyncRead + AsyncWrite + Unpin + Send>(
+    request: &Request,
+    keep_alive: bool,
+    config: &ServerConfig,
+    router: &Router,
+    stream: &mut S,
+    write_buf: &mut Vec<u8>,
+) -> Result<RouteOutcome> {
+    let started = Instant::now();
+    let shortcut = if is_health_check(request, config) {
+        Some(handle_health())
+    } else {
+        trailing_slash_redirect(request, config, router)
+    };
+    let dispatched = match shortcut {
+        Some(resp) => Ok(resp),
+        None => router.dispatch(request, config).await,
+    };
+    let (mut resp, mut keep_alive) = match dispatched {
+        Ok(resp) => (resp, keep_alive),
+        Err(e) => {
+            // The details are for the log only, never the client
+            error!(
+                "[{}] handler failed for {} {}: {:#}",
+                request_id::log_id(request),
+                request.method,
+                request.path,
+                e
+            );
+            (Response::error(500, "Internal Server Error"), false)
+        }
+    };
+    if request.http_version == HttpVersion::Http10 {
+        resp.close_delimit_chunked();
+    }
+    // A HEAD only gets the head a GET would, so nothing takes over
+    let takeover = resp.take_takeover().filter(|_| request.method != "HEAD");
+    keep_alive &= takeover.is_none() && !resp.is_close_delimited();
+    config.error_pages.apply(&mut resp);
+    config.cors.apply(request, &mut resp);
+    if let Some(charset) = &config.default_charset {
+        resp.set_charset(charset);
+    }
+    if let Some(id) = &request.request_id {
+        resp.set_header(request_id::HEADER, id);
+    }
+    resp.maybe_compress_at(
+        request.header_value("Accept-Encoding"),
+        config.compression_level,
+    );
+    // A HEAD response describes the body a GET would get, without it, so
+    // it's only dropped once the body is encoded as a GET's would be
+    if request.method == "HEAD" {
+        resp.strip_body();
+    }
+    if config.response_time_header {
+        let ms = started.elapsed().as_secs_f64() * 1000.0;
+        resp.set_header("X-Response-Time", &format!("{:.3}ms", ms));
+    }
+    if config.peer_addr_header
+        && let Some(peer) = request.peer_addr
+    {
+        resp.set_header("X-Peer-Addr", &peer.to_string());
+    }
+
+    // Always tell the client whether the connection stays open, unless the
+    // handler already did (`Connection: Upgrade`)
+    if resp.headers_all("Connection").is_empty() {
+        resp.header(
+            "Connection",
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+    }
+    let status = resp.status_code();
+
+    if let Some(takeover) = takeover {
+        resp.write_takeover_head(stream, write_buf)
+            .await
+            .context("writing response head")?;
+        if let Err(e) = takeover.run(stream).await {
+            debug!(
+                "[{}] connection takeover ended with error: {:#}",
+                request_id::log_id(request),
+                e
+            );
+        }
+        return Ok(RouteOutcome {
+            status,
+            bytes_written: 0,
+            keep_alive: false,
+        });
+    }
+
+    let bytes_written = resp
+        .write_to_reusing(stream, write_buf)
+        .await
+        .context("writing response")?;
+    Ok(RouteOutcome {
+        status,
+        bytes_written: bytes_written as usize,
+        keep_alive,
+    })
+}
+
+/// Whether `request` is a `GET` or `HEAD` of the configured health check
+/// path.
+fn is_health_check(request: &Request, config: &ServerConfig) -> bool {
+    matches!(request.method.as_str(), "GET" | "HEAD")
+        && config.health_path.as_deref() == Some(request.path.as_str())
+}
+
+/// A `301` to the routed form of `request`'s path, if the path itself has
+/// no route but the form `config.trailing_slash` asks for does. The query
+/// string is kept.
+fn trailing_slash_redirect(
     request: &Request,
-    files_dir: &Path,
-    stream: &mut TcpStream,
-) -> Result<Option<Response>> {
-    if request.path == "/" {
-        Ok(Some(handle_root()))
-    } else if let Some(suffix) = request.path.strip_prefix("/echo/") {
-        Ok(Some(handle_echo(suffix)))
-    } else if request.path.starts_with("/user-agent") {
-        Ok(Some(handle_user_agent(request)))
-    } else if let Some(filename) = request.path.strip_prefix("/files/") {
-        handle_files(filename, files_dir, stream, request).await
+    config: &ServerConfig,
+    router: &Router,
+) -> Option<Response> {
+    let canonical = normalize_path(&request.path, config.trailing_slash)?;
+    if router.allowed_methods(&request.path).is_some()
+        || router.allowed_methods(&canonical).is_none()
+    {
+        return None;
+    }
+    let mut location = percent_encode_path(&canonical);
+    if let Some(query) = &request.query {
+        location.push('?');
+        location.push_str(query);
+    }
+    debug!("redirecting {} to {}", request.path, location);
+    Some(Response::redirect(301, &location))
+}
+
+/// Shut down the write half and discard whatever the client is still
+/// sending, for a bounded time and amount.
+///
+/// Closing a socket with unread input makes the kernel send a reset, which
+/// can destroy an error response the client hasn't read yet.
+pub(crate) async fn lingering_close<S: AsyncRead + AsyncWrite + Unpin>(
+    reader: &mut io::BufReader<S>,
+) {
+    if reader.get_mut().shutdown().await.is_err() {
+        return;
+    }
+    let mut input = reader.take(LINGER_MAX_BYTES);
+    let _ = time::timeout(LINGER_TIMEOUT, io::copy(&mut input, &mut io::sink())).await;
+}
+
+/// The built-in routes: `/`, `/echo/*`, `/user-agent`, the `/events` demo
+/// stream and the `/files/` tree.
+pub fn default_router() -> Router {
+    builtin_routes(&ServerConfig::default(), None)
+}
+
+/// [`default_router`]'s routes as `config` shapes them, plus `/metrics`
+/// when given the counters to serve. With `config.serve_root`, the files
+/// directory is also served read-only from `/`, for whatever paths no
+/// other route claims; `/` itself still answers an empty 200 when the
+/// directory has no index to serve there.
+fn builtin_routes(config: &ServerConfig, metrics: Option<&Arc<Metrics>>) -> Router {
+    let mut router = Router::new();
+    if !config.serve_root {
+        router.route("GET", "/", |_, _, _| Box::pin(async { Ok(handle_root()) }));
+    }
+    router
+        .route("GET", "/echo/*", |req, params, _| {
+            Box::pin(async move {
+                Ok(handle_echo(
+                    params.get("*").unwrap_or_default(),
+                    req.header_value("Accept"),
+                ))
+            })
+        })
+        .route("GET", "/user-agent*", |req, _, _| {
+            Box::pin(async move { Ok(handle_user_agent(req)) })
+        })
+        .route("GET", "/events", |_, _, _| {
+            Box::pin(async { Ok(handle_events()) })
+        });
+    if let Some(metrics) = metrics {
+        let metrics = metrics.clone();
+        router.route("GET", "/metrics", move |_, _, _| {
+            let body = metrics.render();
+            Box::pin(async move {
+                let mut resp = Response::new(200, "OK");
+                resp.header("Content-Type", "text/plain; version=0.0.4")
+                    .body_bytes(body.into_bytes());
+                Ok(resp)
+            })
+        });
+    }
+
+    // Shared by the /files/ routes, so uploads' types outlive the request
+    // and the open-file limit spans all of them. The directory itself can
+    // only be read, so it shadows the wildcard.
+    let types = Arc::new(UploadedTypes::default());
+    let open_files = Arc::new(OpenFiles::new(config.max_open_files));
+    let files_routes = [
+        ("GET", "/files/"),
+        ("HEAD", "/files/"),
+        ("GET", "/files/*"),
+        ("HEAD", "/files/*"),
+        ("POST", "/files/*"),
+        ("PUT", "/files/*"),
+        ("PATCH", "/files/*"),
+        ("DELETE", "/files/*"),
+    ];
+    // Registered last, so every other route takes precedence
+    let root_routes: &[_] = if config.serve_root {
+        &[("GET", "/*"), ("HEAD", "/*")]
     } else {
-        debug!("unknown path: {}", request.path);
-        Ok(Some(Response::not_found()))
+        &[]
+    };
+    for &(method, pattern) in files_routes.iter().chain(root_routes) {
+        let types = types.clone();
+        let open_files = open_files.clone();
+        let at_root = pattern == "/*";
+        router.route(method, pattern, move |req, params, config| {
+            let types = types.clone();
+            let open_files = open_files.clone();
+            Box::pin(async move {
+                let resp = handle_files(req, params, config, &types, &open_files).await?;
+                if at_root && req.path == "/" && resp.status_code() == 404 {
+                    return Ok(handle_root());
+                }
+                Ok(resp)
+            })
+        });
+    }
+    router
+}
+
+/// The built-in routes wrapped in the middleware `config` asks for: request
+/// logging, rate limiting, and Basic auth when credentials are configured.
+/// The auth guards `/files/` under the realm `files`, or, when the files
+/// are also served from the root, the whole site under the realm `site`.
+pub fn app_router(config: &ServerConfig, metrics: &Arc<Metrics>) -> Router {
+    let mut router = builtin_routes(config, config.metrics.then_some(metrics));
+    router.layer(RequestLogger);
+    if let Some(rate_limit) = config.rate_limit {
+        router.layer(RateLimit::new(rate_limit).trusting(config.trusted_proxies.clone()));
+    }
+    if let Some(credentials) = &config.basic_auth {
+        let (scope, realm) = if config.serve_root {
+            ("/", "site")
+        } else {
+            ("/files/", "files")
+        };
+        router.layer(Scoped::new(
+            scope,
+            BasicAuth::new(credentials.clone(), realm),
+        ));
     }
+    router
 }
 
 // ---------------------------------------------------------------------------
@@ -89,9 +504,61 @@ fn handle_root() -> Response {
     Response::ok_text("")
 }
 
-fn handle_echo(echoed: &str) -> Response {
+/// Liveness probe: the server is up if it can answer at all.
+fn handle_health() -> Response {
+    let mut resp = Response::ok_text("ok");
+    resp.header("Cache-Control", "no-store");
+    resp
+}
+
+/// Echo `echoed` back as plain text, or as `{"echo":"..."}` when the
+/// `Accept` header prefers JSON.
+fn handle_echo(echoed: &str, accept: Option<&str>) -> Response {
     debug!("echo path requested: {}", echoed);
-    Response::ok_text(echoed)
+    let mut resp = match preferred_media_type(accept, &["text/plain", "application/json"]) {
+        Some("application/json") => {
+            Response::json(&format!("{{\"echo\":{}}}", json_string(echoed)))
+        }
+        // Clients that accept neither still get text rather than a 406
+        _ => Response::ok_text(echoed),
+    };
+    resp.header("Vary", "Accept");
+    resp
+}
+
+/// `s` as a quoted JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Server-Sent Events demo: the current time, once a second, a few times.
+fn handle_events() -> Response {
+    sse::response(|mut events| {
+        Box::pin(async move {
+            let mut ticks = time::interval(DEMO_EVENT_INTERVAL);
+            for _ in 0..DEMO_EVENT_COUNT {
+                ticks.tick().await;
+                events
+                    .send(&HttpDate::from(SystemTime::now()).to_string())
+                    .await?;
+            }
+            Ok(())
+        })
+    })
 }
 
 fn handle_user_agent(request: &Request) -> Response {
@@ -104,204 +571,1043 @@ fn handle_user_agent(request: &Request) -> Response {
     }
 }
 
-/// Serves a file from `files_dir`. Streams the body directly to `stream` so
-/// that the entire file doesn't have to be buffered in memory.
+/// The content types of files clients uploaded: the type declared with
+/// the upload, so a later GET serves each file as it was sent, or else the
+/// one its extension implies. Uploads of active content (see
+/// [`is_active_content_type`]) are then served as attachments, so they
+/// can't run script in the site's origin.
+///
+/// Keyed on the file's canonical path, which is what [`resolve_safe_path`]
+/// gives for reads and [`resolve_safe_entry`] for writes, since writes never
+/// go through a symlink. Kept in memory only: after a restart, or for files
+/// written some other way, the extension decides.
+#[derive(Debug, Default)]
+struct UploadedTypes(Mutex<HashMap<PathBuf, String>>);
+
+impl UploadedTypes {
+    fn get(&self, path: &Path) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(path)
+            .cloned()
+    }
+
+    /// Record an upload to `path`, with the type its client declared if any.
+    fn set(&self, path: &Path, declared: Option<&str>) {
+        let content_type = declared.unwrap_or_else(|| mime_for_extension(&path.to_string_lossy()));
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(path.to_path_buf(), content_type.to_owned());
+    }
+
+    /// Forget the upload to `path`, once the file is gone.
+    fn remove(&self, path: &Path) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(path);
+    }
+}
+
+/// Caps how many `/files/` GETs hold a file open at once, so a burst of
+/// downloads can't use up the process's file descriptors. Each router
+/// gets its own, sized when it's built.
+#[derive(Debug)]
+struct OpenFiles(Option<Arc<Semaphore>>);
+
+impl OpenFiles {
+    /// Room for `limit` open files at once; `None` is unlimited.
+    fn new(limit: Option<usize>) -> Self {
+        Self(limit.map(|n| Arc::new(Semaphore::new(n))))
+    }
+
+    /// A permit to open a file, to be held until the response is sent;
+    /// `None` when there's no limit. Waits up to `config.open_file_wait`
+    /// for one to free up, then gives up with a `503` to send instead.
+    async fn acquire(
+        &self,
+        config: &ServerConfig,
+    ) -> Result<Option<OwnedSemaphorePermit>, Response> {
+        let Some(limit) = &self.0 else {
+            return Ok(None);
+        };
+        match time::timeout(config.open_file_wait, limit.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            _ => {
+                debug!("no file slot free within {:?}", config.open_file_wait);
+                let mut resp = Response::error(503, "Service Unavailable");
+                resp.header("Retry-After", "1");
+                Err(resp)
+            }
+        }
+    }
+}
+
+/// Serves `/files/*` from `files_dir`. The body is streamed when the response
+/// is written so that the entire file doesn't have to be buffered in memory.
 ///
-/// Returns `Ok(None)` on success (response already written), or
-/// `Ok(Some(Response))` for error responses that the caller should write.
+/// An empty filename (i.e. `/files/`) refers to `files_dir` itself; GETs on
+/// a directory serve its `index.html` when present, or an HTML listing when
+/// autoindex is enabled. Writes are refused with 403 when the name is a
+/// symlink, since they'd otherwise follow it out of `files_dir`.
 async fn handle_files(
-    filename: &str,
-    files_dir: &Path,
-    stream: &mut TcpStream,
     request: &Request,
-) -> Result<Option<Response>> {
-    if !is_valid_single_filename(filename) {
-        return Ok(Some(Response::not_found()));
+    params: &Params,
+    config: &ServerConfig,
+    types: &UploadedTypes,
+    open_files: &OpenFiles,
+) -> Result<Response> {
+    let filename = params.get("*").unwrap_or_default();
+    let writing = matches!(request.method.as_str(), "POST" | "PUT" | "PATCH");
+    let resolved = if writing || request.method == "DELETE" {
+        resolve_safe_entry(&config.directory, filename).await
+    } else {
+        resolve_safe_path(&config.directory, filename).await
+    };
+    let Some(file_path) = resolved else {
+        return Ok(Response::not_found());
+    };
+    // Writing through a link could land anywhere it points, so links are
+    // only ever read
+    if writing
+        && fs::symlink_metadata(&file_path)
+            .await
+            .is_ok_and(|m| m.is_symlink())
+    {
+        debug!("refusing to write through symlink {:?}", file_path);
+        return Ok(Response::error(403, "Forbidden"));
     }
 
-    let file_path = files_dir.join(filename);
-
     match request.method.as_str() {
-        "GET" => handle_file_get(&file_path, filename, stream).await,
-        "POST" => handle_file_post(&file_path, request).await,
-        _ => Ok(Some(Response::not_found())),
+        "GET" | "HEAD" => handle_file_get(&file_path, config, request, types, open_files).await,
+        "POST" | "PUT" => handle_file_post(&file_path, filename, request, types).await,
+        "PATCH" => handle_file_patch(&file_path, request).await,
+        "DELETE" => handle_file_delete(&file_path, types).await,
+        _ => Ok(Response::not_found()),
     }
 }
 
+/// Answers `GET` and `HEAD`. A `HEAD` for a file only stats it: the
+/// headers match a `GET`'s, but the file is never opened. (Bodies of other
+/// `HEAD` responses are dropped by [`route`].) A file opened for a `GET`
+/// counts against `config.max_open_files` until it has been sent.
 async fn handle_file_get(
     file_path: &Path,
-    filename: &str,
-    stream: &mut TcpStream,
-) -> Result<Option<Response>> {
-    let meta = match fs::metadata(&file_path).await {
-        Ok(m) if m.is_file() => m,
-        _ => return Ok(Some(Response::not_found())),
+    config: &ServerConfig,
+    request: &Request,
+    types: &UploadedTypes,
+    open_files: &OpenFiles,
+) -> Result<Response> {
+    let head = request.method == "HEAD";
+    let Some((file_path, meta)) = resolve_servable_file(file_path).await else {
+        if config.autoindex && fs::metadata(file_path).await.is_ok_and(|m| m.is_dir()) {
+            return directory_listing(file_path, &request.path).await;
+        }
+        return Ok(Response::not_found());
+    };
+    if !meta.is_file() {
+        return stream_file(&file_path, config, request, open_files).await;
+    }
+
+    // A precompressed copy stands in for the file, validators and all, for
+    // clients that take gzip
+    let sidecar = gzip_sidecar(&file_path, &meta).await;
+    let has_sidecar = sidecar.is_some();
+    let (body_path, meta, gzipped) = match sidecar {
+        Some((gz_path, gz_meta))
+            if request
+                .header_value("Accept-Encoding")
+                .is_some_and(|accept| headers::coding_quality(accept, "gzip") > 0.0) =>
+        {
+            (gz_path, gz_meta, true)
+        }
+        _ => (file_path.clone(), meta, false),
+    };
+
+    let etag = etag_for(&meta);
+    let last_modified = meta.modified().ok().map(HttpDate::from);
+    if is_not_modified(request, &etag, last_modified) {
+        debug!("{:?} not modified (ETag {})", body_path, etag);
+        let mut resp = Response::not_modified();
+        resp.header("ETag", &etag);
+        if let Some(date) = last_modified {
+            resp.header("Last-Modified", &date.to_string());
+        }
+        if has_sidecar {
+            resp.header("Vary", "Accept-Encoding");
+        }
+        return Ok(resp);
+    }
+
+    // A Range is only honoured for GET, and only while the client's copy
+    // is current
+    let mut ranges = Vec::new();
+    if let Some(header) = request
+        .header_value("Range")
+        .filter(|_| !head && if_range_matches(request, &etag, last_modified))
+    {
+        match headers::parse_range(header, meta.len()) {
+            Some(RangeRequest::Satisfiable(requested)) => ranges = requested,
+            Some(RangeRequest::Unsatisfiable) => {
+                let mut resp = Response::error(416, "Range Not Satisfiable");
+                resp.header("Content-Range", &format!("bytes */{}", meta.len()));
+                return Ok(resp);
+            }
+            None => {}
+        }
+    }
+    // Overlapping or sprawling ranges would cost more than the whole file
+    let total: u64 = ranges.iter().map(ByteRange::size).sum();
+    if ranges.len() > 1 && (total > meta.len() || total > MAX_MULTIPART_BYTES) {
+        debug!("ignoring {} ranges totalling {} bytes", ranges.len(), total);
+        ranges.clear();
+    }
+
+    // A HEAD never opens the file
+    let permit = if head {
+        None
+    } else {
+        match open_files.acquire(config).await {
+            Ok(permit) => permit,
+            Err(resp) => return Ok(resp),
+        }
     };
 
-    let mut file = fs::File::open(&file_path).await.context("opening file")?;
+    let uploaded_type = types.get(&file_path);
+    let content_type = uploaded_type
+        .as_deref()
+        .unwrap_or_else(|| mime_for_extension(&file_path.to_string_lossy()));
+
+    let mut resp = match ranges.len() {
+        0 => Response::new(200, "OK"),
+        _ => Response::new(206, "Partial Content"),
+    };
+    resp.header("ETag", &etag).header("Accept-Ranges", "bytes");
+    if uploaded_type.as_deref().is_some_and(is_active_content_type) {
+        resp.header("Content-Disposition", "attachment")
+            .header("X-Content-Type-Options", "nosniff");
+    }
+    if let Some(date) = last_modified {
+        resp.header("Last-Modified", &date.to_string());
+    }
+    if gzipped {
+        resp.header("Content-Encoding", "gzip");
+    }
+    if has_sidecar {
+        resp.header("Vary", "Accept-Encoding");
+    }
+    match ranges[..] {
+        [] if head => {
+            resp.header("Content-Type", content_type)
+                .header("Content-Length", &meta.len().to_string());
+        }
+        [] => {
+            let file = fs::File::open(&body_path).await.context("opening file")?;
+            debug!("streaming {} bytes for file {:?}", meta.len(), body_path);
+            resp.header("Content-Type", content_type)
+                .file_body(file, meta.len());
+        }
+        [range] => {
+            debug!(
+                "streaming bytes {}-{} of file {:?}",
+                range.start, range.end, body_path
+            );
+            let mut file = fs::File::open(&body_path).await.context("opening file")?;
+            file.seek(SeekFrom::Start(range.start))
+                .await
+                .context("seeking in file")?;
+            resp.header("Content-Type", content_type)
+                .header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", range.start, range.end, meta.len()),
+                )
+                .file_body(file, range.size());
+        }
+        _ => {
+            debug!("sending {} ranges of file {:?}", ranges.len(), body_path);
+            let mut file = fs::File::open(&body_path).await.context("opening file")?;
+            let boundary = multipart_boundary();
+            let body =
+                multipart_byteranges(&mut file, &ranges, content_type, meta.len(), &boundary)
+                    .await?;
+            resp.header(
+                "Content-Type",
+                &format!("multipart/byteranges; boundary={}", boundary),
+            )
+            .body_bytes(body);
+        }
+    }
+    if let Some(permit) = permit {
+        resp.hold_until_sent(permit);
+    }
+    Ok(resp)
+}
 
+/// Serve a pipe or device, whose metadata can't say how long it is:
+/// chunked to HTTP/1.1 clients, ended by closing the connection for
+/// HTTP/1.0 ones. There are no validators or ranges, since the contents
+/// aren't stable, and a `HEAD` doesn't open it (opening a pipe waits for a
+/// writer).
+async fn stream_file(
+    file_path: &Path,
+    config: &ServerConfig,
+    request: &Request,
+    open_files: &OpenFiles,
+) -> Result<Response> {
+    let chunked = request.http_version == HttpVersion::Http11;
     let mut resp = Response::new(200, "OK");
-    resp.header("Content-Type", "application/octet-stream")
-        .header("Content-Length", &meta.len().to_string());
+    resp.header(
+        "Content-Type",
+        mime_for_extension(&file_path.to_string_lossy()),
+    );
+    if request.method == "HEAD" {
+        if chunked {
+            resp.header("Transfer-Encoding", "chunked");
+        }
+        return Ok(resp);
+    }
 
-    resp.write_headers(stream)
-        .await
-        .context("writing file headers")?;
+    let permit = match open_files.acquire(config).await {
+        Ok(permit) => permit,
+        Err(resp) => return Ok(resp),
+    };
+    let file = fs::File::open(file_path).await.context("opening file")?;
+    debug!("streaming {:?} until it ends", file_path);
+    if let Some(permit) = permit {
+        resp.hold_until_sent(permit);
+    }
+    if chunked {
+        resp.chunked_file_body(file);
+    } else {
+        resp.file_body_until_close(file);
+    }
+    Ok(resp)
+}
 
-    let bytes_copied = io::copy(&mut file, stream)
-        .await
-        .context("streaming file")?;
+/// A fresh random boundary for a multipart body.
+fn multipart_boundary() -> String {
+    format!("{:016x}", RandomState::new().hash_one(SystemTime::now()))
+}
+
+/// Read `ranges` of `file` into a `multipart/byteranges` body (RFC 9110
+/// §14.6), each part labelled with its `Content-Type` and `Content-Range`.
+async fn multipart_byteranges(
+    file: &mut fs::File,
+    ranges: &[ByteRange],
+    content_type: &str,
+    len: u64,
+    boundary: &str,
+) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    for range in ranges {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, range.start, range.end, len
+            )
+            .as_bytes(),
+        );
+        file.seek(SeekFrom::Start(range.start))
+            .await
+            .context("seeking in file")?;
+        let part_start = body.len();
+        body.resize(part_start + range.size() as usize, 0);
+        file.read_exact(&mut body[part_start..])
+            .await
+            .context("reading file range")?;
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    Ok(body)
+}
+
+/// Build an HTML index of `dir`, with links relative to `url_path`.
+async fn directory_listing(dir: &Path, url_path: &str) -> Result<Response> {
+    let mut entries = Vec::new();
+    let mut read_dir = fs::read_dir(dir).await.context("reading directory")?;
+    while let Some(entry) = read_dir.next_entry().await.context("reading directory")? {
+        let meta = entry.metadata().await.context("reading entry metadata")?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        entries.push((name, meta.is_dir(), meta.len()));
+    }
+    entries.sort();
+
+    let base = if url_path.ends_with('/') {
+        url_path.to_string()
+    } else {
+        format!("{}/", url_path)
+    };
+    let title = html_escape(&base);
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>Index of {title}</title></head>\n\
+         <body>\n<h1>Index of {title}</h1>\n<table>\n\
+         <tr><th>Name</th><th>Size</th><th>Type</th></tr>\n"
+    );
+    for (name, is_dir, len) in &entries {
+        let slash = if *is_dir { "/" } else { "" };
+        let href = html_escape(&percent_encode_path(&format!("{}{}{}", base, name, slash)));
+        let size = if *is_dir {
+            "-".to_string()
+        } else {
+            len.to_string()
+        };
+        let kind = if *is_dir { "dir" } else { "file" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            href,
+            html_escape(name),
+            slash,
+            size,
+            kind
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
 
-    stream.flush().await?;
-    debug!("streamed {} bytes for file {}", bytes_copied, filename);
+    debug!(
+        "generated listing for {:?} ({} entries)",
+        dir,
+        entries.len()
+    );
 
-    Ok(None)
+    let mut resp = Response::new(200, "OK");
+    resp.header("Content-Type", "text/html")
+        .body_bytes(html.into_bytes());
+    Ok(resp)
 }
 
-/// POST /files/{filename} — create/overwrite a file with the request body.
-async fn handle_file_post(file_path: &Path, request: &Request) -> Result<Option<Response>> {
+/// POST/PUT /files/{filename} — create/overwrite a file with the request body.
+///
+/// Responds 201 with a `Location` pointing at the stored resource when the
+/// file is new, or 200 when an existing file was overwritten. Existence is
+/// checked before writing, so two racing writers may both see "new"; that's
+/// acceptable here since the file content ends up the same either way.
+///
+/// The request's `Content-Type`, if any, is remembered for serving the
+/// file; writing without one reverts to the type the extension implies.
+/// Either way, an active type is served back only as an attachment.
+///
+/// `If-Match` and `If-None-Match` make the write conditional on the file's
+/// current ETag, answering 412 when they fail, so clients can avoid
+/// overwriting each other's changes (`If-None-Match: *` means "only if it
+/// doesn't exist yet").
+async fn handle_file_post(
+    file_path: &Path,
+    filename: &str,
+    request: &Request,
+    types: &UploadedTypes,
+) -> Result<Response> {
     let body = request.body.as_deref().unwrap_or_default();
+    let current = fs::metadata(file_path).await.ok();
+    let existed = current.is_some();
+
+    let etag = current.as_ref().filter(|m| m.is_file()).map(etag_for);
+    if !write_preconditions_hold(request, existed, etag.as_deref()) {
+        debug!("write precondition failed for {:?}", file_path);
+        return Ok(Response::error(412, "Precondition Failed"));
+    }
 
     fs::write(file_path, body)
         .await
         .context("writing file to disk")?;
+    types.set(file_path, request.header_value("Content-Type"));
+
+    if existed {
+        debug!("overwrote file {:?} ({} bytes)", file_path, body.len());
+        return Ok(Response::new(200, "OK"));
+    }
 
     debug!("created file {:?} ({} bytes)", file_path, body.len());
 
-    Ok(Some(Response::created()))
+    let location = percent_encode_path(&format!("/files/{}", filename));
+    Ok(Response::created_at(&location))
+}
+
+/// PATCH /files/{filename} — append the request body to an existing file,
+/// or with `Content-Range: bytes a-b/*`, overwrite those bytes in place.
+///
+/// Only existing regular files can be patched: missing ones are 404 and
+/// anything else 409. A range must be as long as the body (else 400) and
+/// can't start past the end of the file, which would leave a hole (416).
+/// `If-Match` and `If-None-Match` apply as for a PUT.
+async fn handle_file_patch(file_path: &Path, request: &Request) -> Result<Response> {
+    let body = request.body.as_deref().unwrap_or_default();
+    let meta = match fs::metadata(file_path).await {
+        Ok(m) if m.is_file() => m,
+        Ok(_) => return Ok(Response::error(409, "Conflict")),
+        Err(_) => return Ok(Response::not_found()),
+    };
+    if !write_preconditions_hold(request, true, Some(&etag_for(&meta))) {
+        debug!("write precondition failed for {:?}", file_path);
+        return Ok(Response::error(412, "Precondition Failed"));
+    }
+
+    let mut options = fs::OpenOptions::new();
+    let mut file = match request.header_value("Content-Range") {
+        None => {
+            debug!("appending {} bytes to {:?}", body.len(), file_path);
+            options.append(true).open(file_path).await
+        }
+        Some(header) => {
+            let Some(range) = headers::parse_content_range(header)
+                .filter(|range| range.size() == body.len() as u64)
+            else {
+                return Ok(Response::error(400, "Bad Request"));
+            };
+            if range.start > meta.len() {
+                let mut resp = Response::error(416, "Range Not Satisfiable");
+                resp.header("Content-Range", &format!("bytes */{}", meta.len()));
+                return Ok(resp);
+            }
+            debug!(
+                "patching bytes {}-{} of {:?}",
+                range.start, range.end, file_path
+            );
+            let mut file = options.write(true).open(file_path).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+            Ok(file)
+        }
+    }
+    .context("opening file for patching")?;
+    file.write_all(body).await.context("patching file")?;
+    file.flush().await.context("patching file")?;
+    Ok(Response::new(200, "OK"))
+}
+
+/// DELETE /files/{filename} — remove a regular file.
+///
+/// Missing files yield 404; directories are refused with 409 rather than
+/// being removed. A symlink is removed itself, never the file it points to.
+async fn handle_file_delete(file_path: &Path, types: &UploadedTypes) -> Result<Response> {
+    match fs::symlink_metadata(file_path).await {
+        Ok(m) if m.is_dir() => {
+            let mut resp = Response::new(409, "Conflict");
+            resp.header("Content-Type", "text/plain")
+                .body_bytes(b"Conflict".to_vec());
+            return Ok(resp);
+        }
+        Ok(_) => {}
+        Err(_) => return Ok(Response::not_found()),
+    }
+
+    match fs::remove_file(file_path).await {
+        Ok(()) => {
+            debug!("deleted file {:?}", file_path);
+            types.remove(file_path);
+            Ok(Response::no_content())
+        }
+        // Lost a race with another delete
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Response::not_found()),
+        Err(e) => Err(e).context("deleting file"),
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Helpers
 // ---------------------------------------------------------------------------
 
-/// Returns `true` when `name` is exactly one normal path component
-/// (no separators, no `..`, no absolute prefix).
-fn is_valid_single_filename(name: &str) -> bool {
-    let path = Path::new(name);
-    let mut components = path.components();
+/// File served when a GET resolves to a directory.
+const INDEX_FILE: &str = "index.html";
 
-    let is_normal = matches!(components.next(), Some(Component::Normal(_)));
-    let is_single = components.next().is_none();
+/// Stat `path` and return the file to serve along with its metadata: a
+/// regular file, or a pipe or character device streamed until it ends.
+/// Directories resolve to their [`INDEX_FILE`]; anything else is `None`.
+async fn resolve_servable_file(path: &Path) -> Option<(PathBuf, Metadata)> {
+    let meta = fs::metadata(path).await.ok()?;
+    if meta.is_file() || is_stream_file(&meta) {
+        return Some((path.to_path_buf(), meta));
+    }
+    if !meta.is_dir() {
+        return None;
+    }
 
-    is_normal && is_single
+    let index = path.join(INDEX_FILE);
+    let meta = fs::metadata(&index).await.ok().filter(|m| m.is_file())?;
+    Some((index, meta))
 }
 
-/// Spin up a server that handles a full persistent connection
-/// (multiple requests on the same TCP stream), then returns the
-/// address to connect to.
-async fn persistent_server(files_dir: PathBuf) -> std::net::SocketAddr {
-    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-    let addr = listener.local_addr().unwrap();
-    let dir = Arc::new(files_dir);
-
-    tokio::spawn(async move {
-        let (stream, _) = listener.accept().await.unwrap();
-        handle_request(stream, dir).await.unwrap();
-    });
+/// The precompressed `<name>.gz` beside the file at `path`, if it's a
+/// regular file no older than the original (whose metadata is `meta`).
+/// Symlinks are ignored, so a sidecar can't lead out of the directory.
+async fn gzip_sidecar(path: &Path, meta: &Metadata) -> Option<(PathBuf, Metadata)> {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    let gz_path = PathBuf::from(name);
+    let gz_meta = fs::symlink_metadata(&gz_path)
+        .await
+        .ok()
+        .filter(|m| m.is_file())?;
+    (gz_meta.modified().ok()? >= meta.modified().ok()?).then_some((gz_path, gz_meta))
+}
 
-    addr
+/// Whether `meta` is a pipe or character device: readable, but with no
+/// length known up front.
+#[cfg(unix)]
+fn is_stream_file(meta: &Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    meta.file_type().is_fifo() || meta.file_type().is_char_device()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write as IoWrite;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
-    use tokio::net::TcpListener;
+#[cfg(not(unix))]
+fn is_stream_file(_meta: &Metadata) -> bool {
+    false
+}
 
-    // ── Helper ───────────────────────────────────────────────────────
+/// Strong entity tag for a file, derived from its modification time and
+/// size: `"<mtime-nanos-hex>-<len-hex>"`. Changes whenever the file is
+/// rewritten, without having to read its contents.
+fn etag_for(meta: &Metadata) -> String {
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", mtime, meta.len())
+}
 
-    /// Send a raw HTTP request through a real TCP connection and return
-    /// the full response bytes.
-    async fn send_raw_request(addr: std::net::SocketAddr, raw_request: &[u8]) -> Vec<u8> {
-        let mut client = TcpStream::connect(addr).await.unwrap();
-        client.write_all(raw_request).await.unwrap();
-        client.shutdown().await.unwrap();
+/// Whether an `If-None-Match` list matches `etag`. Uses weak comparison, so
+/// `W/` prefixes are ignored; `*` matches any current representation.
+fn etag_list_matches(list: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let etag = opaque(etag);
+    list.split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
 
-        let mut buf = Vec::new();
-        client.read_to_end(&mut buf).await.unwrap();
-        buf
+/// Whether a write's `If-Match` and `If-None-Match` headers allow it,
+/// given whether the target exists and its current ETag.
+///
+/// `If-Match` uses strong comparison, so weak tags never match; `*` only
+/// needs the target to exist. `If-None-Match` fails on any match, `*`
+/// included (RFC 9110 §13.1.1, §13.1.2).
+fn write_preconditions_hold(request: &Request, exists: bool, etag: Option<&str>) -> bool {
+    if let Some(list) = request.header_value("If-Match") {
+        let matches = list
+            .split(',')
+            .map(str::trim)
+            .any(|tag| (tag == "*" && exists) || (!tag.starts_with("W/") && Some(tag) == etag));
+        if !matches {
+            return false;
+        }
     }
-
-    /// Spin up a one-shot server that handles exactly one request,
-    /// returning the address to connect to.
-    async fn one_shot_server(files_dir: PathBuf) -> std::net::SocketAddr {
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        let dir = Arc::new(files_dir);
-
-        tokio::spawn(async move {
-            let (stream, _) = listener.accept().await.unwrap();
-            handle_request(stream, dir).await.unwrap();
-        });
-
-        addr
+    if let Some(list) = request.header_value("If-None-Match") {
+        let any_current = list.split(',').any(|tag| tag.trim() == "*") && exists;
+        if any_current || etag.is_some_and(|etag| etag_list_matches(list, etag)) {
+            return false;
+        }
     }
+    true
+}
 
-    // ── is_valid_single_filename ─────────────────────────────────────
-
-    #[test]
-    fn test_valid_filename_simple() {
-        assert!(is_valid_single_filename("hello.txt"));
+/// Whether the client's cached copy is still current, so a 304 can be sent
+/// instead of the file.
+///
+/// `If-None-Match` takes precedence; `If-Modified-Since` is only consulted
+/// without it, and is ignored if it isn't a valid HTTP-date. Both dates
+/// have one-second resolution.
+fn is_not_modified(request: &Request, etag: &str, last_modified: Option<HttpDate>) -> bool {
+    if let Some(tags) = request.header_value("If-None-Match") {
+        return etag_list_matches(tags, etag);
     }
-
-    #[test]
-    fn test_valid_filename_no_extension() {
-        assert!(is_valid_single_filename("README"));
+    let since = request
+        .header_value("If-Modified-Since")
+        .and_then(|date| date.parse::<HttpDate>().ok());
+    match (since, last_modified) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
     }
+}
 
-    #[test]
-    fn test_valid_filename_dotfile() {
-        assert!(is_valid_single_filename(".gitignore"));
+/// Whether a `Range` may be honoured given the request's `If-Range`: always
+/// without one, otherwise only if it names the current file. An entity tag
+/// must match strongly, so weak tags never do; a date must equal
+/// `Last-Modified` exactly.
+fn if_range_matches(request: &Request, etag: &str, last_modified: Option<HttpDate>) -> bool {
+    let Some(validator) = request.header_value("If-Range").map(str::trim) else {
+        return true;
+    };
+    if validator.starts_with('"') {
+        return validator == etag;
     }
-
-    #[test]
-    fn test_valid_filename_with_spaces() {
-        assert!(is_valid_single_filename("my file.txt"));
+    match (validator.parse::<HttpDate>(), last_modified) {
+        (Ok(date), Some(modified)) => date == modified,
+        _ => false,
     }
+}
 
-    #[test]
-    fn test_invalid_filename_empty() {
-        assert!(!is_valid_single_filename(""));
+/// Resolve `requested` (relative to `files_dir`) to a path guaranteed to stay
+/// inside `files_dir`, or `None` if it would escape or can't be resolved.
+///
+/// The directory part is checked by [`resolve_safe_entry`]. A final component
+/// that exists is canonicalized and checked against the canonical
+/// `files_dir` too, which catches symlinks pointing outside it; one that
+/// doesn't (e.g. a POST target) is returned as is, unless it's a dangling
+/// symlink, which is refused since it still points somewhere.
+async fn resolve_safe_path(files_dir: &Path, requested: &str) -> Option<PathBuf> {
+    let entry = resolve_safe_entry(files_dir, requested).await?;
+    let root = fs::canonicalize(files_dir).await.ok()?;
+
+    match fs::canonicalize(&entry).await {
+        Ok(resolved) => resolved.starts_with(&root).then_some(resolved),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            fs::symlink_metadata(&entry).await.is_err().then_some(entry)
+        }
+        Err(_) => None,
     }
+}
 
-    #[test]
-    fn test_invalid_filename_dot_dot() {
-        assert!(!is_valid_single_filename(".."));
+/// Resolve `requested` to the directory entry it names inside `files_dir`,
+/// without following the final component, or `None` if it would escape.
+///
+/// Every component must be a normal name, so `..` and absolute paths are
+/// rejected up front. The parent directory is canonicalized and checked
+/// against the canonical `files_dir`, so the result is always the canonical
+/// parent joined with the requested name; if that name is a symlink it's
+/// the link itself, wherever it points.
+async fn resolve_safe_entry(files_dir: &Path, requested: &str) -> Option<PathBuf> {
+    let relative = Path::new(requested);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return None;
     }
 
-    #[test]
-    fn test_invalid_filename_traversal() {
-        assert!(!is_valid_single_filename("../etc/passwd"));
-    }
+    let root = fs::canonicalize(files_dir).await.ok()?;
+    let Some(name) = relative.file_name() else {
+        return Some(root);
+    };
+    let parent = fs::canonicalize(root.join(relative).parent()?).await.ok()?;
+    parent.starts_with(&root).then(|| parent.join(name))
+}
 
-    #[test]
-    fn test_invalid_filename_nested_path() {
-        assert!(!is_valid_single_filename("a/b"));
+/// Escape the characters that are significant in HTML text and attributes.
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
     }
+    out
+}
 
-    #[test]
-    fn test_invalid_filename_nested_deep() {
-        assert!(!is_valid_single_filename("a/b/c.txt"));
+/// Percent-encode a URL path, leaving unreserved characters and `/` as-is.
+pub(crate) fn percent_encode_path(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'/') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// `path` with its trailing slash added or removed as `mode` asks, or
+/// `None` if it's already in that form (or `mode` is off). The root path
+/// `/` is always left alone.
+pub fn normalize_path(path: &str, mode: TrailingSlash) -> Option<String> {
+    match mode {
+        TrailingSlash::Off => None,
+        TrailingSlash::Strip => {
+            let stripped = path.trim_end_matches('/');
+            (stripped.len() < path.len() && !stripped.is_empty()).then(|| stripped.to_owned())
+        }
+        TrailingSlash::Append => (!path.ends_with('/')).then(|| format!("{}/", path)),
+    }
+}
+
+/// Map a file name's extension (case-insensitive) to a MIME type, falling
+/// back to `application/octet-stream` for unknown or missing extensions.
+pub(crate) fn mime_for_extension(name: &str) -> &'static str {
+    let ext = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match ext.as_deref() {
+        Some("html" | "htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("txt") => "text/plain",
+        Some("svg") => "image/svg+xml",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether a browser would render or run `content_type` as part of the
+/// page rather than just display it: HTML, XML (including SVG) and script.
+fn is_active_content_type(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "text/html"
+                | "text/xml"
+                | "application/xml"
+                | "text/javascript"
+                | "application/javascript"
+                | "application/ecmascript"
+                | "text/ecmascript"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_pages::{ErrorPage, ErrorPages};
+    use crate::request::HttpVersion;
+    use crate::testutil::{raw_roundtrip, spawn_test_server, spawn_test_server_with};
+    use std::io::Write as IoWrite;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    // ── Helper ───────────────────────────────────────────────────────
+
+    /// Read exactly one response (headers plus `Content-Length` body bytes)
+    /// from a keep-alive connection, however many reads that takes.
+    async fn read_one_response(client: &mut TcpStream) -> String {
+        let mut buf = Vec::new();
+        let mut chunk = vec![0u8; 4096];
+        loop {
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&buf[..pos]).into_owned();
+                let len: usize = head
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Content-Length: "))
+                    .map_or(0, |v| v.parse().unwrap());
+                if buf.len() >= pos + 4 + len {
+                    return String::from_utf8_lossy(&buf).into_owned();
+                }
+            }
+            let n = client.read(&mut chunk).await.unwrap();
+            assert!(n > 0, "connection closed mid-response");
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    // ── resolve_safe_path ────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_single_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "a").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+
+        let resolved = resolve_safe_path(tmp.path(), "a.txt").await;
+        assert_eq!(resolved, Some(root.join("a.txt")));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_nested_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("x/y")).unwrap();
+        std::fs::write(tmp.path().join("x/y/z.txt"), "z").unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+
+        let resolved = resolve_safe_path(tmp.path(), "x/y/z.txt").await;
+        assert_eq!(resolved, Some(root.join("x/y/z.txt")));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_new_file_in_existing_subdir() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("sub")).unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+
+        let resolved = resolve_safe_path(tmp.path(), "sub/new.txt").await;
+        assert_eq!(resolved, Some(root.join("sub/new.txt")));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_new_file_in_missing_subdir() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(resolve_safe_path(tmp.path(), "nope/new.txt").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_rejects_dot_dot() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("sub")).unwrap();
+
+        assert_eq!(resolve_safe_path(tmp.path(), "..").await, None);
+        assert_eq!(resolve_safe_path(tmp.path(), "sub/../..").await, None);
+        assert_eq!(resolve_safe_path(tmp.path(), "sub/../a.txt").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_rejects_absolute() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(resolve_safe_path(tmp.path(), "/etc/passwd").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_safe_path_empty_is_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        assert_eq!(resolve_safe_path(tmp.path(), "").await, Some(root));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resolve_safe_path_rejects_symlink_escape() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        std::fs::write(outside.join("secret.txt"), "s").unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("dirlink")).unwrap();
+
+        assert_eq!(resolve_safe_path(&root, "link.txt").await, None);
+        assert_eq!(resolve_safe_path(&root, "dirlink/secret.txt").await, None);
+        assert_eq!(resolve_safe_path(&root, "dirlink/new.txt").await, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resolve_safe_path_rejects_dangling_symlink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(tmp.path().join("missing.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink("missing.txt", root.join("inside.txt")).unwrap();
+
+        assert_eq!(resolve_safe_path(&root, "link.txt").await, None);
+        assert_eq!(resolve_safe_path(&root, "inside.txt").await, None);
+    }
+
+    // ── resolve_safe_entry ───────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_resolve_safe_entry_new_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+
+        let resolved = resolve_safe_entry(tmp.path(), "new.txt").await;
+        assert_eq!(resolved, Some(root.join("new.txt")));
+        assert_eq!(resolve_safe_entry(tmp.path(), "").await, Some(root));
+        assert_eq!(resolve_safe_entry(tmp.path(), "../new.txt").await, None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_resolve_safe_entry_keeps_final_symlink() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(outside.join("x.txt"), root.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("dirlink")).unwrap();
+        let canonical = root.canonicalize().unwrap();
+
+        let resolved = resolve_safe_entry(&root, "link.txt").await;
+        assert_eq!(resolved, Some(canonical.join("link.txt")));
+        assert_eq!(resolve_safe_entry(&root, "dirlink/x.txt").await, None);
+    }
+
+    // ── mime_for_extension ───────────────────────────────────────────
+
+    #[test]
+    fn test_mime_known_extensions() {
+        assert_eq!(mime_for_extension("index.html"), "text/html");
+        assert_eq!(mime_for_extension("style.css"), "text/css");
+        assert_eq!(mime_for_extension("app.js"), "text/javascript");
+        assert_eq!(mime_for_extension("data.json"), "application/json");
+        assert_eq!(mime_for_extension("logo.png"), "image/png");
+        assert_eq!(mime_for_extension("photo.jpg"), "image/jpeg");
+        assert_eq!(mime_for_extension("notes.txt"), "text/plain");
+        assert_eq!(mime_for_extension("icon.svg"), "image/svg+xml");
+        assert_eq!(mime_for_extension("doc.pdf"), "application/pdf");
+    }
+
+    #[test]
+    fn test_mime_case_insensitive() {
+        assert_eq!(mime_for_extension("INDEX.HTML"), "text/html");
+        assert_eq!(mime_for_extension("Photo.JpG"), "image/jpeg");
+    }
+
+    #[test]
+    fn test_mime_unknown_extension() {
+        assert_eq!(
+            mime_for_extension("archive.xyz"),
+            "application/octet-stream"
+        );
     }
 
     #[test]
-    fn test_invalid_filename_absolute() {
-        assert!(!is_valid_single_filename("/etc/passwd"));
+    fn test_mime_no_extension() {
+        assert_eq!(mime_for_extension("README"), "application/octet-stream");
+        assert_eq!(mime_for_extension(".gitignore"), "application/octet-stream");
+    }
+
+    // ── is_active_content_type ───────────────────────────────────────
+
+    #[test]
+    fn test_active_content_types() {
+        assert!(is_active_content_type("text/html"));
+        assert!(is_active_content_type("Text/HTML; charset=utf-8"));
+        assert!(is_active_content_type("image/svg+xml"));
+        assert!(is_active_content_type("application/xhtml+xml"));
+        assert!(is_active_content_type("text/javascript"));
+        assert!(!is_active_content_type("text/plain"));
+        assert!(!is_active_content_type("image/png"));
+        assert!(!is_active_content_type("application/json"));
+    }
+
+    // ── html_escape / percent_encode_path ────────────────────────────
+
+    #[test]
+    fn test_html_escape() {
+        assert_eq!(
+            html_escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("plain.txt"), "plain.txt");
     }
 
     #[test]
-    fn test_invalid_filename_dot() {
-        assert!(!is_valid_single_filename("."));
+    fn test_percent_encode_path() {
+        assert_eq!(percent_encode_path("/files/a b.txt"), "/files/a%20b.txt");
+        assert_eq!(percent_encode_path("/files/caf\u{e9}"), "/files/caf%C3%A9");
+        assert_eq!(percent_encode_path("/files/x?y#z"), "/files/x%3Fy%23z");
     }
 
     #[test]
-    fn test_trailing_slash_normalized_by_os() {
-        // On Unix, Path::new("dir/").components() normalizes away the trailing
-        // slash, yielding a single Normal("dir") component.  This is fine —
-        // the downstream `meta.is_file()` check rejects directories anyway.
-        assert!(is_valid_single_filename("dir/"));
+    fn test_normalize_path() {
+        use TrailingSlash::*;
+        assert_eq!(
+            normalize_path("/events/", Strip).as_deref(),
+            Some("/events")
+        );
+        assert_eq!(normalize_path("/a/b//", Strip).as_deref(), Some("/a/b"));
+        assert_eq!(normalize_path("/events", Strip), None);
+        assert_eq!(normalize_path("/files", Append).as_deref(), Some("/files/"));
+        assert_eq!(normalize_path("/files/", Append), None);
+        for mode in [Off, Strip, Append] {
+            assert_eq!(normalize_path("/", mode), None);
+        }
+        assert_eq!(normalize_path("/events/", Off), None);
     }
 
     // ── handle_root ──────────────────────────────────────────────────
@@ -319,7 +1625,7 @@ mod tests {
 
     #[test]
     fn test_handle_echo_returns_body() {
-        let resp = handle_echo("hello-world");
+        let resp = handle_echo("hello-world", None);
         let raw = String::from_utf8(resp.build_raw()).unwrap();
         assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"));
         assert!(raw.contains("Content-Type: text/plain\r\n"));
@@ -329,7 +1635,7 @@ mod tests {
 
     #[test]
     fn test_handle_echo_empty_string() {
-        let resp = handle_echo("");
+        let resp = handle_echo("", None);
         let raw = String::from_utf8(resp.build_raw()).unwrap();
         assert!(raw.contains("Content-Length: 0\r\n"));
         assert!(raw.ends_with("\r\n\r\n"));
@@ -337,11 +1643,42 @@ mod tests {
 
     #[test]
     fn test_handle_echo_special_characters() {
-        let resp = handle_echo("hello world & foo=bar");
+        let resp = handle_echo("hello world & foo=bar", None);
         let raw = String::from_utf8(resp.build_raw()).unwrap();
         assert!(raw.ends_with("\r\n\r\nhello world & foo=bar"));
     }
 
+    #[test]
+    fn test_handle_echo_json_when_preferred() {
+        let resp = handle_echo("hi", Some("application/json"));
+        let raw = String::from_utf8(resp.build_raw()).unwrap();
+        assert!(raw.contains("Content-Type: application/json\r\n"));
+        assert!(raw.contains("Vary: Accept\r\n"));
+        assert!(raw.ends_with("\r\n\r\n{\"echo\":\"hi\"}"));
+    }
+
+    #[test]
+    fn test_handle_echo_text_when_json_not_acceptable() {
+        for accept in ["text/plain", "image/png", "application/json;q=0"] {
+            let resp = handle_echo("hi", Some(accept));
+            assert_eq!(
+                resp.headers_all("Content-Type"),
+                vec!["text/plain"],
+                "{}",
+                accept
+            );
+        }
+    }
+
+    #[test]
+    fn test_json_string_escapes() {
+        assert_eq!(json_string("plain"), "\"plain\"");
+        assert_eq!(json_string("a\"b\\c"), r#""a\"b\\c""#);
+        assert_eq!(json_string("line\nbreak\ttab"), r#""line\nbreak\ttab""#);
+        assert_eq!(json_string("\u{1}\u{7f}"), r#""\u0001\u007f""#);
+        assert_eq!(json_string("héllo ✓"), "\"héllo ✓\"");
+    }
+
     // ── handle_user_agent ────────────────────────────────────────────
 
     #[test]
@@ -349,10 +1686,12 @@ mod tests {
         let req = Request {
             method: "GET".to_string(),
             path: "/user-agent".to_string(),
-            http_version: "HTTP/1.1".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
             headers: vec![("User-Agent".into(), "curl/7.64.1".into())],
             body: None,
             peer_addr: None,
+            request_id: None,
         };
         let resp = handle_user_agent(&req);
         let raw = String::from_utf8(resp.build_raw()).unwrap();
@@ -365,10 +1704,12 @@ mod tests {
         let req = Request {
             method: "GET".to_string(),
             path: "/user-agent".to_string(),
-            http_version: "HTTP/1.1".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
             headers: vec![("user-agent".into(), "MyBot/2.0".into())],
             body: None,
             peer_addr: None,
+            request_id: None,
         };
         let resp = handle_user_agent(&req);
         let raw = String::from_utf8(resp.build_raw()).unwrap();
@@ -381,10 +1722,12 @@ mod tests {
         let req = Request {
             method: "GET".to_string(),
             path: "/user-agent".to_string(),
-            http_version: "HTTP/1.1".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
             headers: vec![],
             body: None,
             peer_addr: None,
+            request_id: None,
         };
         let resp = handle_user_agent(&req);
         let raw = String::from_utf8(resp.build_raw()).unwrap();
@@ -395,8 +1738,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_integration_get_root() {
-        let addr = one_shot_server(PathBuf::from("/tmp")).await;
-        let resp = send_raw_request(addr, b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await;
         let text = String::from_utf8(resp).unwrap();
 
         assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
@@ -405,8 +1748,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_integration_get_echo() {
-        let addr = one_shot_server(PathBuf::from("/tmp")).await;
-        let resp = send_raw_request(addr, b"GET /echo/foobar HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/foobar HTTP/1.1\r\nHost: test\r\n\r\n").await;
         let text = String::from_utf8(resp).unwrap();
 
         assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
@@ -414,10 +1757,106 @@ mod tests {
         assert!(text.ends_with("foobar"));
     }
 
+    #[tokio::test]
+    async fn test_integration_echo_ignores_query_string() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/x?y=z HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Length: 1\r\n"));
+        assert!(text.ends_with("\r\n\r\nx"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_echo_percent_decoded() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /echo/hello%20world HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Length: 11\r\n"));
+        assert!(text.ends_with("\r\n\r\nhello world"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_echo_negotiates_json_or_text() {
+        for (accept, content_type, body) in [
+            (
+                "Accept: application/json\r\n",
+                "application/json",
+                r#"{"echo":"say \"hi\""}"#,
+            ),
+            ("Accept: text/plain\r\n", "text/plain", r#"say "hi""#),
+            ("", "text/plain", r#"say "hi""#),
+        ] {
+            let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+            let raw = format!(
+                "GET /echo/say%20%22hi%22 HTTP/1.1\r\nHost: test\r\n{}\r\n",
+                accept
+            );
+            let resp = raw_roundtrip(addr, raw.as_bytes()).await;
+            let text = String::from_utf8(resp).unwrap();
+            assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+            assert!(
+                text.contains(&format!("Content-Type: {}\r\n", content_type)),
+                "{}",
+                text
+            );
+            assert!(text.ends_with(&format!("\r\n\r\n{}", body)), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_integration_echo_gzip_round_trip() {
+        use std::io::Read;
+
+        let echoed = "compress-me-".repeat(10);
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let req = format!(
+            "GET /echo/{} HTTP/1.1\r\nHost: test\r\nAccept-Encoding: gzip\r\n\r\n",
+            echoed
+        );
+        let resp = raw_roundtrip(addr, req.as_bytes()).await;
+
+        let header_end = b"\r\n\r\n";
+        let pos = resp
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .expect("should have header terminator");
+        let head = String::from_utf8_lossy(&resp[..pos]);
+        let body = &resp[pos + header_end.len()..];
+
+        assert!(head.contains("Content-Encoding: gzip"), "head: {}", head);
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, echoed);
+    }
+
+    #[tokio::test]
+    async fn test_integration_echo_no_gzip_without_accept_encoding() {
+        let echoed = "compress-me-".repeat(10);
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let req = format!("GET /echo/{} HTTP/1.1\r\nHost: test\r\n\r\n", echoed);
+        let resp = raw_roundtrip(addr, req.as_bytes()).await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(!text.contains("Content-Encoding"));
+        assert!(text.ends_with(&echoed));
+    }
+
     #[tokio::test]
     async fn test_integration_get_user_agent() {
-        let addr = one_shot_server(PathBuf::from("/tmp")).await;
-        let resp = send_raw_request(
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(
             addr,
             b"GET /user-agent HTTP/1.1\r\nHost: test\r\nUser-Agent: TestAgent/1.0\r\n\r\n",
         )
@@ -428,10 +1867,33 @@ mod tests {
         assert!(text.ends_with("TestAgent/1.0"));
     }
 
+    #[tokio::test]
+    async fn test_integration_unsupported_version_returns_505() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET / HTTP/3.0\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"),
+            "got: {}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_http10_request_accepted() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/old HTTP/1.0\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.ends_with("old"));
+    }
+
     #[tokio::test]
     async fn test_integration_unknown_path_returns_404() {
-        let addr = one_shot_server(PathBuf::from("/tmp")).await;
-        let resp = send_raw_request(addr, b"GET /nonexistent HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /nonexistent HTTP/1.1\r\nHost: test\r\n\r\n").await;
         let text = String::from_utf8(resp).unwrap();
 
         assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
@@ -446,8 +1908,8 @@ mod tests {
             f.write_all(b"file contents here").unwrap();
         }
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let resp = send_raw_request(
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
             addr,
             b"GET /files/testfile.txt HTTP/1.1\r\nHost: test\r\n\r\n",
         )
@@ -455,17 +1917,34 @@ mod tests {
         let text = String::from_utf8(resp).unwrap();
 
         assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
-        assert!(text.contains("Content-Type: application/octet-stream\r\n"));
+        assert!(text.contains("Content-Type: text/plain\r\n"));
         assert!(text.contains("Content-Length: 18\r\n"));
         assert!(text.ends_with("file contents here"));
     }
 
+    #[tokio::test]
+    async fn test_integration_file_html_content_type() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("page.html"), "<h1>hi</h1>").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp =
+            raw_roundtrip(addr, b"GET /files/page.html HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Type: text/html\r\n"));
+        // Only uploads are forced to download
+        assert_eq!(response_header(&text, "Content-Disposition"), None);
+        assert!(text.ends_with("<h1>hi</h1>"));
+    }
+
     #[tokio::test]
     async fn test_integration_file_not_found() {
         let tmp = tempfile::tempdir().unwrap();
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let resp = send_raw_request(
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
             addr,
             b"GET /files/does_not_exist.txt HTTP/1.1\r\nHost: test\r\n\r\n",
         )
@@ -476,173 +1955,3594 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_integration_file_traversal_rejected() {
-        let tmp = tempfile::tempdir().unwrap();
-
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let resp = send_raw_request(
-            addr,
-            b"GET /files/../etc/passwd HTTP/1.1\r\nHost: test\r\n\r\n",
+    async fn test_integration_file_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/../etc/passwd HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_encoded_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("sub")).unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "secret").unwrap();
+
+        // `%2e%2e` decodes to `..` and `%2f` to `/` — both must still be rejected
+        let addr = spawn_test_server(tmp.path().join("sub")).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/%2e%2e%2fsecret.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_encoded_dot_dot_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/%2e%2e HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_equivalent_paths_normalized() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("bar.txt"), "bar").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/./sub/..//bar.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("\r\n\r\nbar"));
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /echo//foo HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.ends_with("\r\n\r\nfoo"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_nested_path_allowed() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("assets")).unwrap();
+        std::fs::write(tmp.path().join("assets/logo.png"), "png-bytes").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/assets/logo.png HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.contains("Content-Type: image/png\r\n"));
+        assert!(text.ends_with("png-bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_nested_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("root/sub")).unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "secret").unwrap();
+
+        let addr = spawn_test_server(tmp.path().join("root")).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/sub/../../secret.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_integration_file_symlink_escape_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+
+        let addr = spawn_test_server(root).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/escape/secret.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_integration_file_delete_symlink_keeps_target() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("target.txt"), "kept").unwrap();
+        std::os::unix::fs::symlink(tmp.path().join("target.txt"), tmp.path().join("link")).unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"DELETE /files/link HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 204 No Content\r\n"), "{text}");
+        assert!(std::fs::symlink_metadata(tmp.path().join("link")).is_err());
+        assert_eq!(
+            std::fs::read(tmp.path().join("target.txt")).unwrap(),
+            b"kept"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_integration_file_write_through_symlink_refused() {
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("kept.txt"), "kept").unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::os::unix::fs::symlink(outside.join("planted.txt"), root.join("dangling")).unwrap();
+        std::os::unix::fs::symlink(outside.join("kept.txt"), root.join("existing")).unwrap();
+
+        for raw in [
+            &b"POST /files/dangling HTTP/1.1\r\nHost: test\r\nContent-Length: 3\r\n\r\nbad"[..],
+            b"PUT /files/existing HTTP/1.1\r\nHost: test\r\nContent-Length: 3\r\n\r\nbad",
+            b"PATCH /files/existing HTTP/1.1\r\nHost: test\r\nContent-Length: 3\r\n\r\nbad",
+        ] {
+            let addr = spawn_test_server(root.clone()).await;
+            let text = String::from_utf8(raw_roundtrip(addr, raw).await).unwrap();
+            assert!(text.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{text}");
+        }
+
+        assert!(!outside.join("planted.txt").exists());
+        assert_eq!(std::fs::read(outside.join("kept.txt")).unwrap(), b"kept");
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("empty.dat"), b"").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp =
+            raw_roundtrip(addr, b"GET /files/empty.dat HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(text.contains("Content-Length: 0\r\n"));
+        assert!(text.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_binary_content() {
+        let tmp = tempfile::tempdir().unwrap();
+        let binary: Vec<u8> = vec![0x00, 0x01, 0xFF, 0xFE, 0x0A, 0x0D];
+        std::fs::write(tmp.path().join("bin.dat"), &binary).unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/bin.dat HTTP/1.1\r\nHost: test\r\n\r\n").await;
+
+        // Find end of headers
+        let header_end = b"\r\n\r\n";
+        let pos = resp
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .expect("should have header terminator");
+        let body = &resp[pos + header_end.len()..];
+
+        assert_eq!(body, &binary);
+    }
+
+    #[tokio::test]
+    async fn test_integration_file_large_multi_megabyte() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Several copy buffers' worth, not a multiple of the buffer size
+        let size = 3 * 1024 * 1024 + 12_345;
+        let content: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        std::fs::write(tmp.path().join("big.bin"), &content).unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/big.bin HTTP/1.1\r\nHost: test\r\n\r\n").await;
+
+        let header_end = b"\r\n\r\n";
+        let pos = resp
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .expect("should have header terminator");
+        let head = String::from_utf8_lossy(&resp[..pos]);
+        let body = &resp[pos + header_end.len()..];
+
+        assert!(head.contains(&format!("Content-Length: {}", size)));
+        assert_eq!(body.len(), content.len());
+        assert!(body == content.as_slice(), "streamed bytes differ");
+    }
+
+    #[tokio::test]
+    async fn test_integration_directory_serves_index_html() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("dir")).unwrap();
+        std::fs::write(tmp.path().join("dir/index.html"), "<p>index</p>").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/dir/ HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.contains("Content-Type: text/html\r\n"));
+        assert!(text.contains("Content-Length: 12\r\n"));
+        assert!(text.ends_with("<p>index</p>"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_files_root_serves_index_html() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("index.html"), "root index").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/ HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("root index"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_directory_without_index_returns_404() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("empty")).unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/empty/ HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_autoindex_lists_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(tmp.path().join("dir/nested")).unwrap();
+        std::fs::write(tmp.path().join("dir/a.txt"), "12345").unwrap();
+        std::fs::write(tmp.path().join("dir/my file.bin"), "x").unwrap();
+        std::fs::write(tmp.path().join("dir/<evil>.txt"), "").unwrap();
+
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            autoindex: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"GET /files/dir/ HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.contains("Content-Type: text/html\r\n"));
+        assert!(text.contains(r#"<a href="/files/dir/a.txt">a.txt</a></td><td>5</td><td>file"#));
+        assert!(text.contains(r#"<a href="/files/dir/my%20file.bin">my file.bin</a>"#));
+        assert!(text.contains(r#"<a href="/files/dir/nested/">nested/</a></td><td>-</td><td>dir"#));
+        assert!(text.contains("&lt;evil&gt;.txt"));
+        assert!(!text.contains("<evil>"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_autoindex_prefers_index_html() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("dir")).unwrap();
+        std::fs::write(tmp.path().join("dir/index.html"), "index wins").unwrap();
+
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            autoindex: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"GET /files/dir/ HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.ends_with("index wins"));
+    }
+
+    // ── ETag / If-None-Match ─────────────────────────────────────────
+
+    #[test]
+    fn test_etag_for_tracks_size_and_mtime() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("f.txt");
+        std::fs::write(&path, "one").unwrap();
+        let first = etag_for(&std::fs::metadata(&path).unwrap());
+        assert!(first.starts_with('"') && first.ends_with('"'), "{}", first);
+        assert_eq!(first, etag_for(&std::fs::metadata(&path).unwrap()));
+
+        std::fs::write(&path, "three").unwrap();
+        assert_ne!(first, etag_for(&std::fs::metadata(&path).unwrap()));
+    }
+
+    #[test]
+    fn test_etag_list_matches() {
+        assert!(etag_list_matches("\"a\"", "\"a\""));
+        assert!(etag_list_matches("\"x\", \"a\"", "\"a\""));
+        assert!(etag_list_matches("W/\"a\"", "\"a\""));
+        assert!(etag_list_matches("*", "\"a\""));
+        assert!(!etag_list_matches("\"b\"", "\"a\""));
+        assert!(!etag_list_matches("", "\"a\""));
+    }
+
+    fn put_with(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "PUT".to_string(),
+            path: "/files/doc.txt".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: headers
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn test_write_preconditions_hold() {
+        let current = Some("\"v2\"");
+        assert!(write_preconditions_hold(&put_with(&[]), true, current));
+        assert!(write_preconditions_hold(&put_with(&[]), false, None));
+
+        // If-Match: strong comparison against the current tag
+        let matching = put_with(&[("If-Match", "\"v1\", \"v2\"")]);
+        assert!(write_preconditions_hold(&matching, true, current));
+        let stale = put_with(&[("If-Match", "\"v1\"")]);
+        assert!(!write_preconditions_hold(&stale, true, current));
+        let weak = put_with(&[("If-Match", "W/\"v2\"")]);
+        assert!(!write_preconditions_hold(&weak, true, current));
+        let any = put_with(&[("If-Match", "*")]);
+        assert!(write_preconditions_hold(&any, true, current));
+        assert!(!write_preconditions_hold(&any, false, None));
+        assert!(!write_preconditions_hold(&matching, false, None));
+
+        // If-None-Match: * only lets creations through
+        let create_only = put_with(&[("If-None-Match", "*")]);
+        assert!(write_preconditions_hold(&create_only, false, None));
+        assert!(!write_preconditions_hold(&create_only, true, current));
+        let not_v2 = put_with(&[("If-None-Match", "\"v2\"")]);
+        assert!(!write_preconditions_hold(&not_v2, true, current));
+        assert!(write_preconditions_hold(&not_v2, true, Some("\"v3\"")));
+    }
+
+    /// Pull a header value out of a raw response.
+    fn response_header<'a>(resp: &'a str, name: &str) -> Option<&'a str> {
+        let prefix = format!("{}: ", name);
+        resp.split("\r\n\r\n")
+            .next()?
+            .lines()
+            .find_map(|l| l.strip_prefix(prefix.as_str()))
+    }
+
+    #[tokio::test]
+    async fn test_integration_etag_then_304() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("cached.txt"), "cache me").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        client
+            .write_all(b"GET /files/cached.txt HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let first = read_one_response(&mut client).await;
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"), "first: {}", first);
+        let etag = response_header(&first, "ETag")
+            .expect("ETag header")
+            .to_string();
+
+        let req = format!(
+            "GET /files/cached.txt HTTP/1.1\r\nHost: test\r\nIf-None-Match: {}\r\n\r\n",
+            etag
+        );
+        client.write_all(req.as_bytes()).await.unwrap();
+        let second = read_one_response(&mut client).await;
+        assert!(
+            second.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+            "second: {}",
+            second
+        );
+        assert_eq!(response_header(&second, "ETag"), Some(etag.as_str()));
+        assert!(second.ends_with("\r\n\r\n"), "304 must not have a body");
+
+        // The connection is still usable afterwards
+        client
+            .write_all(b"GET /echo/after HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let third = read_one_response(&mut client).await;
+        assert!(third.ends_with("after"), "third: {}", third);
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_none_match_stale_etag_serves_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("cached.txt"), "fresh").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/cached.txt HTTP/1.1\r\nHost: test\r\nIf-None-Match: \"stale\"\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("fresh"));
+    }
+
+    // ── Range / If-Range ─────────────────────────────────────────────
+
+    /// A file with known contents, and the validators the server gives it.
+    fn ranged_file() -> (tempfile::TempDir, String, String) {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("digits.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+        let meta = std::fs::metadata(&path).unwrap();
+        let etag = etag_for(&meta);
+        let date = httpdate::fmt_http_date(meta.modified().unwrap());
+        (tmp, etag, date)
+    }
+
+    #[tokio::test]
+    async fn test_integration_range_serves_206() {
+        let (tmp, _, _) = ranged_file();
+        let text = get_with_header(tmp.path(), "/files/digits.txt", "Range: bytes=2-5\r\n").await;
+        assert!(
+            text.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "Content-Range"),
+            Some("bytes 2-5/10")
+        );
+        assert_eq!(response_header(&text, "Content-Length"), Some("4"));
+        assert!(text.ends_with("\r\n\r\n2345"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_full_response_advertises_ranges() {
+        let (tmp, _, _) = ranged_file();
+        let text = get_with_header(tmp.path(), "/files/digits.txt", "").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response_header(&text, "Accept-Ranges"), Some("bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_suffix_range() {
+        let (tmp, _, _) = ranged_file();
+        let text = get_with_header(tmp.path(), "/files/digits.txt", "Range: bytes=-3\r\n").await;
+        assert_eq!(
+            response_header(&text, "Content-Range"),
+            Some("bytes 7-9/10")
+        );
+        assert!(text.ends_with("789"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_unsatisfiable_range_is_416() {
+        let (tmp, _, _) = ranged_file();
+        let text = get_with_header(tmp.path(), "/files/digits.txt", "Range: bytes=10-20\r\n").await;
+        assert!(
+            text.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(response_header(&text, "Content-Range"), Some("bytes */10"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_invalid_range_serves_whole_file() {
+        let (tmp, _, _) = ranged_file();
+        let text = get_with_header(tmp.path(), "/files/digits.txt", "Range: lines=1-2\r\n").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("0123456789"));
+    }
+
+    /// Split a `multipart/byteranges` body into (part headers, data) pairs,
+    /// checking the delimiters along the way.
+    fn multipart_parts<'a>(body: &'a str, boundary: &str) -> Vec<(&'a str, &'a str)> {
+        let delimiter = format!("--{}", boundary);
+        let body = body
+            .strip_suffix(&format!("{}--\r\n", delimiter))
+            .expect("closing delimiter");
+        body.split(&delimiter)
+            .skip(1)
+            .map(|part| {
+                let part = part.strip_prefix("\r\n").expect("CRLF after delimiter");
+                let part = part.strip_suffix("\r\n").expect("CRLF before delimiter");
+                part.split_once("\r\n\r\n").expect("part headers")
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_integration_multiple_ranges_are_multipart() {
+        let (tmp, _, _) = ranged_file();
+        let text =
+            get_with_header(tmp.path(), "/files/digits.txt", "Range: bytes=0-1,7-9\r\n").await;
+        assert!(
+            text.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert!(response_header(&text, "Content-Range").is_none());
+        let content_type = response_header(&text, "Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .expect(content_type);
+
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        let len: usize = response_header(head, "Content-Length")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(body.len(), len);
+
+        let parts = multipart_parts(body, boundary);
+        assert_eq!(
+            parts,
+            vec![
+                (
+                    "Content-Type: text/plain\r\nContent-Range: bytes 0-1/10",
+                    "01"
+                ),
+                (
+                    "Content-Type: text/plain\r\nContent-Range: bytes 7-9/10",
+                    "789"
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_overlapping_ranges_serve_whole_file() {
+        let (tmp, _, _) = ranged_file();
+        let text =
+            get_with_header(tmp.path(), "/files/digits.txt", "Range: bytes=0-8,1-9\r\n").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("\r\n\r\n0123456789"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_too_many_ranges_serve_whole_file() {
+        let (tmp, _, _) = ranged_file();
+        let specs: Vec<String> = (0..=headers::MAX_RANGES)
+            .map(|i| format!("{}-{}", i % 10, i % 10))
+            .collect();
+        let header = format!("Range: bytes={}\r\n", specs.join(","));
+        let text = get_with_header(tmp.path(), "/files/digits.txt", &header).await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("0123456789"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_range_matching_validator_serves_206() {
+        let (tmp, etag, date) = ranged_file();
+        for validator in [etag, date] {
+            let header = format!("Range: bytes=0-1\r\nIf-Range: {}\r\n", validator);
+            let text = get_with_header(tmp.path(), "/files/digits.txt", &header).await;
+            assert!(
+                text.starts_with("HTTP/1.1 206 Partial Content\r\n"),
+                "{}: {}",
+                validator,
+                text
+            );
+            assert!(text.ends_with("\r\n\r\n01"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_range_stale_validator_serves_full_200() {
+        let (tmp, etag, _) = ranged_file();
+        let weak = format!("W/{}", etag);
+        let stale_date = http_date_offset(-3600);
+        for validator in ["\"stale\"", weak.as_str(), stale_date.as_str(), "garbage"] {
+            let header = format!("Range: bytes=0-1\r\nIf-Range: {}\r\n", validator);
+            let text = get_with_header(tmp.path(), "/files/digits.txt", &header).await;
+            assert!(
+                text.starts_with("HTTP/1.1 200 OK\r\n"),
+                "{}: {}",
+                validator,
+                text
+            );
+            assert!(text.ends_with("0123456789"));
+            assert!(response_header(&text, "Content-Range").is_none());
+        }
+    }
+
+    // ── HEAD /files ──────────────────────────────────────────────────
+
+    async fn head(files_dir: &Path, target: &str) -> String {
+        let addr = spawn_test_server(files_dir.to_path_buf()).await;
+        let req = format!("HEAD {} HTTP/1.1\r\nHost: test\r\n\r\n", target);
+        String::from_utf8(raw_roundtrip(addr, req.as_bytes()).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_integration_head_file_reports_length_without_body() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("page.html"), "<p>twelve</p>").unwrap();
+
+        let text = head(tmp.path(), "/files/page.html").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(response_header(&text, "Content-Length"), Some("13"));
+        assert_eq!(response_header(&text, "Content-Type"), Some("text/html"));
+        assert!(response_header(&text, "ETag").is_some());
+        assert!(
+            text.ends_with("\r\n\r\n"),
+            "HEAD must not have a body: {}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_head_ignores_range() {
+        let (tmp, _, _) = ranged_file();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"HEAD /files/digits.txt HTTP/1.1\r\nHost: test\r\nRange: bytes=0-1\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(response_header(&text, "Content-Length"), Some("10"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_head_missing_or_escaping_is_404() {
+        let tmp = tempfile::tempdir().unwrap();
+        for target in ["/files/nope.txt", "/files/../etc/passwd"] {
+            let text = head(tmp.path(), target).await;
+            assert!(
+                text.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{}: {}",
+                target,
+                text
+            );
+            assert!(text.ends_with("\r\n\r\n"), "{}: {}", target, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_integration_head_directory_listing() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "a").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            autoindex: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"HEAD /files/ HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        let len: usize = response_header(&text, "Content-Length")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(len > 0);
+        assert!(text.ends_with("\r\n\r\n"), "got: {}", text);
+    }
+
+    // ── Precompressed .gz files ──────────────────────────────────────
+
+    const STYLE_CSS: &str = "body { color: red; }\n";
+
+    /// A files directory holding `style.css` and a gzipped `style.css.gz`.
+    fn dir_with_sidecar() -> tempfile::TempDir {
+        use std::io::Write;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("style.css"), STYLE_CSS).unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        gz.write_all(STYLE_CSS.as_bytes()).unwrap();
+        std::fs::write(tmp.path().join("style.css.gz"), gz.finish().unwrap()).unwrap();
+        tmp
+    }
+
+    async fn get_raw(files_dir: &Path, target: &str, header: &str) -> (String, Vec<u8>) {
+        let addr = spawn_test_server(files_dir.to_path_buf()).await;
+        let req = format!("GET {} HTTP/1.1\r\nHost: test\r\n{}\r\n", target, header);
+        let resp = raw_roundtrip(addr, req.as_bytes()).await;
+        let pos = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(resp[..pos + 2].to_vec()).unwrap();
+        (head, resp[pos + 4..].to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_gzip_sidecar_served_when_accepted() {
+        use std::io::Read;
+
+        let tmp = dir_with_sidecar();
+        let (head, body) = get_raw(
+            tmp.path(),
+            "/files/style.css",
+            "Accept-Encoding: br;q=1, gzip;q=0.5\r\n",
+        )
+        .await;
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert_eq!(response_header(&head, "Content-Encoding"), Some("gzip"));
+        assert_eq!(response_header(&head, "Content-Type"), Some("text/css"));
+        assert_eq!(response_header(&head, "Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            body,
+            std::fs::read(tmp.path().join("style.css.gz")).unwrap()
+        );
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, STYLE_CSS);
+    }
+
+    #[tokio::test]
+    async fn test_gzip_sidecar_skipped_when_not_accepted() {
+        let tmp = dir_with_sidecar();
+        for header in [
+            "",
+            "Accept-Encoding: br\r\n",
+            "Accept-Encoding: gzip;q=0\r\n",
+        ] {
+            let (head, body) = get_raw(tmp.path(), "/files/style.css", header).await;
+            assert_eq!(
+                response_header(&head, "Content-Encoding"),
+                None,
+                "{}",
+                header
+            );
+            assert_eq!(response_header(&head, "Vary"), Some("Accept-Encoding"));
+            assert_eq!(body, STYLE_CSS.as_bytes(), "{}", header);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stale_gzip_sidecar_ignored() {
+        let tmp = dir_with_sidecar();
+        let hour_ago = std::time::SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(tmp.path().join("style.css.gz"))
+            .unwrap()
+            .set_modified(hour_ago)
+            .unwrap();
+
+        let (head, body) =
+            get_raw(tmp.path(), "/files/style.css", "Accept-Encoding: gzip\r\n").await;
+        assert_eq!(response_header(&head, "Content-Encoding"), None, "{}", head);
+        assert_eq!(body, STYLE_CSS.as_bytes());
+    }
+
+    // ── Pipes and devices ────────────────────────────────────────────
+
+    /// Make a FIFO at `path` and feed it `contents` from another thread
+    /// once the server opens it for reading.
+    #[cfg(unix)]
+    fn fifo_with(path: &Path, contents: &'static [u8]) {
+        let status = std::process::Command::new("mkfifo")
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            // Blocks until the server opens the read end
+            let mut fifo = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+            fifo.write_all(contents).unwrap();
+        });
+    }
+
+    /// Undo chunked transfer coding, checking the framing on the way.
+    fn dechunk(mut body: &str) -> String {
+        let mut out = String::new();
+        loop {
+            let (size, rest) = body.split_once("\r\n").expect("chunk size line");
+            let size = usize::from_str_radix(size, 16).expect("hex chunk size");
+            if size == 0 {
+                assert_eq!(rest, "\r\n", "nothing after the last chunk");
+                return out;
+            }
+            out.push_str(&rest[..size]);
+            body = rest[size..].strip_prefix("\r\n").expect("CRLF after chunk");
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_integration_fifo_is_sent_chunked() {
+        let tmp = tempfile::tempdir().unwrap();
+        fifo_with(&tmp.path().join("live.txt"), b"streamed from a pipe");
+
+        let text = get_with_header(tmp.path(), "/files/live.txt", "").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(response_header(&text, "Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response_header(&text, "Content-Type"), Some("text/plain"));
+        assert!(
+            response_header(&text, "Content-Length").is_none(),
+            "{}",
+            text
+        );
+        assert!(response_header(&text, "ETag").is_none(), "{}", text);
+
+        let (_, body) = text.split_once("\r\n\r\n").unwrap();
+        assert_eq!(dechunk(body), "streamed from a pipe");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_integration_fifo_to_http10_closes_connection() {
+        let tmp = tempfile::tempdir().unwrap();
+        fifo_with(&tmp.path().join("live.txt"), b"old client");
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /files/live.txt HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(response_header(&text, "Connection"), Some("close"));
+        assert!(response_header(&text, "Transfer-Encoding").is_none());
+        assert!(response_header(&text, "Content-Length").is_none());
+        assert!(text.ends_with("\r\n\r\nold client"), "got: {}", text);
+    }
+
+    #[test]
+    fn test_dechunk() {
+        assert_eq!(dechunk("3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n"), "abcde");
+        assert_eq!(dechunk("0\r\n\r\n"), "");
+    }
+
+    // ── Last-Modified / If-Modified-Since ────────────────────────────
+
+    async fn get_with_header(files_dir: &Path, target: &str, header: &str) -> String {
+        let addr = spawn_test_server(files_dir.to_path_buf()).await;
+        let req = format!("GET {} HTTP/1.1\r\nHost: test\r\n{}\r\n", target, header);
+        String::from_utf8(raw_roundtrip(addr, req.as_bytes()).await).unwrap()
+    }
+
+    fn http_date_offset(secs: i64) -> String {
+        let now = std::time::SystemTime::now();
+        let offset = Duration::from_secs(secs.unsigned_abs());
+        let at = if secs >= 0 {
+            now + offset
+        } else {
+            now - offset
+        };
+        httpdate::fmt_http_date(at)
+    }
+
+    #[tokio::test]
+    async fn test_integration_last_modified_header() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "data").unwrap();
+        let mtime = std::fs::metadata(tmp.path().join("a.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+
+        let text = get_with_header(tmp.path(), "/files/a.txt", "").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        let header = response_header(&text, "Last-Modified").expect("Last-Modified header");
+        assert_eq!(header, httpdate::fmt_http_date(mtime));
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_modified_since_older_date_serves_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "data").unwrap();
+
+        let header = format!("If-Modified-Since: {}\r\n", http_date_offset(-3600));
+        let text = get_with_header(tmp.path(), "/files/a.txt", &header).await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("data"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_modified_since_future_date_is_304() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "data").unwrap();
+
+        let header = format!("If-Modified-Since: {}\r\n", http_date_offset(3600));
+        let text = get_with_header(tmp.path(), "/files/a.txt", &header).await;
+        assert!(
+            text.starts_with("HTTP/1.1 304 Not Modified\r\n"),
+            "got: {}",
+            text
+        );
+        assert!(response_header(&text, "Last-Modified").is_some());
+        assert!(!text.ends_with("data"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_modified_since_unparseable_is_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "data").unwrap();
+
+        let text = get_with_header(
+            tmp.path(),
+            "/files/a.txt",
+            "If-Modified-Since: yesterday-ish\r\n",
+        )
+        .await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("data"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_if_none_match_overrides_if_modified_since() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "data").unwrap();
+
+        let header = format!(
+            "If-None-Match: \"stale\"\r\nIf-Modified-Since: {}\r\n",
+            http_date_offset(3600)
+        );
+        let text = get_with_header(tmp.path(), "/files/a.txt", &header).await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+    }
+
+    // ── OPTIONS ──────────────────────────────────────────────────────
+
+    #[test]
+    fn test_allowed_methods_per_route() {
+        let router = default_router();
+        let allowed = |path| router.allowed_methods(path);
+        assert_eq!(allowed("/").as_deref(), Some("GET, HEAD, OPTIONS"));
+        assert_eq!(allowed("/echo/abc").as_deref(), Some("GET, HEAD, OPTIONS"));
+        assert_eq!(
+            allowed("/user-agent").as_deref(),
+            Some("GET, HEAD, OPTIONS")
+        );
+        assert_eq!(allowed("/files/").as_deref(), Some("GET, HEAD, OPTIONS"));
+        assert_eq!(
+            allowed("/files/a.txt").as_deref(),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+        assert_eq!(
+            allowed("*").as_deref(),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+        assert_eq!(allowed("/nope"), None);
+    }
+
+    async fn options_response(target: &str) -> String {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let req = format!("OPTIONS {} HTTP/1.1\r\nHost: test\r\n\r\n", target);
+        String::from_utf8(raw_roundtrip(addr, req.as_bytes()).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_integration_options_echo() {
+        let text = options_response("/echo/hi").await;
+        assert!(
+            text.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(response_header(&text, "Allow"), Some("GET, HEAD, OPTIONS"));
+        assert!(text.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_options_file() {
+        let text = options_response("/files/anything.txt").await;
+        assert!(
+            text.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "Allow"),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_options_asterisk() {
+        let text = options_response("*").await;
+        assert!(
+            text.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "Allow"),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_options_unknown_path_404() {
+        let text = options_response("/nope").await;
+        assert!(
+            text.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "got: {}",
+            text
+        );
+    }
+
+    // ── Routing ──────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_head_on_get_route() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"HEAD /echo/hello HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(response_header(&text, "Content-Length"), Some("5"));
+        assert!(text.ends_with("\r\n\r\n"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_head_matches_compressed_get() {
+        let head_of = async |method: &str| {
+            let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+            let raw = format!(
+                "{} /echo/{} HTTP/1.1\r\nHost: test\r\nAccept-Encoding: gzip\r\n\r\n",
+                method,
+                "a".repeat(200)
+            );
+            let text =
+                String::from_utf8_lossy(&raw_roundtrip(addr, raw.as_bytes()).await).into_owned();
+            let (head, _) = text.split_once("\r\n\r\n").unwrap();
+            let mut lines: Vec<_> = head
+                .lines()
+                .filter(|line| !line.starts_with("Date:") && !line.starts_with("X-Request-Id:"))
+                .map(str::to_owned)
+                .collect();
+            lines.sort();
+            lines
+        };
+
+        let get = head_of("GET").await;
+        assert!(
+            get.contains(&"Content-Encoding: gzip".to_string()),
+            "{get:?}"
+        );
+        assert_eq!(head_of("HEAD").await, get);
+    }
+
+    #[tokio::test]
+    async fn test_integration_head_on_event_stream_sends_no_events() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"HEAD /events HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(
+            response_header(&text, "Content-Type"),
+            Some("text/event-stream")
+        );
+        assert!(text.ends_with("\r\n\r\n"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_wrong_method_gets_405() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"DELETE /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(response_header(&text, "Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_to_files_root_gets_405() {
+        let tmp = tempfile::tempdir().unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"POST /files/ HTTP/1.1\r\nHost: test\r\nContent-Length: 1\r\n\r\nx",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 405 "), "got: {}", text);
+        assert_eq!(response_header(&text, "Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_unknown_method_gets_501() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"FROBNICATE / HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 501 Not Implemented\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(response_header(&text, "Allow"), None);
+    }
+
+    // ── Basic auth ───────────────────────────────────────────────────
+
+    async fn get_file_with_auth(addr: std::net::SocketAddr, auth: Option<&str>) -> String {
+        use base64::Engine;
+
+        let header = auth
+            .map(|creds| {
+                let encoded = base64::engine::general_purpose::STANDARD.encode(creds);
+                format!("Authorization: Basic {}\r\n", encoded)
+            })
+            .unwrap_or_default();
+        let req = format!(
+            "GET /files/secret.txt HTTP/1.1\r\nHost: test\r\n{}\r\n",
+            header
+        );
+        String::from_utf8(raw_roundtrip(addr, req.as_bytes()).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_integration_basic_auth_valid_credentials() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "classified").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            basic_auth: Some(crate::auth::Credentials::parse("alice:s3cret").unwrap()),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let text = get_file_with_auth(addr, Some("alice:s3cret")).await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("classified"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_basic_auth_wrong_password() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "classified").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            basic_auth: Some(crate::auth::Credentials::parse("alice:s3cret").unwrap()),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let text = get_file_with_auth(addr, Some("alice:guess")).await;
+        assert!(
+            text.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "got: {}",
+            text
+        );
+        assert!(!text.contains("classified"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_basic_auth_missing_header_gets_challenge() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "classified").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            basic_auth: Some(crate::auth::Credentials::parse("alice:s3cret").unwrap()),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let text = get_file_with_auth(addr, None).await;
+        assert!(
+            text.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "WWW-Authenticate"),
+            Some("Basic realm=\"files\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_basic_auth_leaves_other_routes_open() {
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: PathBuf::from("/tmp"),
+            basic_auth: Some(crate::auth::Credentials::parse("alice:s3cret").unwrap()),
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"GET /echo/open HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_basic_auth_covers_root_served_files() {
+        use base64::Engine;
+
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "classified").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            basic_auth: Some(crate::auth::Credentials::parse("alice:s3cret").unwrap()),
+            serve_root: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let get = async |target: &str, auth: &str| {
+            let req = format!("GET {} HTTP/1.1\r\nHost: test\r\n{}\r\n", target, auth);
+            String::from_utf8(raw_roundtrip(addr, req.as_bytes()).await).unwrap()
+        };
+
+        for target in ["/secret.txt", "/files/secret.txt", "/"] {
+            let text = get(target, "").await;
+            assert!(
+                text.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+                "{}: {}",
+                target,
+                text
+            );
+            assert!(!text.contains("classified"));
+            assert_eq!(
+                response_header(&text, "WWW-Authenticate"),
+                Some("Basic realm=\"site\"")
+            );
+        }
+
+        let encoded = base64::engine::general_purpose::STANDARD.encode("alice:s3cret");
+        let text = get(
+            "/secret.txt",
+            &format!("Authorization: Basic {}\r\n", encoded),
+        )
+        .await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("\r\n\r\nclassified"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_basic_auth_lets_preflight_through() {
+        let addr = spawn_test_server_with(ServerConfig {
+            basic_auth: Some(crate::auth::Credentials::parse("alice:s3cret").unwrap()),
+            serve_root: true,
+            cors: crate::cors::CorsConfig {
+                allowed_origins: vec!["https://app.example".to_string()],
+                max_age: None,
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(
+            addr,
+            b"OPTIONS /files/upload.txt HTTP/1.1\r\nHost: test\r\n\
+              Origin: https://app.example\r\nAccess-Control-Request-Method: PUT\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+    }
+
+    // ── CORS ─────────────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_cors_allowed_origin() {
+        let addr = spawn_test_server_with(ServerConfig {
+            cors: crate::cors::CorsConfig {
+                allowed_origins: vec!["https://app.example".to_string()],
+                max_age: None,
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /echo/hi HTTP/1.1\r\nHost: test\r\nOrigin: https://app.example\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert_eq!(
+            response_header(&text, "Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert!(text.ends_with("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_cors_disallowed_origin() {
+        let addr = spawn_test_server_with(ServerConfig {
+            cors: crate::cors::CorsConfig {
+                allowed_origins: vec!["https://app.example".to_string()],
+                max_age: None,
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /echo/hi HTTP/1.1\r\nHost: test\r\nOrigin: https://evil.example\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(!text.contains("Access-Control-"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_cors_preflight() {
+        let addr = spawn_test_server_with(ServerConfig {
+            cors: crate::cors::CorsConfig {
+                allowed_origins: vec!["https://app.example".to_string()],
+                max_age: None,
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(
+            addr,
+            b"OPTIONS /files/upload.txt HTTP/1.1\r\nHost: test\r\n\
+              Origin: https://app.example\r\n\
+              Access-Control-Request-Method: PUT\r\n\
+              Access-Control-Request-Headers: content-type\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(
+            response_header(&text, "Access-Control-Allow-Methods"),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS")
+        );
+        assert_eq!(
+            response_header(&text, "Access-Control-Allow-Headers"),
+            Some("content-type")
+        );
+        assert_eq!(response_header(&text, "Access-Control-Max-Age"), None);
+        assert!(text.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_cors_preflight_max_age() {
+        let addr = spawn_test_server_with(ServerConfig {
+            cors: crate::cors::CorsConfig {
+                allowed_origins: vec!["https://app.example".to_string()],
+                max_age: Some(Duration::from_secs(86400)),
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(
+            addr,
+            b"OPTIONS /echo/hi HTTP/1.1\r\nHost: test\r\n\
+              Origin: https://app.example\r\n\
+              Access-Control-Request-Method: GET\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 204 No Content\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(
+            response_header(&text, "Access-Control-Max-Age"),
+            Some("86400")
+        );
+    }
+
+    // ── Integration: POST /files ─────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_post_file_creates_file() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let body = b"hello file content";
+        let req = format!(
+            "POST /files/newfile.txt HTTP/1.1\r\n\
+             Host: test\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Length: {}\r\n\
+             \r\n\
+             {}",
+            body.len(),
+            std::str::from_utf8(body).unwrap(),
+        );
+        let resp = raw_roundtrip(addr, req.as_bytes()).await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(
+            text.starts_with("HTTP/1.1 201 Created\r\n"),
+            "expected 201, got: {}",
+            text
+        );
+
+        // Verify the file was actually created with correct content
+        let written = std::fs::read(tmp.path().join("newfile.txt")).unwrap();
+        assert_eq!(written, body);
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_file_overwrites_existing() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("existing.txt"), b"old content").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let body = b"new content";
+        let req = format!(
+            "POST /files/existing.txt HTTP/1.1\r\n\
+             Host: test\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Length: {}\r\n\
+             \r\n\
+             {}",
+            body.len(),
+            std::str::from_utf8(body).unwrap(),
+        );
+        let resp = raw_roundtrip(addr, req.as_bytes()).await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+
+        let written = std::fs::read(tmp.path().join("existing.txt")).unwrap();
+        assert_eq!(written, body);
+    }
+
+    #[tokio::test]
+    async fn test_integration_conditional_writes() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("doc.txt"), "v1").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        client
+            .write_all(b"GET /files/doc.txt HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let resp = read_one_response(&mut client).await;
+        let etag = response_header(&resp, "ETag").unwrap().to_string();
+
+        // Matching If-Match: the write goes through
+        let put = |etag: &str, body: &str| {
+            format!(
+                "PUT /files/doc.txt HTTP/1.1\r\nHost: test\r\nIf-Match: {}\r\n\
+                 Content-Length: {}\r\n\r\n{}",
+                etag,
+                body.len(),
+                body
+            )
+        };
+        client.write_all(put(&etag, "v2").as_bytes()).await.unwrap();
+        let resp = read_one_response(&mut client).await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+
+        // The same tag is now stale
+        client.write_all(put(&etag, "v3").as_bytes()).await.unwrap();
+        let resp = read_one_response(&mut client).await;
+        assert!(
+            resp.starts_with("HTTP/1.1 412 Precondition Failed\r\n"),
+            "{}",
+            resp
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("doc.txt")).unwrap(),
+            "v2"
+        );
+
+        // If-None-Match: * creates once, then refuses to overwrite
+        let create = b"POST /files/new.txt HTTP/1.1\r\nHost: test\r\nIf-None-Match: *\r\n\
+                       Content-Length: 1\r\n\r\nx";
+        client.write_all(create).await.unwrap();
+        let resp = read_one_response(&mut client).await;
+        assert!(resp.starts_with("HTTP/1.1 201 Created\r\n"), "{}", resp);
+        client.write_all(create).await.unwrap();
+        let resp = read_one_response(&mut client).await;
+        assert!(
+            resp.starts_with("HTTP/1.1 412 Precondition Failed\r\n"),
+            "{}",
+            resp
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_content_type_is_served_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            ..ServerConfig::default()
+        };
+        let responses = exchange_with(
+            config,
+            default_router(),
+            b"POST /files/page HTTP/1.1\r\nHost: test\r\nContent-Type: text/html\r\n\
+              Content-Length: 9\r\n\r\n<p>hi</p>\
+              GET /files/page HTTP/1.1\r\nHost: test\r\n\r\n\
+              PUT /files/page HTTP/1.1\r\nHost: test\r\nContent-Length: 2\r\n\r\nhi\
+              GET /files/page HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(responses.len(), 4);
+        assert!(responses[0].0.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert_eq!(
+            response_header(&responses[1].0, "Content-Type"),
+            Some("text/html")
+        );
+        assert_eq!(responses[1].1, b"<p>hi</p>");
+        // Uploaded HTML is never rendered in the site's origin
+        assert_eq!(
+            response_header(&responses[1].0, "Content-Disposition"),
+            Some("attachment")
+        );
+        assert_eq!(
+            response_header(&responses[1].0, "X-Content-Type-Options"),
+            Some("nosniff")
+        );
+        // Rewriting without a type falls back to the extension's
+        assert_eq!(
+            response_header(&responses[3].0, "Content-Type"),
+            Some("application/octet-stream")
+        );
+        assert_eq!(
+            response_header(&responses[3].0, "Content-Disposition"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_uploaded_svg_is_an_attachment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            ..ServerConfig::default()
+        };
+        let responses = exchange_with(
+            config,
+            default_router(),
+            b"PUT /files/logo.svg HTTP/1.1\r\nHost: test\r\nContent-Length: 6\r\n\r\n<svg/>\
+              GET /files/logo.svg HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            response_header(&responses[1].0, "Content-Type"),
+            Some("image/svg+xml")
+        );
+        assert_eq!(
+            response_header(&responses[1].0, "Content-Disposition"),
+            Some("attachment")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_integration_uploaded_type_found_through_any_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("real")).unwrap();
+        std::os::unix::fs::symlink(tmp.path().join("real"), tmp.path().join("alias")).unwrap();
+        let config = ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            ..ServerConfig::default()
+        };
+        let responses = exchange_with(
+            config,
+            default_router(),
+            b"PUT /files/alias/pic HTTP/1.1\r\nHost: test\r\nContent-Type: image/png\r\n\
+              Content-Length: 2\r\n\r\nhi\
+              GET /files/real/pic HTTP/1.1\r\nHost: test\r\n\r\n\
+              DELETE /files/real/pic HTTP/1.1\r\nHost: test\r\n\r\n\
+              PUT /files/alias/pic HTTP/1.1\r\nHost: test\r\nContent-Length: 2\r\n\r\nhi\
+              GET /files/alias/pic HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(responses.len(), 5);
+        assert_eq!(
+            response_header(&responses[1].0, "Content-Type"),
+            Some("image/png")
+        );
+        assert!(
+            responses[2].0.starts_with("HTTP/1.1 204 "),
+            "{}",
+            responses[2].0
+        );
+        assert_eq!(
+            response_header(&responses[4].0, "Content-Type"),
+            Some("application/octet-stream")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_file_create_then_overwrite_statuses() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buf = vec![0u8; 4096];
+        let req = b"POST /files/report.txt HTTP/1.1\r\nHost: test\r\nContent-Length: 2\r\n\r\nhi";
+
+        // First write creates the file
+        client.write_all(req).await.unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        let resp1 = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(
+            resp1.starts_with("HTTP/1.1 201 Created\r\n"),
+            "resp1: {}",
+            resp1
+        );
+        assert!(resp1.contains("Location: /files/report.txt\r\n"));
+        assert!(resp1.contains("Content-Length: 0\r\n"));
+        assert!(resp1.ends_with("\r\n\r\n"));
+
+        // Second write overwrites it
+        client.write_all(req).await.unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        let resp2 = String::from_utf8_lossy(&buf[..n]);
+        assert!(resp2.starts_with("HTTP/1.1 200 OK\r\n"), "resp2: {}", resp2);
+        assert!(resp2.contains("Content-Length: 0\r\n"));
+        assert!(!resp2.contains("Location:"));
+
+        client.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_file_location_is_encoded() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let req = b"POST /files/my%20notes.txt HTTP/1.1\r\n\
+                     Host: test\r\n\
+                     Content-Length: 0\r\n\
+                     \r\n";
+        let resp = raw_roundtrip(addr, req).await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.contains("Location: /files/my%20notes.txt\r\n"));
+        assert!(tmp.path().join("my notes.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_integration_put_file_matches_post() {
+        let tmp = tempfile::tempdir().unwrap();
+        let body: Vec<u8> = vec![0x00, 0xFF, b'\r', b'\n', 0x7F, b'x'];
+
+        for (method, name) in [("POST", "via-post.bin"), ("PUT", "via-put.bin")] {
+            let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+            let mut req = format!(
+                "{} /files/{} HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n",
+                method,
+                name,
+                body.len()
+            )
+            .into_bytes();
+            req.extend_from_slice(&body);
+            let resp = raw_roundtrip(addr, &req).await;
+            let text = String::from_utf8(resp).unwrap();
+
+            assert!(
+                text.starts_with("HTTP/1.1 201 Created\r\n"),
+                "{}: {}",
+                method,
+                text
+            );
+            assert!(text.contains(&format!("Location: /files/{}\r\n", name)));
+        }
+
+        let posted = std::fs::read(tmp.path().join("via-post.bin")).unwrap();
+        let put = std::fs::read(tmp.path().join("via-put.bin")).unwrap();
+        assert_eq!(put, body);
+        assert_eq!(put, posted);
+    }
+
+    #[tokio::test]
+    async fn test_integration_put_file_overwrite_returns_200() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("doc.txt"), "old").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"PUT /files/doc.txt HTTP/1.1\r\nHost: test\r\nContent-Length: 3\r\n\r\nnew",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(std::fs::read(tmp.path().join("doc.txt")).unwrap(), b"new");
+    }
+
+    #[tokio::test]
+    async fn test_integration_put_file_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("root")).unwrap();
+
+        let addr = spawn_test_server(tmp.path().join("root")).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"PUT /files/../evil.txt HTTP/1.1\r\nHost: test\r\nContent-Length: 4\r\n\r\nevil",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(!tmp.path().join("evil.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_file_empty_body() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let req = b"POST /files/empty.txt HTTP/1.1\r\n\
+                     Host: test\r\n\
+                     Content-Type: application/octet-stream\r\n\
+                     Content-Length: 0\r\n\
+                     \r\n";
+        let resp = raw_roundtrip(addr, req).await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 201 Created\r\n"));
+
+        let written = std::fs::read(tmp.path().join("empty.txt")).unwrap();
+        assert!(written.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_integration_post_file_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let req = b"POST /files/../evil.txt HTTP/1.1\r\n\
+                     Host: test\r\n\
+                     Content-Type: application/octet-stream\r\n\
+                     Content-Length: 4\r\n\
+                     \r\n\
+                     evil";
+        let resp = raw_roundtrip(addr, req).await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    // ── Integration: DELETE /files ──────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_delete_file_then_get_404() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("doomed.txt"), "bye").unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        client
+            .write_all(
+                b"DELETE /files/doomed.txt HTTP/1.1\r\nHost: test\r\nX-Request-Id: del-1\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).await.unwrap();
+        let resp1 = String::from_utf8_lossy(&buf[..n]);
+        assert_eq!(
+            resp1,
+            format!(
+                "HTTP/1.1 204 No Content\r\nX-Request-Id: del-1\r\nConnection: keep-alive\r\n\
+                 Server: {}\r\n\r\n",
+                crate::response::DEFAULT_SERVER_NAME
+            )
+        );
+        assert!(!tmp.path().join("doomed.txt").exists());
+
+        client
+            .write_all(b"GET /files/doomed.txt HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        let resp2 = String::from_utf8_lossy(&rest);
+        assert!(
+            resp2.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "resp2: {}",
+            resp2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_delete_missing_file_returns_404() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"DELETE /files/ghost.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_delete_directory_returns_409() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("subdir")).unwrap();
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp =
+            raw_roundtrip(addr, b"DELETE /files/subdir HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 409 Conflict\r\n"));
+        assert!(tmp.path().join("subdir").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_integration_delete_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("root")).unwrap();
+        std::fs::write(tmp.path().join("keep.txt"), "keep").unwrap();
+
+        let addr = spawn_test_server(tmp.path().join("root")).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"DELETE /files/../keep.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(tmp.path().join("keep.txt").exists());
+    }
+
+    // ── Integration: PATCH /files ────────────────────────────────────
+
+    async fn patch(files_dir: &Path, target: &str, headers: &str, body: &str) -> String {
+        let addr = spawn_test_server(files_dir.to_path_buf()).await;
+        let req = format!(
+            "PATCH {} HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n{}\r\n{}",
+            target,
+            body.len(),
+            headers,
+            body
+        );
+        String::from_utf8(raw_roundtrip(addr, req.as_bytes()).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_appends() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("log.txt"), "one\n").unwrap();
+
+        let text = patch(tmp.path(), "/files/log.txt", "", "two\n").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        let text = patch(tmp.path(), "/files/log.txt", "", "three\n").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("log.txt")).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_missing_file_404() {
+        let tmp = tempfile::tempdir().unwrap();
+        let text = patch(tmp.path(), "/files/ghost.txt", "", "boo").await;
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", text);
+        assert!(!tmp.path().join("ghost.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_directory_409() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("subdir")).unwrap();
+        let text = patch(tmp.path(), "/files/subdir", "", "x").await;
+        assert!(text.starts_with("HTTP/1.1 409 Conflict\r\n"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_traversal_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("root")).unwrap();
+        std::fs::write(tmp.path().join("keep.txt"), "keep").unwrap();
+        let text = patch(&tmp.path().join("root"), "/files/../keep.txt", "", "x").await;
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", text);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("keep.txt")).unwrap(),
+            "keep"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_content_range() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("data.txt");
+        std::fs::write(&path, "hello world").unwrap();
+
+        let text = patch(
+            tmp.path(),
+            "/files/data.txt",
+            "Content-Range: bytes 6-10/*\r\n",
+            "there",
+        )
+        .await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello there");
+
+        // Starting right at the end extends the file
+        let text = patch(
+            tmp.path(),
+            "/files/data.txt",
+            "Content-Range: bytes 11-11/12\r\n",
+            "!",
+        )
+        .await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello there!");
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_bad_content_range() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("data.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        // Past the end would leave a hole
+        let text = patch(
+            tmp.path(),
+            "/files/data.txt",
+            "Content-Range: bytes 9-9/*\r\n",
+            "x",
+        )
+        .await;
+        assert!(
+            text.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"),
+            "{}",
+            text
+        );
+        assert_eq!(response_header(&text, "Content-Range"), Some("bytes */5"));
+
+        // The range must match the body
+        for header in ["bytes 0-9/*", "lines 0-0/*"] {
+            let headers = format!("Content-Range: {}\r\n", header);
+            let text = patch(tmp.path(), "/files/data.txt", &headers, "x").await;
+            assert!(text.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", text);
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn test_integration_patch_honours_if_match() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "a").unwrap();
+        let text = patch(tmp.path(), "/files/a.txt", "If-Match: \"stale\"\r\n", "b").await;
+        assert!(
+            text.starts_with("HTTP/1.1 412 Precondition Failed\r\n"),
+            "{}",
+            text
+        );
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("a.txt")).unwrap(),
+            "a"
+        );
+    }
+
+    // ── Integration: read timeout ────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_read_timeout_idle_client_gets_408() {
+        let addr = spawn_test_server_with(ServerConfig {
+            read_timeout: std::time::Duration::from_millis(100),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let start = std::time::Instant::now();
+
+        // Send nothing; the server should answer 408 and close
+        let mut buf = Vec::new();
+        time::timeout(
+            std::time::Duration::from_secs(5),
+            client.read_to_end(&mut buf),
+        )
+        .await
+        .expect("server should close the idle connection")
+        .unwrap();
+
+        let resp = String::from_utf8_lossy(&buf);
+        assert!(
+            resp.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+            "resp: {}",
+            resp
+        );
+        assert!(resp.contains("Connection: close\r\n"));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout_resets_between_requests() {
+        let addr = spawn_test_server_with(ServerConfig {
+            read_timeout: std::time::Duration::from_millis(300),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buf = vec![0u8; 4096];
+
+        // Each request arrives well inside its own window, though together
+        // they take longer than a single timeout
+        for word in ["one", "two", "three"] {
+            time::sleep(std::time::Duration::from_millis(150)).await;
+            let req = format!("GET /echo/{} HTTP/1.1\r\nHost: test\r\n\r\n", word);
+            client.write_all(req.as_bytes()).await.unwrap();
+            let n = client.read(&mut buf).await.unwrap();
+            let resp = String::from_utf8_lossy(&buf[..n]);
+            assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {}", resp);
+            assert!(resp.ends_with(word));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_closes_without_response() {
+        let addr = spawn_test_server_with(ServerConfig {
+            idle_timeout: std::time::Duration::from_millis(100),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /echo/once HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let first = read_one_response(&mut client).await;
+        assert!(first.contains("Connection: keep-alive\r\n"), "{}", first);
+
+        // Send nothing more: the server hangs up without a 408
+        let mut rest = Vec::new();
+        time::timeout(
+            std::time::Duration::from_secs(5),
+            client.read_to_end(&mut rest),
+        )
+        .await
+        .expect("server should close the idle connection")
+        .unwrap();
+        assert!(rest.is_empty(), "{}", String::from_utf8_lossy(&rest));
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_does_not_apply_to_first_request() {
+        // The first request is bounded by the read timeout alone
+        let addr = spawn_test_server_with(ServerConfig {
+            idle_timeout: std::time::Duration::from_millis(50),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        time::sleep(std::time::Duration::from_millis(150)).await;
+        client
+            .write_all(b"GET /echo/late HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let resp = read_one_response(&mut client).await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    }
+
+    // ── Integration: Host header ─────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_http11_without_host_is_400() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", resp);
+        assert_eq!(response_header(&resp, "Connection"), Some("close"));
+    }
+
+    #[tokio::test]
+    async fn test_http11_with_host_passes() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_http10_without_host_passes() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.0\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_missing_host_allowed_in_lenient_mode() {
+        let addr = spawn_test_server_with(ServerConfig {
+            require_host: false,
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_lowercase_method_is_501_when_strict() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"get /echo/hi HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 501 "), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_lowercase_method_routed_in_lenient_mode() {
+        let addr = spawn_test_server_with(ServerConfig {
+            limits: crate::request::RequestLimits {
+                case_insensitive_methods: true,
+                ..Default::default()
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"gEt /echo/hi HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\nhi"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_lowercase_options_star_in_lenient_mode() {
+        let addr = spawn_test_server_with(ServerConfig {
+            limits: crate::request::RequestLimits {
+                case_insensitive_methods: true,
+                ..Default::default()
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"options * HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 204 No Content\r\n"), "{}", resp);
+        assert!(response_header(&resp, "Allow").is_some(), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_lowercase_connect_in_lenient_mode() {
+        let addr = spawn_test_server_with(ServerConfig {
+            limits: crate::request::RequestLimits {
+                case_insensitive_methods: true,
+                ..Default::default()
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(
+            addr,
+            b"connect example.com:443 HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        )
+        .await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(
+            resp.starts_with("HTTP/1.1 501 Not Implemented\r\n"),
+            "{}",
+            resp
+        );
+    }
+
+    // ── Integration: header validation ───────────────────────────────
+
+    #[tokio::test]
+    async fn test_invalid_headers_are_400() {
+        for raw in [
+            &b"GET /echo/hi HTTP/1.1\r\nHost: x\r\nBad Name: y\r\n\r\n"[..],
+            b"GET /echo/hi HTTP/1.1\r\nHost: x\r\nX-A: y\rSet-Cookie: z\r\n\r\n",
+        ] {
+            let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+            let resp = String::from_utf8(raw_roundtrip(addr, raw).await).unwrap();
+            assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", resp);
+            assert!(!resp.contains("Set-Cookie"));
+        }
+    }
+
+    // ── Integration: request limits ──────────────────────────────────
+
+    #[tokio::test]
+    async fn test_too_many_headers_gets_431() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+
+        let mut raw = b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n".to_vec();
+        for i in 0..150 {
+            raw.extend_from_slice(format!("X-Filler-{}: x\r\n", i).as_bytes());
+        }
+        raw.extend_from_slice(b"\r\n");
+
+        let resp = String::from_utf8_lossy(&raw_roundtrip(addr, &raw).await).into_owned();
+        assert!(
+            resp.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "resp: {}",
+            resp
+        );
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_enormous_header_value_gets_431() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+
+        let raw = format!(
+            "GET /echo/hi HTTP/1.1\r\nHost: test\r\nX-Big: {}\r\n\r\n",
+            "a".repeat(64 * 1024)
+        );
+
+        let resp = String::from_utf8_lossy(&raw_roundtrip(addr, raw.as_bytes()).await).into_owned();
+        assert!(
+            resp.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "resp: {}",
+            resp
+        );
+    }
+
+    #[tokio::test]
+    async fn test_configured_header_limit_applies() {
+        let addr = spawn_test_server_with(ServerConfig {
+            limits: crate::request::RequestLimits {
+                max_headers: 1,
+                ..Default::default()
+            },
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let raw = b"GET /echo/hi HTTP/1.1\r\nHost: test\r\nAccept: */*\r\n\r\n";
+        let resp = String::from_utf8_lossy(&raw_roundtrip(addr, raw).await).into_owned();
+        assert!(resp.starts_with("HTTP/1.1 431 "), "resp: {}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_long_request_line_gets_414() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+
+        let raw = format!(
+            "GET /echo/{} HTTP/1.1\r\nHost: test\r\n\r\n",
+            "a".repeat(256 * 1024)
+        );
+
+        let resp = String::from_utf8_lossy(&raw_roundtrip(addr, raw.as_bytes()).await).into_owned();
+        assert!(
+            resp.starts_with("HTTP/1.1 414 URI Too Long\r\n"),
+            "resp: {}",
+            resp
+        );
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
+    // ── route / RouteOutcome ─────────────────────────────────────────
+
+    /// Run [`route`] for a GET of `target` over a real socket, returning the
+    /// outcome and the raw bytes the client received.
+    async fn route_get(files_dir: &Path, target: &str) -> (RouteOutcome, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let config = ServerConfig {
+            directory: files_dir.to_path_buf(),
+            ..ServerConfig::default()
+        };
+        let request = Request {
+            method: "GET".to_string(),
+            path: target.to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: vec![("Host".to_string(), "localhost".to_string())],
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        };
+        let outcome = route(
+            &request,
+            false,
+            &config,
+            &default_router(),
+            &mut server,
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap();
+        drop(server);
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        (outcome, buf)
+    }
+
+    #[tokio::test]
+    async fn test_route_outcome_echo() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (outcome, raw) = route_get(tmp.path(), "/echo/hello").await;
+        assert_eq!(
+            outcome,
+            RouteOutcome {
+                status: 200,
+                bytes_written: 5,
+                keep_alive: false,
+            }
+        );
+        assert!(raw.ends_with(b"\r\n\r\nhello"));
+    }
+
+    #[tokio::test]
+    async fn test_route_outcome_not_found() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (outcome, raw) = route_get(tmp.path(), "/nope").await;
+        assert_eq!(outcome.status, 404);
+        assert_eq!(outcome.bytes_written, "Not Found".len());
+        assert!(raw.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_route_outcome_streamed_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let content = vec![b'z'; 200_000];
+        std::fs::write(tmp.path().join("big.bin"), &content).unwrap();
+
+        let (outcome, raw) = route_get(tmp.path(), "/files/big.bin").await;
+        assert_eq!(
+            outcome,
+            RouteOutcome {
+                status: 200,
+                bytes_written: content.len(),
+                keep_alive: false,
+            }
+        );
+        assert!(raw.ends_with(&content));
+    }
+
+    #[tokio::test]
+    async fn test_route_handler_error_is_generic_500() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let mut router = Router::new();
+        router.route("GET", "/boom", |_, _, _| {
+            Box::pin(async { Err(anyhow::anyhow!("secret internal detail")) })
+        });
+        let request = Request {
+            method: "GET".to_string(),
+            path: "/boom".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: Vec::new(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        };
+        let outcome = route(
+            &request,
+            true,
+            &ServerConfig::default(),
+            &router,
+            &mut server,
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome.status, 500);
+        assert!(!outcome.keep_alive);
+        drop(server);
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let raw = String::from_utf8(buf).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        assert!(raw.contains("Connection: close\r\n"));
+        assert!(raw.ends_with("\r\n\r\nInternal Server Error"));
+        assert!(!raw.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_failed_file_write_gets_500() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Writing a file over a directory fails, even for root
+        std::fs::create_dir(tmp.path().join("taken")).unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"POST /files/taken HTTP/1.1\r\nHost: test\r\nContent-Length: 2\r\n\r\nhi")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(
+            resp.starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+            "{}",
+            resp
+        );
+        assert!(resp.contains("Connection: close\r\n"));
+        assert!(!resp.contains("writing file"), "{}", resp);
+    }
+
+    // ── Integration: error pages ─────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_custom_404_page() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut error_pages = ErrorPages::default();
+        error_pages
+            .insert(404, ErrorPage::new("text/html", "<h1>Nothing here</h1>"))
+            .unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            error_pages,
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let resp = get_close(addr, "/files/missing.txt").await;
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", resp);
+        assert_eq!(response_header(&resp, "Content-Type"), Some("text/html"));
+        assert_eq!(response_header(&resp, "Content-Length"), Some("21"));
+        assert!(resp.ends_with("\r\n\r\n<h1>Nothing here</h1>"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_custom_page_for_rejected_request() {
+        let mut error_pages = ErrorPages::default();
+        error_pages
+            .insert(505, ErrorPage::new("text/plain", "HTTP/1.x only"))
+            .unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            error_pages,
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let resp = raw_roundtrip(addr, b"GET / HTTP/2.0\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 505 "), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\nHTTP/1.x only"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_default_404_body_without_error_pages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = get_close(addr, "/files/missing.txt").await;
+        assert_eq!(response_header(&resp, "Content-Type"), Some("text/plain"));
+        assert!(resp.ends_with("\r\n\r\nNot Found"));
+    }
+
+    // ── Integration: metrics ─────────────────────────────────────────
+
+    async fn get_close(addr: std::net::SocketAddr, target: &str) -> String {
+        let raw = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            target
+        );
+        String::from_utf8(raw_roundtrip(addr, raw.as_bytes()).await).unwrap()
+    }
+
+    /// The value of an unlabelled or labelled sample in a `/metrics` body.
+    fn metric_value(body: &str, name: &str) -> u64 {
+        body.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("metric {} missing from:\n{}", name, body))
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_counts_requests() {
+        let tmp = tempfile::tempdir().unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            metrics: true,
+            ..ServerConfig::default()
+        })
+        .await;
+
+        get_close(addr, "/echo/abc").await;
+        get_close(addr, "/echo/defgh").await;
+        get_close(addr, "/missing").await;
+
+        let resp = get_close(addr, "/metrics").await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            response_header(&resp, "Content-Type"),
+            Some("text/plain; version=0.0.4")
+        );
+        let body = resp.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(metric_value(body, "http_requests_total"), 3);
+        assert_eq!(metric_value(body, "http_responses_total{class=\"2xx\"}"), 2);
+        assert_eq!(metric_value(body, "http_responses_total{class=\"4xx\"}"), 1);
+        assert_eq!(
+            metric_value(body, "http_response_bytes_total"),
+            (3 + 5 + "Not Found".len()) as u64
+        );
+        // The scraping connection itself is open
+        assert_eq!(metric_value(body, "http_active_connections"), 1);
+
+        // The scrape is counted once it has been answered
+        let body = get_close(addr, "/metrics").await;
+        assert!(body.contains("\nhttp_requests_total 4\n"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_counts_rejected_requests() {
+        let addr = spawn_test_server_with(ServerConfig {
+            metrics: true,
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let resp = raw_roundtrip(addr, b"GET / HTTP/2.0\r\n\r\n").await;
+        assert!(resp.starts_with(b"HTTP/1.1 505 "));
+
+        let body = get_close(addr, "/metrics").await;
+        assert!(body.contains("\nhttp_requests_total 1\n"), "{}", body);
+        assert!(
+            body.contains("\nhttp_responses_total{class=\"5xx\"} 1\n"),
+            "{}",
+            body
+        );
+    }
+
+    #[tokio::test]
+    async fn test_metrics_route_disabled_by_default() {
+        let tmp = tempfile::tempdir().unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = get_close(addr, "/metrics").await;
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", resp);
+    }
+
+    // ── Integration: access log ──────────────────────────────────────
+
+    /// Collects every `access` log line; installed once as the global logger.
+    struct AccessLogCapture;
+
+    static ACCESS_LINES: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for AccessLogCapture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "access"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                ACCESS_LINES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_access_log() {
+        static INSTALL: std::sync::Once = std::sync::Once::new();
+        INSTALL.call_once(|| {
+            log::set_boxed_logger(Box::new(AccessLogCapture)).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+    }
+
+    /// Wait briefly for the access line mentioning `needle` to be logged.
+    async fn access_line_containing(needle: &str) -> String {
+        for _ in 0..50 {
+            if let Some(line) = ACCESS_LINES
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|l| l.contains(needle))
+            {
+                return line.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no access log line containing {}", needle);
+    }
+
+    #[tokio::test]
+    async fn test_access_log_common_log_format() {
+        capture_access_log();
+        let tmp = tempfile::tempdir().unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+
+        raw_roundtrip(
+            addr,
+            b"GET /echo/access-log-clf?x=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+
+        let line = access_line_containing("/echo/access-log-clf").await;
+        let (prefix, rest) = line.split_once(" [").unwrap();
+        assert_eq!(prefix, "127.0.0.1 - -");
+        let (date, rest) = rest.split_once("] ").unwrap();
+        assert_eq!(date.len(), "10/Oct/2000:13:55:36 +0000".len(), "{}", date);
+        assert!(date.ends_with(" +0000"), "{}", date);
+        assert_eq!(rest, "\"GET /echo/access-log-clf?x=1 HTTP/1.1\" 200 14");
+    }
+
+    #[tokio::test]
+    async fn test_access_log_reports_status_and_file_bytes() {
+        capture_access_log();
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("access-log.txt"), vec![b'x'; 1000]).unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+
+        raw_roundtrip(
+            addr,
+            b"GET /files/access-log.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let line = access_line_containing("/files/access-log.txt").await;
+        assert!(line.ends_with("\" 200 1000"), "{}", line);
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        raw_roundtrip(
+            addr,
+            b"GET /access-log-missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let line = access_line_containing("/access-log-missing").await;
+        assert!(line.ends_with("\" 404 9"), "{}", line);
+
+        // A bodiless response logs its size as `-`
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        raw_roundtrip(
+            addr,
+            b"DELETE /files/access-log.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        let line = access_line_containing("\"DELETE /files/access-log.txt").await;
+        assert!(line.ends_with("\" 204 -"), "{}", line);
+    }
+
+    // ── Integration: trailing slashes ────────────────────────────────
+
+    async fn get_with_trailing_slash(mode: TrailingSlash, target: &str) -> String {
+        let addr = spawn_test_server_with(ServerConfig {
+            trailing_slash: mode,
+            ..ServerConfig::default()
+        })
+        .await;
+        let raw = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", target);
+        String::from_utf8(raw_roundtrip(addr, raw.as_bytes()).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_integration_trailing_slash_strip_redirects() {
+        let resp = get_with_trailing_slash(TrailingSlash::Strip, "/events/?since=1").await;
+        assert!(
+            resp.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+            "{}",
+            resp
+        );
+        assert_eq!(response_header(&resp, "Location"), Some("/events?since=1"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_trailing_slash_append_redirects() {
+        let resp = get_with_trailing_slash(TrailingSlash::Append, "/files").await;
+        assert!(
+            resp.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+            "{}",
+            resp
+        );
+        assert_eq!(response_header(&resp, "Location"), Some("/files/"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_trailing_slash_leaves_routed_paths() {
+        // `/echo/*` matches the slash too, so stripping doesn't apply
+        let resp = get_with_trailing_slash(TrailingSlash::Strip, "/echo/abc/").await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\nabc/"), "{}", resp);
+        // Neither form of an unknown path is routed
+        let resp = get_with_trailing_slash(TrailingSlash::Strip, "/nowhere/").await;
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_integration_trailing_slash_off_by_default() {
+        let resp = get_with_trailing_slash(TrailingSlash::default(), "/events/").await;
+        assert!(resp.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", resp);
+        assert_eq!(response_header(&resp, "Location"), None);
+    }
+
+    // ── Integration: malformed requests ──────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_absolute_form_target_routes_on_path() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET http://localhost/echo/proxied HTTP/1.1\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "got: {}", text);
+        assert!(text.ends_with("\r\n\r\nproxied"), "got: {}", text);
+    }
+
+    #[tokio::test]
+    async fn test_integration_invalid_utf8_path_is_400() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/\xff\xfe HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(
+            text.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "got: {}",
+            text
+        );
+        assert_eq!(response_header(&text, "Connection"), Some("close"));
+    }
+
+    // ── Integration: request IDs ─────────────────────────────────────
+
+    fn request_id_of(resp: &str) -> Vec<&str> {
+        resp.lines()
+            .filter_map(|l| l.strip_prefix("X-Request-Id: "))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_integration_response_carries_request_id() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..2 {
+            client
+                .write_all(b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n")
+                .await
+                .unwrap();
+            let resp = read_one_response(&mut client).await;
+            let id = request_id_of(&resp);
+            assert_eq!(id.len(), 1, "{}", resp);
+            ids.push(id[0].to_string());
+        }
+        // Each request on the connection gets its own ID
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[tokio::test]
+    async fn test_integration_client_request_id_preserved() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /no-such-route HTTP/1.1\r\nHost: test\r\nX-Request-Id: trace-42\r\n\r\n",
+        )
+        .await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 404"), "{}", resp);
+        assert_eq!(request_id_of(&resp), vec!["trace-42"]);
+    }
+
+    #[tokio::test]
+    async fn test_integration_rejected_request_gets_request_id() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        // HTTP/1.1 without a Host header is rejected before routing
+        let resp = raw_roundtrip(addr, b"GET / HTTP/1.1\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400"), "{}", resp);
+        assert_eq!(request_id_of(&resp).len(), 1, "{}", resp);
+    }
+
+    // ── Integration: default charset ─────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_default_charset_on_text_only() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        let config = || ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            default_charset: Some("utf-8".to_string()),
+            ..ServerConfig::default()
+        };
+
+        let addr = spawn_test_server_with(config()).await;
+        let text = String::from_utf8(
+            raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await,
+        )
+        .unwrap();
+        assert_eq!(
+            response_header(&text, "Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+
+        let addr = spawn_test_server_with(config()).await;
+        let resp = raw_roundtrip(addr, b"GET /files/logo.png HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8_lossy(&resp);
+        assert_eq!(response_header(&text, "Content-Type"), Some("image/png"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_no_charset_by_default() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let text = String::from_utf8(
+            raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await,
+        )
+        .unwrap();
+        assert_eq!(response_header(&text, "Content-Type"), Some("text/plain"));
+    }
+
+    // ── Integration: response time ───────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_response_time_header_when_enabled() {
+        let addr = spawn_test_server_with(ServerConfig {
+            response_time_header: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        let value = resp
+            .lines()
+            .find_map(|l| l.strip_prefix("X-Response-Time: "))
+            .unwrap_or_else(|| panic!("no X-Response-Time in {}", resp));
+        let ms: f64 = value.strip_suffix("ms").unwrap().parse().unwrap();
+        assert!(ms >= 0.0, "{}", value);
+    }
+
+    #[tokio::test]
+    async fn test_integration_no_response_time_header_by_default() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(!resp.contains("X-Response-Time"), "{}", resp);
+    }
+
+    // ── Integration: serving from the root ───────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_serve_root() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("style.css"), "body {}").unwrap();
+        std::fs::write(tmp.path().join("index.html"), "<h1>home</h1>").unwrap();
+        std::fs::create_dir(tmp.path().join("echo")).unwrap();
+        std::fs::write(tmp.path().join("echo").join("hi"), "shadowed").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            serve_root: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let get = async |path: &str| {
+            let raw = format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path);
+            String::from_utf8(raw_roundtrip(addr, raw.as_bytes()).await).unwrap()
+        };
+
+        let text = get("/style.css").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert_eq!(response_header(&text, "Content-Type"), Some("text/css"));
+        assert!(text.ends_with("\r\n\r\nbody {}"), "{}", text);
+
+        let text = get("/").await;
+        assert!(text.ends_with("\r\n\r\n<h1>home</h1>"), "{}", text);
+        std::fs::remove_file(tmp.path().join("index.html")).unwrap();
+        let text = get("/").await;
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert_eq!(response_header(&text, "Content-Length"), Some("0"));
+
+        // Built-in routes still win, and /files/ is unchanged
+        let text = get("/echo/hi").await;
+        assert!(text.ends_with("\r\n\r\nhi"), "{}", text);
+        let text = get("/files/style.css").await;
+        assert!(text.ends_with("\r\n\r\nbody {}"), "{}", text);
+
+        let text = get("/missing.css").await;
+        assert!(text.starts_with("HTTP/1.1 404 "), "{}", text);
+        let text = get("/../etc/passwd").await;
+        assert!(text.starts_with("HTTP/1.1 404 "), "{}", text);
+
+        // Read-only: writes are only accepted under /files/
+        let resp = raw_roundtrip(
+            addr,
+            b"PUT /style.css HTTP/1.1\r\nHost: test\r\nContent-Length: 1\r\n\r\nx",
+        )
+        .await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 405 "), "{}", text);
+        assert_eq!(response_header(&text, "Allow"), Some("GET, HEAD, OPTIONS"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_root_not_served_by_default() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("style.css"), "body {}").unwrap();
+        std::fs::write(tmp.path().join("index.html"), "<h1>home</h1>").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET /style.css HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 404 "), "{}", text);
+
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let resp = raw_roundtrip(addr, b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let text = String::from_utf8(resp).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert!(!text.contains("home"), "{}", text);
+    }
+
+    // ── Integration: open file limit ─────────────────────────────────
+
+    #[tokio::test]
+    async fn test_open_files_sized_per_instance() {
+        let config = ServerConfig {
+            open_file_wait: Duration::from_millis(10),
+            ..ServerConfig::default()
+        };
+        let one = OpenFiles::new(Some(1));
+        let held = one.acquire(&config).await.unwrap();
+        assert!(held.is_some());
+        let busy = one.acquire(&config).await.unwrap_err();
+        assert_eq!(busy.status_code(), 503);
+
+        // Another instance keeps its own size, whatever was built first
+        let two = OpenFiles::new(Some(2));
+        let _a = two.acquire(&config).await.unwrap();
+        assert!(two.acquire(&config).await.unwrap().is_some());
+        assert!(
+            OpenFiles::new(None)
+                .acquire(&config)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        drop(held);
+        assert!(one.acquire(&config).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_integration_open_file_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        // Bigger than the socket buffers, so an unread download keeps its
+        // file open
+        let big_len = 32 * 1024 * 1024;
+        std::fs::write(tmp.path().join("big.bin"), vec![b'x'; big_len]).unwrap();
+        std::fs::write(tmp.path().join("small.txt"), "small").unwrap();
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            max_open_files: Some(1),
+            open_file_wait: Duration::from_millis(200),
+            ..ServerConfig::default()
+        })
+        .await;
+
+        let mut download = TcpStream::connect(addr).await.unwrap();
+        download
+            .write_all(b"GET /files/big.bin HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // Once the head arrives, the file is open and being streamed
+        let mut status = [0u8; 12];
+        download.read_exact(&mut status).await.unwrap();
+        assert_eq!(&status, b"HTTP/1.1 200");
+
+        let get_small = b"GET /files/small.txt HTTP/1.1\r\nHost: test\r\n\r\n";
+        let busy = String::from_utf8(raw_roundtrip(addr, get_small).await).unwrap();
+        assert!(
+            busy.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            busy
+        );
+        assert_eq!(response_header(&busy, "Retry-After"), Some("1"));
+
+        // A HEAD never opens the file, so the limit doesn't hold it up
+        let head = raw_roundtrip(
+            addr,
+            b"HEAD /files/small.txt HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        assert!(head.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        // Finishing the download frees the slot
+        let mut rest = Vec::new();
+        download.read_to_end(&mut rest).await.unwrap();
+        let body_at = rest.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(rest.len() - body_at, big_len);
+        let freed = String::from_utf8(raw_roundtrip(addr, get_small).await).unwrap();
+        assert!(freed.starts_with("HTTP/1.1 200 OK\r\n"), "{}", freed);
+        assert!(freed.ends_with("\r\n\r\nsmall"), "{}", freed);
+    }
+
+    // ── Integration: peer address ────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_peer_addr_header_when_enabled() {
+        let addr = spawn_test_server_with(ServerConfig {
+            peer_addr_header: true,
+            ..ServerConfig::default()
+        })
+        .await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let local = client.local_addr().unwrap();
+        client
+            .write_all(b"GET /echo/hi HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).await.unwrap();
+        assert_eq!(
+            response_header(&resp, "X-Peer-Addr"),
+            Some(local.to_string().as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_integration_no_peer_addr_header_by_default() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(!resp.contains("X-Peer-Addr"), "{}", resp);
+    }
+
+    // ── Integration: server-sent events ──────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_events_stream() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /events HTTP/1.1\r\nHost: test\r\nAccept-Encoding: gzip\r\n\r\n")
+            .await
+            .unwrap();
+
+        // Read until the head and two complete events have arrived
+        let mut received = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let text = loop {
+            let n = time::timeout(Duration::from_secs(5), client.read(&mut chunk))
+                .await
+                .expect("events should keep coming")
+                .unwrap();
+            assert!(n > 0, "stream ended early");
+            received.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&received).into_owned();
+            if text.matches("\n\n").count() >= 2 {
+                break text;
+            }
+        };
+
+        let (head, events) = text.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains("Content-Type: text/event-stream\r\n"));
+        assert!(head.contains("Cache-Control: no-cache\r\n"));
+        assert!(!head.contains("Content-Length"), "{}", head);
+        assert!(!head.contains("Content-Encoding"), "{}", head);
+
+        let frames: Vec<&str> = events.split("\n\n").take(2).collect();
+        for frame in frames {
+            let date = frame.strip_prefix("data: ").expect(frame);
+            assert!(!date.contains('\n'), "{:?}", frame);
+            assert!(httpdate::parse_http_date(date).is_ok(), "{:?}", date);
+        }
+    }
+
+    // ── Integration: truncated bodies ────────────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_truncated_body_gets_400() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"POST /files/t.txt HTTP/1.1\r\nHost: test\r\nContent-Length: 100\r\n\r\nonly a little")
+            .await
+            .unwrap();
+        // Stop sending, but keep reading
+        client.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8_lossy(&buf);
+        assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", resp);
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_integration_truncated_headers_get_400() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n").await;
+        let resp = String::from_utf8(resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", resp);
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
+    // ── Integration: keep-alive request limit ────────────────────────
+
+    #[tokio::test]
+    async fn test_integration_keepalive_request_limit_closes() {
+        let config = ServerConfig {
+            max_keepalive_requests: 2,
+            ..ServerConfig::default()
+        };
+        let responses = exchange_with(
+            config,
+            default_router(),
+            b"GET /echo/one HTTP/1.1\r\nHost: test\r\n\r\n\
+              GET /echo/two HTTP/1.1\r\nHost: test\r\n\r\n\
+              GET /echo/three HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        // The connection closes after the second response; the third
+        // request is never answered
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].0.contains("Connection: keep-alive\r\n"));
+        assert!(
+            responses[1].0.contains("Connection: close\r\n"),
+            "{}",
+            responses[1].0
+        );
+        assert_eq!(responses[1].1, b"two");
+    }
+
+    #[tokio::test]
+    async fn test_integration_status_only_framing_on_keep_alive() {
+        let mut router = default_router();
+        router
+            .route("GET", "/gone", |_, _, _| {
+                Box::pin(async { Ok(Response::status_only(404, "Not Found")) })
+            })
+            .route("GET", "/same", |_, _, _| {
+                Box::pin(async { Ok(Response::not_modified()) })
+            });
+        let responses = exchange_with(
+            ServerConfig::default(),
+            router,
+            b"GET /gone HTTP/1.1\r\nHost: test\r\n\r\n\
+              GET /same HTTP/1.1\r\nHost: test\r\n\r\n\
+              GET /echo/after HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(responses.len(), 3);
+        assert!(responses[0].0.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(
+            response_header(&responses[0].0, "Content-Length"),
+            Some("0")
+        );
+        assert!(responses[1].0.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(response_header(&responses[1].0, "Content-Length"), None);
+        assert_eq!(responses[2].1, b"after");
+    }
+
+    #[tokio::test]
+    async fn test_integration_stream_of_unknown_length_to_http10() {
+        let mut router = default_router();
+        router.route("GET", "/stream", |_, _, _| {
+            Box::pin(async {
+                let mut resp = Response::new(200, "OK");
+                resp.stream_from(std::io::Cursor::new(b"streamed".to_vec()), None);
+                Ok(resp)
+            })
+        });
+        let responses = exchange_with(
+            ServerConfig::default(),
+            router,
+            b"GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+              GET /echo/never HTTP/1.0\r\n\r\n",
+        )
+        .await;
+        // The body runs to the end of the connection, so nothing follows it
+        assert_eq!(responses.len(), 1);
+        let head = &responses[0].0;
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert_eq!(response_header(head, "Connection"), Some("close"));
+        assert_eq!(response_header(head, "Transfer-Encoding"), None);
+        assert_eq!(response_header(head, "Content-Length"), None);
+    }
+
+    #[tokio::test]
+    async fn test_integration_close_among_connection_tokens() {
+        // The second request is never read: close wins over keep-alive
+        let responses = exchange_with(
+            ServerConfig::default(),
+            default_router(),
+            b"GET /echo/one HTTP/1.1\r\nHost: test\r\nConnection: keep-alive, close\r\n\r\n\
+              GET /echo/two HTTP/1.1\r\nHost: test\r\n\r\n",
+        )
+        .await;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(
+            response_header(&responses[0].0, "Connection"),
+            Some("close")
+        );
+        assert_eq!(responses[0].1, b"one");
+    }
+
+    #[tokio::test]
+    async fn test_integration_transfer_encoding_closes_connection() {
+        // The chunk lines must never be read as a request of their own
+        let responses = exchange_with(
+            ServerConfig::default(),
+            default_router(),
+            b"POST /files/a HTTP/1.1\r\nHost: test\r\nTransfer-Encoding: chunked\r\n\r\n\
+              1a\r\nGET /echo/smuggled HTTP/1.1\r\n\r\n0\r\n\r\n",
         )
         .await;
-        let text = String::from_utf8(resp).unwrap();
-
-        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(responses.len(), 1);
+        assert!(
+            responses[0]
+                .0
+                .starts_with("HTTP/1.1 501 Not Implemented\r\n"),
+            "{}",
+            responses[0].0
+        );
+        assert_eq!(
+            response_header(&responses[0].0, "Connection"),
+            Some("close")
+        );
     }
 
+    // ── Integration: Expect: 100-continue ────────────────────────────
+
     #[tokio::test]
-    async fn test_integration_file_nested_path_rejected() {
+    async fn test_integration_oversized_expect_refused_before_body() {
         let tmp = tempfile::tempdir().unwrap();
-        // Create a nested file that should NOT be reachable
-        std::fs::create_dir_all(tmp.path().join("sub")).unwrap();
-        std::fs::write(tmp.path().join("sub/secret.txt"), "secret").unwrap();
-
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let resp = send_raw_request(
-            addr,
-            b"GET /files/sub/secret.txt HTTP/1.1\r\nHost: test\r\n\r\n",
-        )
+        let addr = spawn_test_server_with(ServerConfig {
+            directory: tmp.path().to_path_buf(),
+            limits: crate::request::RequestLimits {
+                max_body_size: 1024,
+                ..Default::default()
+            },
+            ..ServerConfig::default()
+        })
         .await;
-        let text = String::from_utf8(resp).unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"PUT /files/big.bin HTTP/1.1\r\nHost: test\r\nExpect: 100-continue\r\n\
+                  Content-Length: 1025\r\n\r\n",
+            )
+            .await
+            .unwrap();
 
-        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        // Answered without a single body byte sent, and without a 100
+        let mut buf = Vec::new();
+        time::timeout(
+            std::time::Duration::from_secs(5),
+            client.read_to_end(&mut buf),
+        )
+        .await
+        .expect("server should refuse without waiting for the body")
+        .unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(
+            resp.starts_with("HTTP/1.1 413 Content Too Large\r\n"),
+            "{}",
+            resp
+        );
+        assert!(!resp.contains("100 Continue"), "{}", resp);
+        assert!(!tmp.path().join("big.bin").exists());
     }
 
     #[tokio::test]
-    async fn test_integration_file_empty() {
+    async fn test_integration_expect_continue_then_upload() {
         let tmp = tempfile::tempdir().unwrap();
-        std::fs::write(tmp.path().join("empty.dat"), b"").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(
+                b"PUT /files/ok.txt HTTP/1.1\r\nHost: test\r\nExpect: 100-continue\r\n\
+                  Content-Length: 5\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut interim = [0u8; 25];
+        client.read_exact(&mut interim).await.unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let resp =
-            send_raw_request(addr, b"GET /files/empty.dat HTTP/1.1\r\nHost: test\r\n\r\n").await;
-        let text = String::from_utf8(resp).unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 201 Created\r\n"), "{}", resp);
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("ok.txt")).unwrap(),
+            "hello"
+        );
+    }
 
-        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"));
-        assert!(text.contains("Content-Length: 0\r\n"));
-        assert!(text.ends_with("\r\n\r\n"));
+    // ── Integration: health check ────────────────────────────────────
+
+    /// Send `raw` to [`handle_request`] with `router` and `config`,
+    /// returning each response's head and body.
+    async fn exchange_with(
+        config: ServerConfig,
+        router: Router,
+        raw: &[u8],
+    ) -> Vec<(String, Vec<u8>)> {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let handler = tokio::spawn(handle_request(
+            server,
+            None,
+            Arc::new(config),
+            Arc::new(router),
+            Arc::new(Metrics::new()),
+        ));
+        client.write_all(raw).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        handler.await.unwrap().unwrap();
+        split_responses(&buf)
     }
 
     #[tokio::test]
-    async fn test_integration_file_binary_content() {
-        let tmp = tempfile::tempdir().unwrap();
-        let binary: Vec<u8> = vec![0x00, 0x01, 0xFF, 0xFE, 0x0A, 0x0D];
-        std::fs::write(tmp.path().join("bin.dat"), &binary).unwrap();
-
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let resp =
-            send_raw_request(addr, b"GET /files/bin.dat HTTP/1.1\r\nHost: test\r\n\r\n").await;
+    async fn test_integration_health_check_skips_auth() {
+        let mut router = default_router();
+        router.layer(BasicAuth::new(
+            crate::auth::Credentials::parse("alice:s3cret").unwrap(),
+            "everything",
+        ));
+        let responses = exchange_with(
+            ServerConfig::default(),
+            router,
+            b"GET /healthz HTTP/1.1\r\nHost: test\r\n\r\n\
+              GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert_eq!(responses.len(), 2);
+        assert!(
+            responses[0].0.starts_with("HTTP/1.1 200 OK\r\n"),
+            "{}",
+            responses[0].0
+        );
+        assert!(responses[0].0.contains("Cache-Control: no-store\r\n"));
+        assert_eq!(responses[0].1, b"ok");
+        // Everything else still needs credentials
+        assert!(
+            responses[1].0.starts_with("HTTP/1.1 401 "),
+            "{}",
+            responses[1].0
+        );
+    }
 
-        // Find end of headers
-        let header_end = b"\r\n\r\n";
-        let pos = resp
-            .windows(header_end.len())
-            .position(|w| w == header_end)
-            .expect("should have header terminator");
-        let body = &resp[pos + header_end.len()..];
+    #[tokio::test]
+    async fn test_integration_health_check_path_and_disabling() {
+        let config = ServerConfig {
+            health_path: Some("/ping".to_string()),
+            ..ServerConfig::default()
+        };
+        let responses = exchange_with(
+            config,
+            default_router(),
+            b"GET /ping HTTP/1.1\r\nHost: test\r\n\r\n\
+              GET /healthz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(responses[0].0.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(responses[0].1, b"ok");
+        assert!(
+            responses[1].0.starts_with("HTTP/1.1 404 "),
+            "{}",
+            responses[1].0
+        );
 
-        assert_eq!(body, &binary);
+        let config = ServerConfig {
+            health_path: None,
+            ..ServerConfig::default()
+        };
+        let responses = exchange_with(
+            config,
+            default_router(),
+            b"GET /healthz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(
+            responses[0].0.starts_with("HTTP/1.1 404 "),
+            "{}",
+            responses[0].0
+        );
     }
 
-    // ── Integration: POST /files ─────────────────────────────────────
+    // ── Integration: non-TCP streams ─────────────────────────────────
 
     #[tokio::test]
-    async fn test_integration_post_file_creates_file() {
-        let tmp = tempfile::tempdir().unwrap();
+    async fn test_handle_request_over_duplex_pipe() {
+        let config = ServerConfig::default();
+        let metrics = Arc::new(Metrics::new());
+        let router = Arc::new(app_router(&config, &metrics));
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let handler = tokio::spawn(handle_request(
+            server,
+            None,
+            Arc::new(config),
+            router,
+            metrics.clone(),
+        ));
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let body = b"hello file content";
-        let req = format!(
-            "POST /files/newfile.txt HTTP/1.1\r\n\
-             Host: test\r\n\
-             Content-Type: application/octet-stream\r\n\
-             Content-Length: {}\r\n\
-             \r\n\
-             {}",
-            body.len(),
-            std::str::from_utf8(body).unwrap(),
-        );
-        let resp = send_raw_request(addr, req.as_bytes()).await;
-        let text = String::from_utf8(resp).unwrap();
+        client
+            .write_all(
+                b"GET /echo/one HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET /echo/two HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        handler.await.unwrap().unwrap();
+
+        let responses = split_responses(&buf);
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].0.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(responses[0].1, b"one");
+        assert!(responses[1].0.contains("Connection: close"));
+        assert_eq!(responses[1].1, b"two");
+        assert_eq!(metrics.requests_total(), 2);
+    }
 
-        assert!(
-            text.starts_with("HTTP/1.1 201 Created\r\n"),
-            "expected 201, got: {}",
-            text
-        );
+    // ── Integration: persistent connections ──────────────────────────
 
-        // Verify the file was actually created with correct content
-        let written = std::fs::read(tmp.path().join("newfile.txt")).unwrap();
-        assert_eq!(written, body);
+    /// Split a byte stream of back-to-back responses into each response's
+    /// head and body, using their `Content-Length`s. A trailing incomplete
+    /// response is left out.
+    fn split_responses(mut raw: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut responses = Vec::new();
+        while let Some(pos) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&raw[..pos]).into_owned();
+            let len: usize =
+                response_header(&head, "Content-Length").map_or(0, |v| v.parse().unwrap());
+            let body_end = pos + 4 + len;
+            if raw.len() < body_end {
+                break;
+            }
+            responses.push((head, raw[pos + 4..body_end].to_vec()));
+            raw = &raw[body_end..];
+        }
+        responses
     }
 
     #[tokio::test]
-    async fn test_integration_post_file_overwrites_existing() {
-        let tmp = tempfile::tempdir().unwrap();
-        std::fs::write(tmp.path().join("existing.txt"), b"old content").unwrap();
+    async fn test_pipelined_requests_in_one_write() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let body = b"new content";
-        let req = format!(
-            "POST /files/existing.txt HTTP/1.1\r\n\
-             Host: test\r\n\
-             Content-Type: application/octet-stream\r\n\
-             Content-Length: {}\r\n\
-             \r\n\
-             {}",
-            body.len(),
-            std::str::from_utf8(body).unwrap(),
-        );
-        let resp = send_raw_request(addr, req.as_bytes()).await;
-        let text = String::from_utf8(resp).unwrap();
+        client
+            .write_all(
+                b"GET /echo/first HTTP/1.1\r\nHost: test\r\n\r\n\
+                  GET /echo/second HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
 
-        assert!(text.starts_with("HTTP/1.1 201 Created\r\n"));
+        let mut raw = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut raw))
+            .await
+            .expect("pipelined requests should not hang")
+            .unwrap();
 
-        let written = std::fs::read(tmp.path().join("existing.txt")).unwrap();
-        assert_eq!(written, body);
+        let responses = split_responses(&raw);
+        assert_eq!(responses.len(), 2, "{}", String::from_utf8_lossy(&raw));
+        assert!(responses[0].0.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(responses[0].1, b"first");
+        assert_eq!(
+            response_header(&responses[0].0, "Connection"),
+            Some("keep-alive")
+        );
+        assert!(responses[1].0.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(responses[1].1, b"second");
+        assert_eq!(
+            response_header(&responses[1].0, "Connection"),
+            Some("close")
+        );
     }
 
     #[tokio::test]
-    async fn test_integration_post_file_empty_body() {
+    async fn test_pipelined_request_after_body() {
         let tmp = tempfile::tempdir().unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let req = b"POST /files/empty.txt HTTP/1.1\r\n\
-                     Host: test\r\n\
-                     Content-Type: application/octet-stream\r\n\
-                     Content-Length: 0\r\n\
-                     \r\n";
-        let resp = send_raw_request(addr, req).await;
-        let text = String::from_utf8(resp).unwrap();
+        // The body of the POST is followed directly by the next request line
+        client
+            .write_all(
+                b"POST /files/piped.txt HTTP/1.1\r\nHost: test\r\nContent-Length: 5\r\n\r\nhello\
+                  GET /files/piped.txt HTTP/1.1\r\nHost: test\r\n\r\n\
+                  GET /echo/done HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
 
-        assert!(text.starts_with("HTTP/1.1 201 Created\r\n"));
+        let mut raw = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut raw))
+            .await
+            .expect("pipelined requests should not hang")
+            .unwrap();
 
-        let written = std::fs::read(tmp.path().join("empty.txt")).unwrap();
-        assert!(written.is_empty());
+        let responses = split_responses(&raw);
+        assert_eq!(responses.len(), 3, "{}", String::from_utf8_lossy(&raw));
+        assert!(responses[0].0.starts_with("HTTP/1.1 201 Created"));
+        assert!(responses[1].0.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(responses[1].1, b"hello");
+        assert_eq!(responses[2].1, b"done");
     }
 
     #[tokio::test]
-    async fn test_integration_post_file_traversal_rejected() {
-        let tmp = tempfile::tempdir().unwrap();
+    async fn test_pipelined_requests_answered_without_further_input() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+        let mut client = TcpStream::connect(addr).await.unwrap();
 
-        let addr = one_shot_server(tmp.path().to_path_buf()).await;
-        let req = b"POST /files/../evil.txt HTTP/1.1\r\n\
-                     Host: test\r\n\
-                     Content-Type: application/octet-stream\r\n\
-                     Content-Length: 4\r\n\
-                     \r\n\
-                     evil";
-        let resp = send_raw_request(addr, req).await;
-        let text = String::from_utf8(resp).unwrap();
+        // Both requests are buffered; the second must be answered without
+        // the server waiting for more bytes (the client stays open)
+        client
+            .write_all(
+                b"GET /echo/a HTTP/1.1\r\nHost: test\r\n\r\nGET /echo/bb HTTP/1.1\r\nHost: test\r\n\r\n",
+            )
+            .await
+            .unwrap();
 
-        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"));
-    }
+        let mut raw = Vec::new();
+        let mut chunk = [0u8; 4096];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while split_responses(&raw).len() < 2 {
+                let n = client.read(&mut chunk).await.unwrap();
+                assert!(n > 0, "connection closed early");
+                raw.extend_from_slice(&chunk[..n]);
+            }
+        })
+        .await
+        .expect("second pipelined response should arrive");
 
-    // ── Integration: persistent connections ──────────────────────────
+        let responses = split_responses(&raw);
+        assert_eq!(responses[0].1, b"a");
+        assert_eq!(responses[1].1, b"bb");
+    }
 
     #[tokio::test]
     async fn test_persistent_two_requests_same_connection() {
-        let addr = persistent_server(PathBuf::from("/tmp")).await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
 
         let mut client = TcpStream::connect(addr).await.unwrap();
 
@@ -675,7 +5575,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_persistent_echo_then_user_agent() {
-        let addr = persistent_server(PathBuf::from("/tmp")).await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
 
         let mut client = TcpStream::connect(addr).await.unwrap();
 
@@ -713,7 +5613,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_persistent_connection_close_header() {
-        let addr = persistent_server(PathBuf::from("/tmp")).await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
 
         let mut client = TcpStream::connect(addr).await.unwrap();
 
@@ -734,9 +5634,126 @@ mod tests {
         // (If the server didn't close, read_to_end would hang.)
     }
 
+    #[tokio::test]
+    async fn test_persistent_http10_closes_by_default() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /echo/old HTTP/1.0\r\n\r\n")
+            .await
+            .unwrap();
+
+        // read_to_end only returns because the server closed its side
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8_lossy(&buf);
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "resp: {}", resp);
+        assert!(resp.ends_with("old"));
+        assert!(!resp.contains("Connection: keep-alive"));
+    }
+
+    #[tokio::test]
+    async fn test_persistent_http10_keep_alive_stays_open() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /echo/one HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; 4096];
+        let n = client.read(&mut buf).await.unwrap();
+        let resp1 = String::from_utf8_lossy(&buf[..n]);
+        assert!(resp1.starts_with("HTTP/1.1 200 OK\r\n"), "resp1: {}", resp1);
+        assert!(resp1.contains("Connection: keep-alive\r\n"));
+        assert!(resp1.ends_with("one"));
+
+        // Second request without keep-alive: served, then the server closes
+        client
+            .write_all(b"GET /echo/two HTTP/1.0\r\n\r\n")
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        let resp2 = String::from_utf8_lossy(&rest);
+        assert!(resp2.starts_with("HTTP/1.1 200 OK\r\n"), "resp2: {}", resp2);
+        assert!(resp2.ends_with("two"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_header_keep_alive_then_close() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f.txt"), "file body").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+
+        // Streamed file response on a connection that stays open
+        client
+            .write_all(b"GET /files/f.txt HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        let resp1 = read_one_response(&mut client).await;
+        assert!(
+            resp1.contains("Connection: keep-alive\r\n"),
+            "resp1: {}",
+            resp1
+        );
+        assert!(resp1.ends_with("file body"));
+
+        // Buffered response on a connection that's about to close
+        client
+            .write_all(b"GET /echo/bye HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        let resp2 = String::from_utf8_lossy(&rest);
+        assert!(resp2.contains("Connection: close\r\n"), "resp2: {}", resp2);
+        assert_eq!(resp2.matches("Connection:").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_header_on_streamed_file_close() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("f.txt"), "file body").unwrap();
+        let addr = spawn_test_server(tmp.path().to_path_buf()).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET /files/f.txt HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8_lossy(&buf);
+        assert!(resp.contains("Connection: close\r\n"), "resp: {}", resp);
+        assert!(resp.ends_with("file body"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_header_on_rejected_request() {
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/2.0\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8_lossy(&buf);
+        assert!(resp.starts_with("HTTP/1.1 505 "), "resp: {}", resp);
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
     #[tokio::test]
     async fn test_persistent_client_closes_after_first_request() {
-        let addr = persistent_server(PathBuf::from("/tmp")).await;
+        let addr = spawn_test_server(PathBuf::from("/tmp")).await;
 
         let mut client = TcpStream::connect(addr).await.unwrap();
 
@@ -0,0 +1,506 @@
+/// Split a comma-separated list of weighted items (`Accept`,
+/// `Accept-Encoding`) into `(item, q)` pairs in the order sent, ignoring
+/// parameters other than `q`. Items are lowercased; a missing q-value is
+/// `1.0`. Entries with a malformed or out-of-range q-value are skipped, as
+/// RFC 9110 says to ignore them.
+fn weighted_items(header: &str) -> impl Iterator<Item = (String, f32)> + '_ {
+    header.split(',').filter_map(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let item = parts.next().filter(|c| !c.is_empty())?;
+        let mut q = 1.0;
+        for param in parts {
+            let Some((name, value)) = param.split_once('=') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("q") {
+                q = value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q))?;
+            }
+        }
+        Some((item.to_ascii_lowercase(), q))
+    })
+}
+
+/// Parse an `Accept-Encoding` value into the codings the client accepts,
+/// most wanted first.
+///
+/// Codings are lowercased and sorted by descending q-value, keeping the
+/// client's order among equal ones. A missing q-value counts as `1.0`.
+/// Refused codings (`q=0`) and entries with malformed q-values are
+/// dropped. `identity` and `*` are listed like any other coding.
+pub fn parse_accept_encoding(header: &str) -> Vec<(String, f32)> {
+    let mut codings: Vec<_> = weighted_items(header).filter(|&(_, q)| q > 0.0).collect();
+    // Stable, so ties stay in the order the client sent them
+    codings.sort_by(|a, b| b.1.total_cmp(&a.1));
+    codings
+}
+
+/// How much the client wants `coding`, per an `Accept-Encoding` value:
+/// its own q-value if listed, else that of `*`, else `0.0`.
+///
+/// `identity` is acceptable unless refused by name or by `*;q=0`.
+pub fn coding_quality(header: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for (name, q) in weighted_items(header) {
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    match wildcard {
+        Some(q) => q,
+        None if coding.eq_ignore_ascii_case("identity") => 1.0,
+        None => 0.0,
+    }
+}
+
+/// The media type in `available` an `Accept` value rates highest, or `None`
+/// if it accepts none of them.
+///
+/// Each type takes the q-value of the most specific range matching it
+/// (`text/plain` over `text/*` over `*/*`). Ties go to the earlier type in
+/// `available`, so list the server's preference first. A missing or empty
+/// header accepts anything.
+pub fn preferred_media_type<'a>(accept: Option<&str>, available: &[&'a str]) -> Option<&'a str> {
+    let ranges: Vec<_> = match accept.map(str::trim) {
+        Some(accept) if !accept.is_empty() => weighted_items(accept).collect(),
+        _ => return available.first().copied(),
+    };
+
+    let quality = |media_type: &str| {
+        let main_type = media_type.split('/').next().unwrap_or_default();
+        ranges
+            .iter()
+            .filter_map(|(range, q)| {
+                let specificity = match range.split_once('/')? {
+                    ("*", "*") => 0,
+                    (main, "*") if main.eq_ignore_ascii_case(main_type) => 1,
+                    _ if range.eq_ignore_ascii_case(media_type) => 2,
+                    _ => return None,
+                };
+                Some((specificity, *q))
+            })
+            .max_by_key(|&(specificity, _)| specificity)
+            .map_or(0.0, |(_, q)| q)
+    };
+
+    let mut best: Option<(&str, f32)> = None;
+    for &media_type in available {
+        let q = quality(media_type);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((media_type, q));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+/// Whether a comma-separated header value (`Connection`, `Upgrade`, ...)
+/// lists `token`, ignoring case and surrounding whitespace.
+pub fn contains_token(value: &str, token: &str) -> bool {
+    value
+        .split(',')
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+/// The options listed in a `Connection` header value, lowercased and in
+/// order: `"keep-alive, Upgrade"` gives `["keep-alive", "upgrade"]`. Empty
+/// list elements are skipped.
+pub fn connection_tokens(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect()
+}
+
+/// Most ranges honoured in one `Range` header; more than this and the
+/// header is ignored, so a client can't make the server send a file
+/// piecemeal.
+pub const MAX_RANGES: usize = 16;
+
+/// An inclusive byte range within a representation, as in `Content-Range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Number of bytes in the range.
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// What a `Range` header asks of a representation of a given length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// These ranges, clamped to the representation, in the order sent.
+    Satisfiable(Vec<ByteRange>),
+    /// Every range starts past the end: answer `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+/// Resolve a `Range` header against a representation `len` bytes long.
+///
+/// Handles `bytes=a-b`, open-ended `bytes=a-` and suffix `bytes=-n`
+/// ranges, comma-separated. Ranges running past the end are clamped and
+/// ones starting past it are dropped. `None` means the header should be
+/// ignored and the whole representation sent, as RFC 9110 §14.2 allows: a
+/// unit other than `bytes`, a malformed range, or more than [`MAX_RANGES`].
+pub fn parse_range(header: &str, len: u64) -> Option<RangeRequest> {
+    let (unit, specs) = header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for (count, spec) in specs.split(',').map(str::trim).enumerate() {
+        if count == MAX_RANGES {
+            return None;
+        }
+        let (first, last) = spec.split_once('-')?;
+        let range = if first.is_empty() {
+            // Suffix: the final `last` bytes
+            let suffix = parse_digits(last)?;
+            (suffix > 0 && len > 0).then(|| ByteRange {
+                start: len.saturating_sub(suffix),
+                end: len - 1,
+            })
+        } else {
+            let start = parse_digits(first)?;
+            let end = match last {
+                "" => u64::MAX,
+                last => parse_digits(last)?,
+            };
+            if end < start {
+                return None;
+            }
+            (start < len).then(|| ByteRange {
+                start,
+                end: end.min(len - 1),
+            })
+        };
+        ranges.extend(range);
+    }
+
+    if ranges.is_empty() {
+        Some(RangeRequest::Unsatisfiable)
+    } else {
+        Some(RangeRequest::Satisfiable(ranges))
+    }
+}
+
+/// Parse a `Content-Range: bytes a-b/len` header, as sent with a partial
+/// write. The complete length may be `*` and isn't checked. `None` for
+/// any other unit or a malformed range.
+pub fn parse_content_range(header: &str) -> Option<ByteRange> {
+    let (unit, rest) = header.trim().split_once(' ')?;
+    if !unit.eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+    let (range, complete) = rest.trim().split_once('/')?;
+    if complete != "*" {
+        parse_digits(complete)?;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = (parse_digits(start)?, parse_digits(end)?);
+    (start <= end).then_some(ByteRange { start, end })
+}
+
+/// A non-empty run of ASCII digits as a number. `u64::from_str` would
+/// also accept a leading `+`.
+fn parse_digits(n: &str) -> Option<u64> {
+    n.bytes()
+        .all(|b| b.is_ascii_digit())
+        .then(|| n.parse().ok())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(list: &[(&str, f32)]) -> Vec<(String, f32)> {
+        list.iter().map(|&(c, q)| (c.to_string(), q)).collect()
+    }
+
+    // ── parse_accept_encoding ────────────────────────────────────────
+
+    #[test]
+    fn test_parse_plain_list_defaults_to_q1() {
+        assert_eq!(
+            parse_accept_encoding("gzip, deflate"),
+            owned(&[("gzip", 1.0), ("deflate", 1.0)])
+        );
+    }
+
+    #[test]
+    fn test_parse_sorts_by_descending_q() {
+        assert_eq!(
+            parse_accept_encoding("gzip;q=0.5, br;q=1.0, deflate;q=0.8"),
+            owned(&[("br", 1.0), ("deflate", 0.8), ("gzip", 0.5)])
+        );
+    }
+
+    #[test]
+    fn test_parse_drops_refused_codings() {
+        assert_eq!(
+            parse_accept_encoding("*;q=0, gzip"),
+            owned(&[("gzip", 1.0)])
+        );
+        assert_eq!(
+            parse_accept_encoding("identity;q=0, br"),
+            owned(&[("br", 1.0)])
+        );
+    }
+
+    #[test]
+    fn test_parse_keeps_identity() {
+        assert_eq!(
+            parse_accept_encoding("gzip;q=0.9, identity;q=0.1"),
+            owned(&[("gzip", 0.9), ("identity", 0.1)])
+        );
+    }
+
+    #[test]
+    fn test_parse_tolerates_whitespace_and_case() {
+        assert_eq!(
+            parse_accept_encoding("  GZIP ; Q = 0.7 ,br  ,, "),
+            owned(&[("br", 1.0), ("gzip", 0.7)])
+        );
+    }
+
+    #[test]
+    fn test_parse_skips_malformed_q_values() {
+        assert_eq!(
+            parse_accept_encoding("gzip;q=high, br;q=2, deflate;q=-1, zstd;q="),
+            Vec::new()
+        );
+        assert_eq!(
+            parse_accept_encoding("gzip;q=abc, br"),
+            owned(&[("br", 1.0)])
+        );
+    }
+
+    #[test]
+    fn test_parse_ignores_other_parameters() {
+        assert_eq!(
+            parse_accept_encoding("gzip;level=9;q=0.3"),
+            owned(&[("gzip", 0.3)])
+        );
+    }
+
+    #[test]
+    fn test_parse_empty_header() {
+        assert!(parse_accept_encoding("").is_empty());
+    }
+
+    // ── coding_quality ───────────────────────────────────────────────
+
+    #[test]
+    fn test_coding_quality_named_beats_wildcard() {
+        assert_eq!(coding_quality("br;q=0, *", "br"), 0.0);
+        assert_eq!(coding_quality("br;q=0, *", "gzip"), 1.0);
+        assert_eq!(coding_quality("*;q=0.2, gzip", "GZIP"), 1.0);
+        assert_eq!(coding_quality("gzip", "br"), 0.0);
+    }
+
+    #[test]
+    fn test_coding_quality_identity_acceptable_by_default() {
+        assert_eq!(coding_quality("gzip", "identity"), 1.0);
+        assert_eq!(coding_quality("gzip, *;q=0", "identity"), 0.0);
+        assert_eq!(coding_quality("identity;q=0", "identity"), 0.0);
+    }
+
+    // ── preferred_media_type ─────────────────────────────────────────
+
+    const TEXT_OR_JSON: &[&str] = &["text/plain", "application/json"];
+
+    #[test]
+    fn test_preferred_media_type_exact_match() {
+        assert_eq!(
+            preferred_media_type(Some("application/json"), TEXT_OR_JSON),
+            Some("application/json")
+        );
+        assert_eq!(
+            preferred_media_type(Some("text/plain"), TEXT_OR_JSON),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_preferred_media_type_missing_header_takes_first() {
+        assert_eq!(preferred_media_type(None, TEXT_OR_JSON), Some("text/plain"));
+        assert_eq!(
+            preferred_media_type(Some(" "), TEXT_OR_JSON),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_preferred_media_type_by_q_value() {
+        assert_eq!(
+            preferred_media_type(Some("text/plain;q=0.5, application/json"), TEXT_OR_JSON),
+            Some("application/json")
+        );
+        assert_eq!(
+            preferred_media_type(Some("application/json;q=0.4, */*;q=0.8"), TEXT_OR_JSON),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_preferred_media_type_wildcards_and_ties() {
+        assert_eq!(
+            preferred_media_type(Some("*/*"), TEXT_OR_JSON),
+            Some("text/plain")
+        );
+        assert_eq!(
+            preferred_media_type(Some("application/*"), TEXT_OR_JSON),
+            Some("application/json")
+        );
+        // A browser-style header: JSON only through the wildcard
+        assert_eq!(
+            preferred_media_type(
+                Some("text/html,application/xhtml+xml,*/*;q=0.8"),
+                TEXT_OR_JSON
+            ),
+            Some("text/plain")
+        );
+    }
+
+    #[test]
+    fn test_preferred_media_type_specific_range_overrides_wildcard() {
+        assert_eq!(
+            preferred_media_type(Some("*/*, text/plain;q=0"), TEXT_OR_JSON),
+            Some("application/json")
+        );
+        assert_eq!(preferred_media_type(Some("image/png"), TEXT_OR_JSON), None);
+    }
+
+    // ── parse_range ──────────────────────────────────────────────────
+
+    fn ranges(list: &[(u64, u64)]) -> Option<RangeRequest> {
+        Some(RangeRequest::Satisfiable(
+            list.iter()
+                .map(|&(start, end)| ByteRange { start, end })
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_parse_range_forms() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ranges(&[(0, 99)]));
+        assert_eq!(parse_range("bytes=900-", 1000), ranges(&[(900, 999)]));
+        assert_eq!(parse_range("bytes=-100", 1000), ranges(&[(900, 999)]));
+        assert_eq!(
+            parse_range(" Bytes = 0-0 , 5-9", 10),
+            ranges(&[(0, 0), (5, 9)])
+        );
+    }
+
+    #[test]
+    fn test_parse_range_clamps_to_length() {
+        assert_eq!(parse_range("bytes=5-500", 10), ranges(&[(5, 9)]));
+        assert_eq!(parse_range("bytes=-500", 10), ranges(&[(0, 9)]));
+        assert_eq!(ByteRange { start: 5, end: 9 }.size(), 5);
+    }
+
+    #[test]
+    fn test_parse_range_unsatisfiable() {
+        assert_eq!(
+            parse_range("bytes=10-20", 10),
+            Some(RangeRequest::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_range("bytes=-0", 10),
+            Some(RangeRequest::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_range("bytes=0-", 0),
+            Some(RangeRequest::Unsatisfiable)
+        );
+        // Unsatisfiable ranges are dropped when others can be served
+        assert_eq!(parse_range("bytes=50-60, 0-1", 10), ranges(&[(0, 1)]));
+    }
+
+    #[test]
+    fn test_parse_range_ignores_invalid_headers() {
+        for header in [
+            "items=0-1",
+            "bytes",
+            "bytes=",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=+1-2",
+            "bytes=1",
+            "bytes=0-1,,2-3",
+        ] {
+            assert_eq!(parse_range(header, 10), None, "{}", header);
+        }
+    }
+
+    #[test]
+    fn test_parse_range_too_many_ranges() {
+        let at_limit = vec!["0-0"; MAX_RANGES].join(",");
+        assert!(parse_range(&format!("bytes={}", at_limit), 10).is_some());
+        let over = vec!["0-0"; MAX_RANGES + 1].join(",");
+        assert_eq!(parse_range(&format!("bytes={}", over), 10), None);
+    }
+
+    // ── parse_content_range ──────────────────────────────────────────
+
+    #[test]
+    fn test_parse_content_range() {
+        let range = |start, end| Some(ByteRange { start, end });
+        assert_eq!(parse_content_range("bytes 0-4/*"), range(0, 4));
+        assert_eq!(parse_content_range("bytes 10-19/100"), range(10, 19));
+        assert_eq!(parse_content_range(" Bytes 3-3/*"), range(3, 3));
+        for header in [
+            "items 0-4/*",
+            "bytes 0-4",
+            "bytes 5-1/*",
+            "bytes -4/*",
+            "bytes 0-/10",
+            "bytes +0-4/*",
+            "bytes 0-4/ten",
+            "bytes */10",
+        ] {
+            assert_eq!(parse_content_range(header), None, "{}", header);
+        }
+    }
+
+    // ── contains_token ───────────────────────────────────────────────
+
+    #[test]
+    fn test_contains_token() {
+        assert!(contains_token("Upgrade", "upgrade"));
+        assert!(contains_token("keep-alive, Upgrade", "upgrade"));
+        assert!(contains_token(" close ", "close"));
+        assert!(!contains_token("upgrade-insecure", "upgrade"));
+        assert!(!contains_token("", "upgrade"));
+    }
+
+    // ── connection_tokens ────────────────────────────────────────────
+
+    #[test]
+    fn test_connection_tokens() {
+        assert_eq!(connection_tokens("close"), vec!["close"]);
+        assert_eq!(
+            connection_tokens("Keep-Alive, Upgrade"),
+            vec!["keep-alive", "upgrade"]
+        );
+        assert_eq!(
+            connection_tokens(" keep-alive ,, CLOSE ,"),
+            vec!["keep-alive", "close"]
+        );
+        assert!(connection_tokens("").is_empty());
+        assert!(connection_tokens(" , ").is_empty());
+    }
+}
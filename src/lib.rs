@@ -1,4 +1,20 @@
+pub mod access_log;
+pub mod auth;
+pub mod config;
+pub mod cors;
+pub mod error_pages;
+pub mod forwarded;
 pub mod handlers;
+pub mod headers;
+pub mod metrics;
+pub mod middleware;
+pub mod rate_limit;
 pub mod request;
+pub mod request_id;
 pub mod response;
+pub mod router;
 pub mod server;
+pub mod sse;
+#[cfg(any(test, feature = "testing"))]
+pub mod testutil;
+pub mod websocket;
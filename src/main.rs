@@ -1,27 +1,25 @@
-use std::{env, path::PathBuf};
+use std::{env, process};
 
-use anyhow::Result;
+use http_server_rust::config::ServerConfig;
 use log::info;
 
+const USAGE: &str = "usage: http-server-rust [--address <host>] [--port <port>] [--unix <path>] [--directory <path>] [--create-dir] [--autoindex] [--serve-root] [--read-timeout <secs>] [--idle-timeout <secs>] [--max-keepalive-requests <n>] [--max-request-line <n>] [--max-headers <n>] [--max-header-bytes <n>] [--max-body-size <bytes>] [--max-path-depth <n>] [--cors-origin <origin>]... [--cors-max-age <secs>] [--basic-auth <user:password>] [--metrics] [--max-connections <n>] [--overload <queue|reject>] [--max-open-files <n>] [--open-file-wait <secs>] [--rate-limit <requests/sec>] [--rate-burst <n>] [--trusted-proxy <ip>]... [--forwarded-for <leftmost|rightmost>] [--error-page <status>=<path>]... [--allow-missing-host] [--case-insensitive-methods] [--enable-trace] [--no-tcp-nodelay] [--tcp-keepalive <secs>] [--listen-backlog <n>] [--log-format <common|json>] [--response-time-header] [--peer-addr-header] [--charset <name>] [--compression-level <0-9>] [--trailing-slash <off|strip|append>] [--health-path <path>] [--no-health-check]";
+
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() {
     env_logger::init();
     info!("Starting http-server-rust (tokio)");
 
-    let addr = env::var("ADDR").unwrap_or_else(|_| "127.0.0.1:4221".to_string());
-
-    let files_dir = parse_directory_arg().unwrap_or_else(|| PathBuf::from("/tmp"));
-
-    http_server_rust::server::run(&addr, files_dir).await
-}
-
-/// Parse `--directory <path>` from command-line arguments.
-fn parse_directory_arg() -> Option<PathBuf> {
-    let mut args = env::args().skip(1);
-    while let Some(arg) = args.next() {
-        if arg == "--directory" {
-            return args.next().map(PathBuf::from);
+    let config = match ServerConfig::from_args(env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {:#}\n{}", e, USAGE);
+            process::exit(2);
         }
+    };
+
+    if let Err(e) = http_server_rust::server::run(config).await {
+        eprintln!("error: {:#}", e);
+        process::exit(1);
     }
-    None
 }
@@ -0,0 +1,162 @@
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Server-wide counters, shared by every connection and exposed in the
+/// Prometheus text format on `/metrics` when enabled.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests_total: AtomicU64,
+    /// Responses by status class: index 0 is 1xx, 4 is 5xx.
+    responses_by_class: [AtomicU64; 5],
+    bytes_served: AtomicU64,
+    active_connections: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one answered request and the body bytes sent for it.
+    pub fn record(&self, status: u16, bytes_written: usize) {
+        self.requests_total.fetch_add(1, Ordering::Relaxed);
+        if let Some(class) = self.class_counter(status / 100) {
+            class.fetch_add(1, Ordering::Relaxed);
+        }
+        self.bytes_served
+            .fetch_add(bytes_written as u64, Ordering::Relaxed);
+    }
+
+    /// Count a connection as active until the returned guard is dropped.
+    pub fn connection_opened(&self) -> ConnectionGuard<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard { metrics: self }
+    }
+
+    pub fn requests_total(&self) -> u64 {
+        self.requests_total.load(Ordering::Relaxed)
+    }
+
+    /// Responses whose status falls in the given class (`2` for 2xx, etc.).
+    pub fn responses_in_class(&self, class: u16) -> u64 {
+        self.class_counter(class)
+            .map_or(0, |c| c.load(Ordering::Relaxed))
+    }
+
+    fn class_counter(&self, class: u16) -> Option<&AtomicU64> {
+        usize::from(class)
+            .checked_sub(1)
+            .and_then(|i| self.responses_by_class.get(i))
+    }
+
+    pub fn bytes_served(&self) -> u64 {
+        self.bytes_served.load(Ordering::Relaxed)
+    }
+
+    pub fn active_connections(&self) -> u64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Render every counter in the Prometheus plaintext exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::with_capacity(768);
+        out.push_str("# HELP http_requests_total Requests answered.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        let _ = writeln!(out, "http_requests_total {}", self.requests_total());
+
+        out.push_str("# HELP http_responses_total Responses by status class.\n");
+        out.push_str("# TYPE http_responses_total counter\n");
+        for class in 1..=5 {
+            let _ = writeln!(
+                out,
+                "http_responses_total{{class=\"{}xx\"}} {}",
+                class,
+                self.responses_in_class(class)
+            );
+        }
+
+        out.push_str("# HELP http_response_bytes_total Response body bytes served.\n");
+        out.push_str("# TYPE http_response_bytes_total counter\n");
+        let _ = writeln!(out, "http_response_bytes_total {}", self.bytes_served());
+
+        out.push_str("# HELP http_active_connections Connections currently open.\n");
+        out.push_str("# TYPE http_active_connections gauge\n");
+        let _ = writeln!(out, "http_active_connections {}", self.active_connections());
+        out
+    }
+}
+
+/// Keeps a connection counted in [`Metrics`] while alive.
+#[derive(Debug)]
+pub struct ConnectionGuard<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_counts_by_class() {
+        let m = Metrics::new();
+        m.record(200, 10);
+        m.record(204, 0);
+        m.record(404, 9);
+        m.record(503, 0);
+        assert_eq!(m.requests_total(), 4);
+        assert_eq!(m.responses_in_class(2), 2);
+        assert_eq!(m.responses_in_class(3), 0);
+        assert_eq!(m.responses_in_class(4), 1);
+        assert_eq!(m.responses_in_class(5), 1);
+        assert_eq!(m.bytes_served(), 19);
+    }
+
+    #[test]
+    fn test_record_out_of_range_status_still_counted_in_total() {
+        let m = Metrics::new();
+        m.record(99, 0);
+        m.record(600, 0);
+        assert_eq!(m.requests_total(), 2);
+        assert!((0..=9).all(|c| m.responses_in_class(c) == 0));
+    }
+
+    #[test]
+    fn test_connection_guard_tracks_active_connections() {
+        let m = Metrics::new();
+        let a = m.connection_opened();
+        let b = m.connection_opened();
+        assert_eq!(m.active_connections(), 2);
+        drop(a);
+        assert_eq!(m.active_connections(), 1);
+        drop(b);
+        assert_eq!(m.active_connections(), 0);
+    }
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let m = Metrics::new();
+        m.record(200, 5);
+        m.record(404, 9);
+        let _conn = m.connection_opened();
+
+        let text = m.render();
+        assert!(text.contains("# TYPE http_requests_total counter\n"));
+        assert!(text.contains("\nhttp_requests_total 2\n"));
+        assert!(text.contains("\nhttp_responses_total{class=\"2xx\"} 1\n"));
+        assert!(text.contains("\nhttp_responses_total{class=\"4xx\"} 1\n"));
+        assert!(text.contains("\nhttp_responses_total{class=\"5xx\"} 0\n"));
+        assert!(text.contains("\nhttp_response_bytes_total 14\n"));
+        assert!(text.contains("# TYPE http_active_connections gauge\n"));
+        assert!(text.contains("\nhttp_active_connections 1\n"));
+    }
+}
@@ -0,0 +1,299 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use log::info;
+
+use crate::{
+    config::ServerConfig,
+    request::Request,
+    request_id,
+    response::Response,
+    router::{BoxFuture, Router},
+};
+
+/// Code run around every request before it reaches the router, e.g. for
+/// logging, authentication or adding headers.
+///
+/// Conceptually `async fn handle(&self, request, next) -> Result<Response>`;
+/// the future is boxed so middleware can be stored as trait objects. Call
+/// `next.run(request)` to continue down the stack, or return a response
+/// directly to short-circuit it.
+pub trait Middleware: Send + Sync {
+    fn handle<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>>;
+}
+
+/// The rest of the middleware stack, ending in the router.
+pub struct Next<'a> {
+    remaining: &'a [Box<dyn Middleware>],
+    router: &'a Router,
+    config: &'a ServerConfig,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        stack: &'a [Box<dyn Middleware>],
+        router: &'a Router,
+        config: &'a ServerConfig,
+    ) -> Self {
+        Self {
+            remaining: stack,
+            router,
+            config,
+        }
+    }
+
+    /// The server config, for middleware that needs it.
+    pub fn config(&self) -> &'a ServerConfig {
+        self.config
+    }
+
+    /// Pass `request` to the next middleware, or to the matching route once
+    /// the stack is exhausted.
+    pub fn run(self, request: &'a Request) -> BoxFuture<'a, Result<Response>> {
+        match self.remaining.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    remaining: rest,
+                    ..self
+                },
+            ),
+            None => Box::pin(self.router.route_request(request, self.config)),
+        }
+    }
+}
+
+/// Applies `inner` only to requests whose path starts with `prefix`; other
+/// requests skip straight to the rest of the stack.
+pub struct Scoped<M> {
+    prefix: String,
+    inner: M,
+}
+
+impl<M: Middleware> Scoped<M> {
+    pub fn new(prefix: &str, inner: M) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            inner,
+        }
+    }
+}
+
+impl<M: Middleware> Middleware for Scoped<M> {
+    fn handle<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>> {
+        if request.path.starts_with(&self.prefix) {
+            self.inner.handle(request, next)
+        } else {
+            next.run(request)
+        }
+    }
+}
+
+/// Logs each request's method, path, status and handling time at `info`.
+#[derive(Debug, Default)]
+pub struct RequestLogger;
+
+impl Middleware for RequestLogger {
+    fn handle<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let resp = next.run(request).await?;
+            info!(
+                "[{}] {} {} -> {} ({:?})",
+                request_id::log_id(request),
+                request.method,
+                request.path,
+                resp.status_code(),
+                start.elapsed()
+            );
+            Ok(resp)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::request::HttpVersion;
+
+    fn request(path: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    /// Records its name on the way in and out, and tags the response.
+    struct Trace {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Middleware for Trace {
+        fn handle<'a>(
+            &'a self,
+            request: &'a Request,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, Result<Response>> {
+            Box::pin(async move {
+                self.log.lock().unwrap().push(format!("{} in", self.name));
+                let mut resp = next.run(request).await?;
+                self.log.lock().unwrap().push(format!("{} out", self.name));
+                resp.header("X-Trace", self.name);
+                Ok(resp)
+            })
+        }
+    }
+
+    /// Rejects requests without the right token before they reach a route.
+    struct RequireToken;
+
+    impl Middleware for RequireToken {
+        fn handle<'a>(
+            &'a self,
+            request: &'a Request,
+            next: Next<'a>,
+        ) -> BoxFuture<'a, Result<Response>> {
+            Box::pin(async move {
+                if request.header_value("X-Token") != Some("secret") {
+                    return Ok(Response::error(401, "Unauthorized"));
+                }
+                next.run(request).await
+            })
+        }
+    }
+
+    fn router_with_hits(hits: Arc<Mutex<Vec<String>>>) -> Router {
+        let mut router = Router::new();
+        router.route("GET", "/hello", move |_, _, _| {
+            hits.lock().unwrap().push("handler".to_string());
+            Box::pin(async { Ok(Response::ok_text("hello")) })
+        });
+        router
+    }
+
+    #[tokio::test]
+    async fn test_middleware_runs_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut router = router_with_hits(log.clone());
+        router
+            .layer(Trace {
+                name: "outer",
+                log: log.clone(),
+            })
+            .layer(Trace {
+                name: "inner",
+                log: log.clone(),
+            });
+
+        let resp = router
+            .dispatch(&request("/hello", &[]), &ServerConfig::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["outer in", "inner in", "handler", "inner out", "outer out"]
+        );
+        assert_eq!(resp.headers_all("X-Trace"), vec!["inner", "outer"]);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_can_short_circuit() {
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let mut router = router_with_hits(hits.clone());
+        router.layer(RequireToken);
+
+        let resp = router
+            .dispatch(&request("/hello", &[]), &ServerConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 401);
+        assert!(hits.lock().unwrap().is_empty(), "handler must not run");
+
+        let resp = router
+            .dispatch(
+                &request("/hello", &[("X-Token", "secret")]),
+                &ServerConfig::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 200);
+        assert_eq!(*hits.lock().unwrap(), vec!["handler"]);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_sees_unrouted_requests() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut router = Router::new();
+        router.layer(Trace {
+            name: "only",
+            log: log.clone(),
+        });
+
+        let resp = router
+            .dispatch(&request("/missing", &[]), &ServerConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 404);
+        assert_eq!(*log.lock().unwrap(), vec!["only in", "only out"]);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_middleware_only_applies_under_prefix() {
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let mut router = router_with_hits(hits.clone());
+        router.route("GET", "/private/data", |_, _, _| {
+            Box::pin(async { Ok(Response::ok_text("data")) })
+        });
+        router.layer(Scoped::new("/private/", RequireToken));
+
+        let config = ServerConfig::default();
+        let resp = router
+            .dispatch(&request("/hello", &[]), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 200);
+
+        let resp = router
+            .dispatch(&request("/private/data", &[]), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_request_logger_passes_response_through() {
+        let mut router = router_with_hits(Arc::default());
+        router.layer(RequestLogger);
+
+        let resp = router
+            .dispatch(&request("/hello", &[]), &ServerConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 200);
+    }
+}
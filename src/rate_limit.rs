@@ -0,0 +1,322 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    forwarded::TrustedProxies,
+    middleware::{Middleware, Next},
+    request::Request,
+    response::Response,
+    router::BoxFuture,
+};
+
+/// How often idle buckets are swept out of the table.
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Per-client request budget: a steady `per_second` rate, with up to `burst`
+/// requests allowed back to back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    last_cleanup: Instant,
+}
+
+/// Token-bucket rate limiter keyed on the client's IP address.
+///
+/// Clients over budget get `429 Too Many Requests` with a `Retry-After`
+/// header. Requests without a known peer address are not limited. Behind
+/// trusted proxies (see [`trusting`](Self::trusting)) the client is the one
+/// named in `X-Forwarded-For`, so each gets its own budget.
+#[derive(Debug)]
+pub struct RateLimit {
+    config: RateLimitConfig,
+    proxies: TrustedProxies,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimit {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            proxies: TrustedProxies::default(),
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                last_cleanup: Instant::now(),
+            }),
+        }
+    }
+
+    /// Key requests from `proxies` on the client they forward for.
+    pub fn trusting(mut self, proxies: TrustedProxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+
+    /// Take a token for `ip` at time `now`, or return how long until one
+    /// will be available.
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let burst = f64::from(self.config.burst);
+        let rate = self.config.per_second;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if now.saturating_duration_since(buckets.last_cleanup) >= CLEANUP_INTERVAL {
+            // A bucket that would have refilled completely is the same as no
+            // bucket at all
+            buckets.by_ip.retain(|_, b| {
+                b.tokens + now.saturating_duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+            buckets.last_cleanup = now;
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    fn too_many_requests(wait: Duration) -> Response {
+        // Round up so clients never retry before a token is available
+        let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        let mut resp = Response::error(429, "Too Many Requests");
+        resp.header("Retry-After", &secs.max(1).to_string());
+        resp
+    }
+
+    #[cfg(test)]
+    fn tracked_clients(&self) -> usize {
+        self.buckets.lock().unwrap().by_ip.len()
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle<'a>(
+        &'a self,
+        request: &'a Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>> {
+        if let Some(client) = self.proxies.client_ip(request)
+            && let Err(wait) = self.check_at(client, Instant::now())
+        {
+            return Box::pin(async move { Ok(Self::too_many_requests(wait)) });
+        }
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{config::ServerConfig, request::HttpVersion, router::Router};
+
+    fn limiter(per_second: f64, burst: u32) -> RateLimit {
+        RateLimit::new(RateLimitConfig { per_second, burst })
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn request_from(peer: Option<&str>) -> Request {
+        forwarded_request(peer, &[])
+    }
+
+    fn forwarded_request(peer: Option<&str>, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: headers
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: peer.map(|p| p.parse::<SocketAddr>().unwrap()),
+            request_id: None,
+        }
+    }
+
+    fn limited_router(per_second: f64, burst: u32) -> Router {
+        let mut router = Router::new();
+        router
+            .route("GET", "/", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("hi")) })
+            })
+            .layer(limiter(per_second, burst));
+        router
+    }
+
+    // ── Token bucket ─────────────────────────────────────────────────
+
+    #[test]
+    fn test_burst_then_limited() {
+        let rl = limiter(1.0, 3);
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(rl.check_at(ip("10.0.0.1"), now).is_ok());
+        }
+        let wait = rl.check_at(ip("10.0.0.1"), now).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_tokens_refill_over_time() {
+        let rl = limiter(2.0, 1);
+        let start = Instant::now();
+        assert!(rl.check_at(ip("10.0.0.1"), start).is_ok());
+        assert!(rl.check_at(ip("10.0.0.1"), start).is_err());
+        let wait = rl
+            .check_at(ip("10.0.0.1"), start + Duration::from_millis(250))
+            .unwrap_err();
+        assert_eq!(wait, Duration::from_millis(250));
+        assert!(
+            rl.check_at(ip("10.0.0.1"), start + Duration::from_millis(500))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_refill_is_capped_at_burst() {
+        let rl = limiter(10.0, 2);
+        let start = Instant::now();
+        assert!(rl.check_at(ip("10.0.0.1"), start).is_ok());
+        let later = start + Duration::from_secs(60);
+        assert!(rl.check_at(ip("10.0.0.1"), later).is_ok());
+        assert!(rl.check_at(ip("10.0.0.1"), later).is_ok());
+        assert!(rl.check_at(ip("10.0.0.1"), later).is_err());
+    }
+
+    #[test]
+    fn test_clients_have_separate_budgets() {
+        let rl = limiter(1.0, 1);
+        let now = Instant::now();
+        assert!(rl.check_at(ip("10.0.0.1"), now).is_ok());
+        assert!(rl.check_at(ip("10.0.0.1"), now).is_err());
+        assert!(rl.check_at(ip("10.0.0.2"), now).is_ok());
+        assert!(rl.check_at(ip("::1"), now).is_ok());
+    }
+
+    #[test]
+    fn test_idle_buckets_are_cleaned_up() {
+        let rl = limiter(1.0, 5);
+        let start = Instant::now();
+        assert!(rl.check_at(ip("10.0.0.1"), start).is_ok());
+        assert!(rl.check_at(ip("10.0.0.2"), start).is_ok());
+        assert_eq!(rl.tracked_clients(), 2);
+
+        // Only the client seen after the sweep remains
+        assert!(
+            rl.check_at(ip("10.0.0.3"), start + CLEANUP_INTERVAL)
+                .is_ok()
+        );
+        assert_eq!(rl.tracked_clients(), 1);
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        let resp = RateLimit::too_many_requests(Duration::from_millis(1500));
+        assert_eq!(resp.status_code(), 429);
+        assert_eq!(resp.headers_all("Retry-After"), vec!["2"]);
+        let resp = RateLimit::too_many_requests(Duration::from_millis(10));
+        assert_eq!(resp.headers_all("Retry-After"), vec!["1"]);
+    }
+
+    // ── Middleware ───────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_middleware_answers_429_past_budget() {
+        let router = limited_router(0.5, 3);
+        let config = ServerConfig::default();
+        let hammered = request_from(Some("192.0.2.1:40000"));
+        let other = request_from(Some("192.0.2.2:40000"));
+
+        let mut statuses = Vec::new();
+        for _ in 0..5 {
+            let resp = router.dispatch(&hammered, &config).await.unwrap();
+            statuses.push(resp.status_code());
+        }
+        assert_eq!(statuses, vec![200, 200, 200, 429, 429]);
+
+        let resp = router.dispatch(&hammered, &config).await.unwrap();
+        assert_eq!(resp.headers_all("Retry-After"), vec!["2"]);
+
+        // A different client still has its own full budget
+        for _ in 0..3 {
+            let resp = router.dispatch(&other, &config).await.unwrap();
+            assert_eq!(resp.status_code(), 200);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middleware_limits_forwarded_clients_separately() {
+        let mut router = Router::new();
+        router
+            .route("GET", "/", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("hi")) })
+            })
+            .layer(limiter(0.5, 1).trusting(TrustedProxies {
+                proxies: vec![ip("10.0.0.1")],
+                ..TrustedProxies::default()
+            }));
+        let config = ServerConfig::default();
+        let status = |req| {
+            let router = &router;
+            let config = &config;
+            async move { router.dispatch(&req, config).await.unwrap().status_code() }
+        };
+        let via_proxy =
+            |client| forwarded_request(Some("10.0.0.1:40000"), &[("X-Forwarded-For", client)]);
+
+        // Two clients behind the same proxy each get a budget
+        assert_eq!(status(via_proxy("203.0.113.1")).await, 200);
+        assert_eq!(status(via_proxy("203.0.113.2")).await, 200);
+        assert_eq!(status(via_proxy("203.0.113.1")).await, 429);
+
+        // An untrusted peer can't dodge its limit by forging the header
+        let forged =
+            |client| forwarded_request(Some("192.0.2.9:40000"), &[("X-Forwarded-For", client)]);
+        assert_eq!(status(forged("198.51.100.1")).await, 200);
+        assert_eq!(status(forged("198.51.100.2")).await, 429);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_ignores_requests_without_peer() {
+        let router = limited_router(1.0, 1);
+        let config = ServerConfig::default();
+        for _ in 0..3 {
+            let resp = router.dispatch(&request_from(None), &config).await.unwrap();
+            assert_eq!(resp.status_code(), 200);
+        }
+    }
+}
@@ -1,22 +1,140 @@
-use std::net::SocketAddr;
+use std::{collections::HashMap, fmt, net::SocketAddr};
 
 use anyhow::{Context, Result, bail};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
     net::TcpStream,
 };
 
+use crate::{headers::connection_tokens, response::Response};
+
 pub type Key = String;
 pub type Value = String;
 
+pub const DEFAULT_MAX_REQUEST_LINE: usize = 8 * 1024;
+pub const DEFAULT_MAX_HEADERS: usize = 100;
+pub const DEFAULT_MAX_HEADER_BYTES: usize = 16 * 1024;
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_MAX_PATH_DEPTH: usize = 64;
+
+/// Bounds applied while parsing a request, so a client can't make the
+/// server buffer an unbounded request line or header block, plus how
+/// strictly the request line is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Maximum length of the request line, including its line ending.
+    pub max_request_line: usize,
+    /// Maximum number of header lines.
+    pub max_headers: usize,
+    /// Maximum combined size of all header lines, including line endings.
+    pub max_header_bytes: usize,
+    /// Maximum `Content-Length` accepted; larger bodies are refused with
+    /// 413 before any of them is read.
+    pub max_body_size: usize,
+    /// Maximum number of segments in the normalized request path; deeper
+    /// paths are refused with 400.
+    pub max_path_depth: usize,
+    /// Uppercase the method as it's parsed, so `get /` reads as `GET /`.
+    /// Methods are case-sensitive, so by default a lowercase one is
+    /// unknown and gets `501 Not Implemented`.
+    pub case_insensitive_methods: bool,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_request_line: DEFAULT_MAX_REQUEST_LINE,
+            max_headers: DEFAULT_MAX_HEADERS,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_path_depth: DEFAULT_MAX_PATH_DEPTH,
+            case_insensitive_methods: false,
+        }
+    }
+}
+
+/// HTTP protocol versions the server understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl HttpVersion {
+    /// Parse a request-line version token. Anything other than `HTTP/1.0`
+    /// or `HTTP/1.1` is unsupported.
+    pub fn parse(token: &str) -> Option<Self> {
+        match token {
+            "HTTP/1.0" => Some(Self::Http10),
+            "HTTP/1.1" => Some(Self::Http11),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Http10 => "HTTP/1.0",
+            Self::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A request the server refuses to process, carrying the status line to
+/// reply with before closing the connection.
+///
+/// Returned (wrapped in `anyhow::Error`) from [`Request::from_reader`];
+/// callers can `downcast_ref::<RequestError>()` to answer the client.
 #[derive(Debug)]
+pub struct RequestError {
+    pub status_code: u16,
+    pub reason: &'static str,
+    message: String,
+}
+
+impl RequestError {
+    pub fn new(status_code: u16, reason: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status_code,
+            reason,
+            message: message.into(),
+        }
+    }
+
+    /// Build the error response sent to the client. The body is just the
+    /// reason phrase so internal details aren't leaked.
+    pub fn to_response(&self) -> Response {
+        Response::error(self.status_code, self.reason)
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status_code, self.reason, self.message)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Request {
     pub method: String,
+    /// Request target with any query string removed.
     pub path: String,
-    pub http_version: String,
+    /// Raw query string (everything after the first `?`), if present.
+    pub query: Option<String>,
+    pub http_version: HttpVersion,
     pub headers: Vec<(Key, Value)>,
     pub body: Option<Vec<u8>>,
     pub peer_addr: Option<SocketAddr>,
+    /// Correlates this request's log lines and response; assigned by the
+    /// connection handler, `None` straight out of the parser.
+    pub request_id: Option<String>,
 }
 
 /// Trim trailing CR/LF characters from a line read from the network.
@@ -25,119 +143,513 @@ fn trim_line_ending(s: &str) -> &str {
     s.trim_end_matches(['\r', '\n'])
 }
 
+/// Read one line (up to and including `\n`) into `buf`, consuming at most
+/// `limit` bytes.
+///
+/// Returns the number of bytes read (0 at EOF), or `None` if `limit` bytes
+/// were read without reaching the end of the line.
+async fn read_line_limited<S: AsyncRead + Unpin>(
+    reader: &mut BufReader<S>,
+    buf: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<Option<usize>> {
+    let n = (&mut *reader)
+        .take(limit as u64)
+        .read_until(b'\n', buf)
+        .await?;
+    if n == limit && buf.last() != Some(&b'\n') {
+        return Ok(None);
+    }
+    Ok(Some(n))
+}
+
+fn header_too_large(message: impl Into<String>) -> RequestError {
+    RequestError::new(431, "Request Header Fields Too Large", message)
+}
+
+fn bad_request(message: impl Into<String>) -> RequestError {
+    RequestError::new(400, "Bad Request", message)
+}
+
+/// The body length a request declares in `Content-Length`, `0` without one.
+///
+/// Repeated headers and comma-separated lists must all agree, and each value
+/// must be plain digits: any ambiguity would let the body end somewhere other
+/// than where the client (or a proxy in front of us) thinks, and the rest
+/// would be parsed as the next request (RFC 9112 §6.3). For the same reason
+/// a request framed by both `Content-Length` and `Transfer-Encoding` is
+/// refused outright rather than trusting either (RFC 9112 §6.1).
+fn declared_content_length(request: &Request) -> Result<usize, RequestError> {
+    if request.header_value("Content-Length").is_some()
+        && request.header_value("Transfer-Encoding").is_some()
+    {
+        return Err(bad_request(
+            "both Content-Length and Transfer-Encoding present",
+        ));
+    }
+    let mut length = None;
+    for value in request
+        .header_values("Content-Length")
+        .iter()
+        .flat_map(|v| v.split(','))
+    {
+        let value = value.trim();
+        let n = value
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| value.parse::<usize>().ok())
+            .flatten()
+            .ok_or_else(|| bad_request(format!("invalid Content-Length: {:?}", value)))?;
+        if length.is_some_and(|len| len != n) {
+            return Err(bad_request("conflicting Content-Length values"));
+        }
+        length = Some(n);
+    }
+    Ok(length.unwrap_or(0))
+}
+
+/// Whether `s` is a non-empty RFC 7230 token, as header names must be.
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Whether a header value is free of control characters (tab is allowed).
+/// Rejecting CR and LF here keeps a value from ever splitting a response
+/// it is echoed into.
+fn is_valid_header_value(s: &str) -> bool {
+    s.bytes().all(|b| b == b'\t' || !b.is_ascii_control())
+}
+
+/// Split a request target into its path and optional query string.
+///
+/// `/foo?a=1` → (`/foo`, `Some("a=1")`); `/foo?` → (`/foo`, `Some("")`);
+/// `/foo` → (`/foo`, `None`).
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+/// Split an absolute-form request target (`http://host/path?q`, as sent to
+/// proxies) into its authority and the origin-form rest (`/path?q`).
+///
+/// Returns `None` for targets without an `http` or `https` scheme. A missing
+/// path becomes `/`.
+fn split_absolute_form(target: &str) -> Option<(&str, String)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, origin) = rest.split_at(end);
+    let origin = if origin.starts_with('/') {
+        origin.to_string()
+    } else {
+        format!("/{}", origin)
+    };
+    Some((authority, origin))
+}
+
+/// Collapse repeated slashes and resolve `.` and `..` segments in a
+/// decoded request path, so equivalent spellings route the same way.
+///
+/// `/echo//foo` → `/echo/foo`; `/files/./a/../b` → `/files/b`. A trailing
+/// slash (or trailing dot segment) is kept. Returns `None` when `..` would
+/// climb out of the first segment, so a dot segment can never move a request
+/// from one route to another (`/files/../x`). Targets not starting with `/`
+/// (`*`) are returned unchanged.
+pub fn normalize_request_path(path: &str) -> Option<String> {
+    let Some(rest) = path.strip_prefix('/') else {
+        return Some(path.to_string());
+    };
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in rest.split('/') {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." if segments.len() > 1 => {
+                segments.pop();
+            }
+            ".." => return None,
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/// Number of non-empty segments in `path`: `/files/a/b.txt` has 3.
+fn path_depth(path: &str) -> usize {
+    path.split('/').filter(|s| !s.is_empty()).count()
+}
+
+/// Decode `%XX` escape sequences in a URL path.
+///
+/// Malformed escapes (a trailing `%`, or `%` followed by non-hex digits) are
+/// passed through literally. `+` is left untouched since it only means a space
+/// inside query strings. Decoded bytes that aren't valid UTF-8 are replaced
+/// with U+FFFD.
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(decoded) = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            out.push(decoded);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
 impl Request {
-    /// Asynchronously parse a Request from a TcpStream.
-    /// Returns the parsed Request and the original TcpStream back (ready for writing).
+    /// Parse the next HTTP request from an existing `BufReader`.
+    ///
+    /// This is the request parser; everything else ([`from_stream`], the
+    /// connection loop) goes through it, so there is one place that decides
+    /// what a valid request looks like.
+    ///
+    /// Returns `Ok(None)` when the connection is closed cleanly (EOF before the
+    /// request line), `Ok(Some(request))` on success, or `Err(...)` on a parse
+    /// error. Because the reader is borrowed, callers can loop over a
+    /// persistent connection, and bytes of a pipelined next request stay
+    /// buffered for the following call.
     ///
-    /// This is a convenience wrapper around [`from_reader`] for one-shot usage.
+    /// Requests exceeding `limits` fail with a 414 (request line), 431
+    /// (headers) or 413 (body) [`RequestError`]. An `Expect` other than
+    /// `100-continue` fails with 417.
+    ///
+    /// The path is percent-decoded and cleaned up by
+    /// [`normalize_request_path`]. Absolute-form targets (`GET
+    /// http://host/path`) are routed on their path, and their authority
+    /// replaces the `Host` header. `CONNECT` is answered 501, since there is
+    /// no proxy to tunnel through. The asterisk-form target (`*`) is only
+    /// accepted for `OPTIONS`.
+    ///
+    /// [`from_stream`]: Self::from_stream
+    pub async fn from_reader<S: AsyncRead + Unpin>(
+        reader: &mut BufReader<S>,
+        peer_addr: Option<SocketAddr>,
+        limits: &RequestLimits,
+    ) -> Result<Option<Self>> {
+        Self::from_reader_reusing(reader, peer_addr, limits, &mut Vec::new()).await
+    }
+
+    /// Parse a single request from `stream` with the default limits and hand
+    /// the stream back for writing the response.
+    ///
+    /// A one-shot wrapper around [`from_reader`](Self::from_reader): EOF
+    /// before the request line is an error here, and anything the client
+    /// sent after the first request is discarded.
     pub async fn from_stream(stream: TcpStream) -> Result<(Self, TcpStream)> {
         let peer_addr = stream.peer_addr().ok();
         let mut reader = BufReader::new(stream);
 
-        match Self::from_reader(&mut reader, peer_addr).await? {
+        match Self::from_reader(&mut reader, peer_addr, &RequestLimits::default()).await? {
             Some(req) => Ok((req, reader.into_inner())),
             None => bail!("connection closed before request line"),
         }
     }
 
-    /// Parse the next HTTP request from an existing `BufReader`.
-    ///
-    /// Returns `Ok(None)` when the connection is closed cleanly (EOF before the
-    /// request line), `Ok(Some(request))` on success, or `Err(...)` on a parse
-    /// error.  This signature allows callers to loop over a persistent connection.
-    pub async fn from_reader(
-        reader: &mut BufReader<TcpStream>,
+    /// [`from_reader`](Self::from_reader), reading lines into `scratch` so a
+    /// keep-alive connection can reuse one buffer for every request instead
+    /// of allocating per line.
+    pub(crate) async fn from_reader_reusing<S: AsyncRead + Unpin>(
+        reader: &mut BufReader<S>,
         peer_addr: Option<SocketAddr>,
+        limits: &RequestLimits,
+        scratch: &mut Vec<u8>,
     ) -> Result<Option<Self>> {
-        // Read and parse the request line — None means clean EOF
-        let (method, path, http_version) = match Self::read_request_line(reader).await? {
-            Some(parts) => parts,
-            None => return Ok(None),
+        let Some((mut request, body_len)) =
+            Self::read_head(reader, peer_addr, limits, scratch).await?
+        else {
+            return Ok(None);
+        };
+        request.read_body(reader, body_len).await?;
+        Ok(Some(request))
+    }
+
+    /// [`from_reader_reusing`](Self::from_reader_reusing) on a connection
+    /// that can also be written to, so an HTTP/1.1 request with `Expect:
+    /// 100-continue` gets a `100 Continue` before its body is read. A body
+    /// over the limit is refused first, so the client never sends it.
+    pub(crate) async fn from_connection_reusing<S: AsyncRead + AsyncWrite + Unpin>(
+        reader: &mut BufReader<S>,
+        peer_addr: Option<SocketAddr>,
+        limits: &RequestLimits,
+        scratch: &mut Vec<u8>,
+    ) -> Result<Option<Self>> {
+        let Some((mut request, body_len)) =
+            Self::read_head(reader, peer_addr, limits, scratch).await?
+        else {
+            return Ok(None);
         };
+        if body_len > 0
+            && request.http_version == HttpVersion::Http11
+            && request
+                .header_value("Expect")
+                .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+        {
+            Response::informational(100, "Continue")
+                .write_interim(reader.get_mut())
+                .await
+                .context("sending 100 Continue")?;
+        }
+        request.read_body(reader, body_len).await?;
+        Ok(Some(request))
+    }
+
+    /// Read the request line and headers, checking the declared body
+    /// against `limits`. Returns the request without its body, plus the
+    /// body's length.
+    async fn read_head<S: AsyncRead + Unpin>(
+        reader: &mut BufReader<S>,
+        peer_addr: Option<SocketAddr>,
+        limits: &RequestLimits,
+        scratch: &mut Vec<u8>,
+    ) -> Result<Option<(Self, usize)>> {
+        // Read and parse the request line — None means clean EOF
+        let (method, mut target, http_version) =
+            match Self::read_request_line(reader, limits, scratch).await? {
+                Some(parts) => parts,
+                None => return Ok(None),
+            };
+        // CONNECT's authority-form target asks for a tunnel, which only a
+        // proxy can provide
+        if method == "CONNECT" {
+            return Err(
+                RequestError::new(501, "Not Implemented", "CONNECT is not supported").into(),
+            );
+        }
+        // `*` names the server as a whole, which only OPTIONS can ask about
+        // (RFC 9112 §3.2.4)
+        if target == "*" && method != "OPTIONS" {
+            return Err(bad_request(format!("{} does not accept the * target", method)).into());
+        }
+        let mut authority = None;
+        if let Some((host, origin)) = split_absolute_form(&target) {
+            if host.is_empty() {
+                return Err(bad_request(format!("absolute URI without a host: {}", target)).into());
+            }
+            authority = Some(host.to_string());
+            target = origin;
+        }
+        let (raw_path, query) = split_target(&target);
+        let decoded = percent_decode(&raw_path);
+        // An escaping path is left as sent, for the route to reject
+        let path = normalize_request_path(&decoded).unwrap_or(decoded);
+        if path_depth(&path) > limits.max_path_depth {
+            return Err(
+                bad_request(format!("path exceeds {} segments", limits.max_path_depth)).into(),
+            );
+        }
 
         // Read headers
-        let headers = Self::read_headers(reader).await?;
+        let mut headers = Self::read_headers(reader, limits, scratch).await?;
+        // The target's authority overrides any Host header (RFC 7230 §5.4)
+        if let Some(authority) = authority {
+            headers.retain(|(k, _)| !k.eq_ignore_ascii_case("Host"));
+            headers.push(("Host".to_string(), authority));
+        }
 
         // Build a partial request so we can use header_value() for Content-Length
-        let mut request = Self {
+        let request = Self {
             method,
             path,
+            query,
             http_version,
             headers,
             body: None,
             peer_addr,
+            request_id: None,
         };
 
-        // Read body based on Content-Length header (if present)
-        let content_length = request
-            .header_value("Content-Length")
-            .map(|v| v.parse::<usize>())
-            .transpose()
-            .context("parsing Content-Length header")?;
+        // Exactly this many bytes follow as the body; anything after them
+        // belongs to the next request
+        let content_length = declared_content_length(&request)?;
+        // Without a decoder for transfer codings the body's end is unknown,
+        // and guessing would parse the rest as the next request
+        if let Some(coding) = request.header_value("Transfer-Encoding") {
+            return Err(RequestError::new(
+                501,
+                "Not Implemented",
+                format!("unsupported Transfer-Encoding: {}", coding),
+            )
+            .into());
+        }
+        if content_length > limits.max_body_size {
+            return Err(RequestError::new(
+                413,
+                "Content Too Large",
+                format!(
+                    "{}-byte body exceeds the {}-byte limit",
+                    content_length, limits.max_body_size
+                ),
+            )
+            .into());
+        }
+        if let Some(expect) = request.header_value("Expect")
+            && !expect.eq_ignore_ascii_case("100-continue")
+        {
+            return Err(RequestError::new(
+                417,
+                "Expectation Failed",
+                format!("unsupported expectation: {}", expect),
+            )
+            .into());
+        }
+
+        Ok(Some((request, content_length)))
+    }
 
-        if let Some(len) = content_length.filter(|&len| len > 0) {
+    /// Read a `len`-byte body into `self.body`. An empty body leaves it
+    /// `None`.
+    async fn read_body<S: AsyncRead + Unpin>(
+        &mut self,
+        reader: &mut BufReader<S>,
+        len: usize,
+    ) -> Result<()> {
+        if len > 0 {
             let mut buf = vec![0u8; len];
-            reader
-                .read_exact(&mut buf)
-                .await
-                .context("reading request body")?;
-            request.body = Some(buf);
+            match reader.read_exact(&mut buf).await {
+                Ok(_) => self.body = Some(buf),
+                // The client gave up mid-body; it can still read a 400
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(bad_request(format!(
+                        "connection closed before the {}-byte body was complete",
+                        len
+                    ))
+                    .into());
+                }
+                Err(e) => return Err(e).context("reading request body"),
+            }
         }
-
-        Ok(Some(request))
+        Ok(())
     }
 
     /// Read and parse the HTTP request line (e.g. "GET / HTTP/1.1").
     ///
-    /// Returns `Ok(None)` on clean EOF (0 bytes read).
-    async fn read_request_line(
-        reader: &mut BufReader<TcpStream>,
-    ) -> Result<Option<(String, String, String)>> {
-        let mut line = String::new();
-        let n = reader
-            .read_line(&mut line)
+    /// Returns `Ok(None)` on clean EOF (0 bytes read), a 414 [`RequestError`]
+    /// for lines longer than `limits.max_request_line`, and a 505 for
+    /// versions other than HTTP/1.0 and HTTP/1.1. The method comes back
+    /// uppercased if `limits.case_insensitive_methods` is set.
+    async fn read_request_line<S: AsyncRead + Unpin>(
+        reader: &mut BufReader<S>,
+        limits: &RequestLimits,
+        line: &mut Vec<u8>,
+    ) -> Result<Option<(String, String, HttpVersion)>> {
+        line.clear();
+        let n = read_line_limited(reader, line, limits.max_request_line)
             .await
-            .context("reading request line")?;
+            .context("reading request line")?
+            .ok_or_else(|| {
+                RequestError::new(
+                    414,
+                    "URI Too Long",
+                    format!("request line exceeds {} bytes", limits.max_request_line),
+                )
+            })?;
         if n == 0 {
             return Ok(None); // clean EOF
         }
 
-        let trimmed = trim_line_ending(&line);
-        match trimmed.split_whitespace().collect::<Vec<_>>().as_slice() {
-            [method, path, version] => Ok(Some((
-                method.to_string(),
-                path.to_string(),
-                version.to_string(),
-            ))),
+        let line = std::str::from_utf8(line)
+            .map_err(|_| bad_request("request line is not valid UTF-8"))?;
+        let trimmed = trim_line_ending(line);
+        let mut parts = trimmed.split_whitespace();
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(path), Some(version), None) => {
+                let version = HttpVersion::parse(version).ok_or_else(|| {
+                    RequestError::new(
+                        505,
+                        "HTTP Version Not Supported",
+                        format!("unsupported HTTP version: {}", version),
+                    )
+                })?;
+                let mut method = method.to_string();
+                if limits.case_insensitive_methods {
+                    method.make_ascii_uppercase();
+                }
+                Ok(Some((method, path.to_string(), version)))
+            }
             _ => bail!("invalid request line: {}", trimmed),
         }
     }
 
-    /// Read all HTTP headers until the blank line delimiter.
-    async fn read_headers(reader: &mut BufReader<TcpStream>) -> Result<Vec<(Key, Value)>> {
+    /// Read all HTTP headers until the blank line delimiter, enforcing the
+    /// header count and total size limits. EOF before the blank line is a
+    /// 400 [`RequestError`].
+    async fn read_headers<S: AsyncRead + Unpin>(
+        reader: &mut BufReader<S>,
+        limits: &RequestLimits,
+        line: &mut Vec<u8>,
+    ) -> Result<Vec<(Key, Value)>> {
         let mut headers = Vec::new();
-        let mut line = String::new();
+        let mut total = 0;
 
         loop {
             line.clear();
-            let n = reader
-                .read_line(&mut line)
+            let remaining = limits.max_header_bytes - total;
+            let n = read_line_limited(reader, line, remaining)
                 .await
-                .context("reading header line")?;
+                .context("reading header line")?
+                .ok_or_else(|| {
+                    header_too_large(format!("headers exceed {} bytes", limits.max_header_bytes))
+                })?;
             if n == 0 {
-                break; // EOF
+                // The request line arrived, so this is a truncated request
+                // rather than a clean close
+                return Err(bad_request("connection closed before the end of the headers").into());
             }
+            total += n;
 
-            let trimmed = trim_line_ending(&line);
+            let line = std::str::from_utf8(line)
+                .map_err(|_| bad_request("header line is not valid UTF-8"))?;
+            let trimmed = trim_line_ending(line);
             if trimmed.is_empty() {
                 break; // End of headers
             }
 
+            if headers.len() == limits.max_headers {
+                return Err(header_too_large(format!(
+                    "more than {} header lines",
+                    limits.max_headers
+                ))
+                .into());
+            }
+
             let (key, value) = trimmed
                 .split_once(':')
-                .with_context(|| format!("malformed header line: {}", trimmed))?;
+                .ok_or_else(|| bad_request(format!("malformed header line: {:?}", trimmed)))?;
+            // No whitespace is allowed before the colon (or, via obs-fold,
+            // at the start of a line)
+            if !is_token(key) {
+                return Err(bad_request(format!("invalid header name: {:?}", key)).into());
+            }
+            let value = value.trim_matches([' ', '\t']);
+            if !is_valid_header_value(value) {
+                return Err(bad_request(format!("invalid value for header {}", key)).into());
+            }
 
-            headers.push((key.trim().to_string(), value.trim().to_string()));
+            headers.push((key.to_string(), value.to_string()));
         }
 
         Ok(headers)
@@ -150,6 +662,91 @@ impl Request {
             .find(|(k, _)| k.eq_ignore_ascii_case(name))
             .map(|(_, v)| v.as_str())
     }
+
+    /// All values of a header (case-insensitive), in the order received.
+    /// Repeated headers are kept as separate entries, not comma-joined.
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Whether this is an HTTP/1.1 request without the `Host` header that
+    /// RFC 7230 §5.4 requires. HTTP/1.0 requests may omit it.
+    pub fn missing_host(&self) -> bool {
+        self.http_version == HttpVersion::Http11 && self.header_value("Host").is_none()
+    }
+
+    /// Whether the connection should stay open after this request.
+    ///
+    /// HTTP/1.1 defaults to persistent connections unless the client sends
+    /// `Connection: close`; HTTP/1.0 defaults to closing unless it sends
+    /// `Connection: keep-alive`. Each option is looked for among all the
+    /// `Connection` tokens (`keep-alive, Upgrade`), and `close` wins over
+    /// anything else listed.
+    pub fn keep_alive(&self) -> bool {
+        let tokens: Vec<String> = self
+            .header_values("Connection")
+            .into_iter()
+            .flat_map(connection_tokens)
+            .collect();
+        if tokens.iter().any(|t| t == "close") {
+            return false;
+        }
+        match self.http_version {
+            HttpVersion::Http11 => true,
+            HttpVersion::Http10 => tokens.iter().any(|t| t == "keep-alive"),
+        }
+    }
+
+    /// Iterate over `name=value` pairs from every `Cookie` header.
+    ///
+    /// Segments are split on `;` and the first `=`, so values may contain `=`.
+    /// Surrounding whitespace is trimmed; segments without `=` or with an
+    /// empty name are skipped.
+    fn cookie_pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.header_values("Cookie")
+            .into_iter()
+            .flat_map(|header| header.split(';'))
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .filter(|(k, _)| !k.is_empty())
+    }
+
+    /// Parse the `Cookie` header(s) into a map. If a name repeats, the first
+    /// value wins.
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let mut cookies = HashMap::new();
+        for (k, v) in self.cookie_pairs() {
+            cookies
+                .entry(k.to_string())
+                .or_insert_with(|| v.to_string());
+        }
+        cookies
+    }
+
+    /// Look up a single cookie by name (first match).
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookie_pairs()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    }
+
+    /// Look up a query parameter by name, parsing the query string on demand.
+    ///
+    /// Repeated keys return the first value; a key with no `=` (e.g. `?flag`)
+    /// yields an empty string. Values are returned as-is (not percent-decoded).
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v)
+    }
 }
 
 #[cfg(test)]
@@ -222,10 +819,12 @@ mod tests {
         Request {
             method: "GET".to_string(),
             path: "/".to_string(),
-            http_version: "HTTP/1.1".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
             headers,
             body: None,
             peer_addr: None,
+            request_id: None,
         }
     }
 
@@ -265,227 +864,1485 @@ mod tests {
         assert_eq!(req.header_value("X-Custom"), Some("first"));
     }
 
-    // ── from_stream: valid requests ──────────────────────────────────
-
-    #[tokio::test]
-    async fn test_from_stream_simple_get() {
-        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
-        let stream = stream_from_bytes(raw).await;
-
-        let (req, _stream) = Request::from_stream(stream).await.unwrap();
+    #[test]
+    fn test_header_values_repeated_header() {
+        let req = make_request_with_headers(vec![
+            ("X-Forwarded-For".into(), "10.0.0.1".into()),
+            ("Host".into(), "localhost".into()),
+            ("x-forwarded-for".into(), "10.0.0.2".into()),
+            ("X-FORWARDED-FOR".into(), "10.0.0.3".into()),
+        ]);
+        assert_eq!(
+            req.header_values("X-Forwarded-For"),
+            vec!["10.0.0.1", "10.0.0.2", "10.0.0.3"]
+        );
+        assert_eq!(req.header_value("X-Forwarded-For"), Some("10.0.0.1"));
+    }
 
-        assert_eq!(req.method, "GET");
-        assert_eq!(req.path, "/");
-        assert_eq!(req.http_version, "HTTP/1.1");
-        assert_eq!(req.headers.len(), 1);
-        assert_eq!(req.header_value("Host"), Some("localhost"));
-        assert!(req.body.is_none());
+    #[test]
+    fn test_header_values_missing() {
+        let req = make_request_with_headers(vec![("Host".into(), "localhost".into())]);
+        assert!(req.header_values("X-Forwarded-For").is_empty());
     }
 
     #[tokio::test]
-    async fn test_from_stream_get_with_path() {
-        let raw = b"GET /echo/hello HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    async fn test_from_stream_keeps_repeated_headers() {
+        let raw = b"GET / HTTP/1.1\r\nX-Forwarded-For: 1.1.1.1\r\nX-Forwarded-For: 2.2.2.2\r\n\r\n";
         let stream = stream_from_bytes(raw).await;
-
         let (req, _) = Request::from_stream(stream).await.unwrap();
-
-        assert_eq!(req.method, "GET");
-        assert_eq!(req.path, "/echo/hello");
-        assert_eq!(req.http_version, "HTTP/1.1");
+        assert_eq!(
+            req.header_values("x-forwarded-for"),
+            vec!["1.1.1.1", "2.2.2.2"]
+        );
     }
 
-    #[tokio::test]
-    async fn test_from_stream_multiple_headers() {
-        let raw = b"GET / HTTP/1.1\r\n\
-                     Host: example.com\r\n\
-                     User-Agent: test/1.0\r\n\
-                     Accept: */*\r\n\
-                     X-Custom: value\r\n\
-                     \r\n";
-        let stream = stream_from_bytes(raw).await;
-
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    // ── Cookies ──────────────────────────────────────────────────────
 
-        assert_eq!(req.headers.len(), 4);
-        assert_eq!(req.header_value("Host"), Some("example.com"));
-        assert_eq!(req.header_value("User-Agent"), Some("test/1.0"));
-        assert_eq!(req.header_value("Accept"), Some("*/*"));
-        assert_eq!(req.header_value("X-Custom"), Some("value"));
+    fn make_request_with_cookie(cookie: &str) -> Request {
+        make_request_with_headers(vec![("Cookie".into(), cookie.into())])
     }
 
-    #[tokio::test]
-    async fn test_from_stream_post_with_body() {
-        let body = b"hello world";
-        let raw = format!(
-            "POST /data HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
-            body.len(),
-            std::str::from_utf8(body).unwrap(),
-        );
-        let stream = stream_from_bytes(raw.as_bytes()).await;
+    #[test]
+    fn test_cookies_multiple() {
+        let req = make_request_with_cookie("session=abc123; theme=dark; lang=en");
+        let cookies = req.cookies();
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["session"], "abc123");
+        assert_eq!(cookies["theme"], "dark");
+        assert_eq!(cookies["lang"], "en");
+    }
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    #[test]
+    fn test_cookies_single() {
+        let req = make_request_with_cookie("id=42");
+        assert_eq!(req.cookies(), HashMap::from([("id".into(), "42".into())]));
+        assert_eq!(req.cookie("id"), Some("42"));
+    }
 
-        assert_eq!(req.method, "POST");
-        assert_eq!(req.path, "/data");
-        assert_eq!(req.header_value("Content-Length"), Some("11"));
-        assert_eq!(req.body.as_deref(), Some(b"hello world".as_slice()));
+    #[test]
+    fn test_cookies_value_with_equals_and_whitespace() {
+        let req = make_request_with_cookie("  token = a=b==  ;empty=; x=1 ");
+        assert_eq!(req.cookie("token"), Some("a=b=="));
+        assert_eq!(req.cookie("empty"), Some(""));
+        assert_eq!(req.cookie("x"), Some("1"));
     }
 
-    #[tokio::test]
-    async fn test_from_stream_post_binary_body() {
-        let body: Vec<u8> = vec![0x00, 0xFF, 0x0A, 0x0D, 0xBE, 0xEF];
-        let mut raw = format!(
-            "POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
-            body.len()
-        )
-        .into_bytes();
-        raw.extend_from_slice(&body);
-        let stream = stream_from_bytes(&raw).await;
+    #[test]
+    fn test_cookies_malformed_segments_skipped() {
+        let req = make_request_with_cookie("a=1; garbage; =nameless; ; b=2");
+        let cookies = req.cookies();
+        assert_eq!(cookies.len(), 2);
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["b"], "2");
+    }
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    #[test]
+    fn test_cookies_repeated_name_first_wins() {
+        let req = make_request_with_headers(vec![
+            ("Cookie".into(), "a=1; b=2".into()),
+            ("Cookie".into(), "a=3; c=4".into()),
+        ]);
+        let cookies = req.cookies();
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["c"], "4");
+        assert_eq!(req.cookie("a"), Some("1"));
+    }
 
-        assert_eq!(req.method, "POST");
-        assert_eq!(req.body.as_deref(), Some(body.as_slice()));
+    #[test]
+    fn test_cookies_no_header() {
+        let req = make_request_with_headers(vec![]);
+        assert!(req.cookies().is_empty());
+        assert_eq!(req.cookie("a"), None);
     }
 
-    #[tokio::test]
-    async fn test_from_stream_content_length_zero() {
-        let raw = b"POST /empty HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
-        let stream = stream_from_bytes(raw).await;
+    // ── missing_host ─────────────────────────────────────────────────
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    #[test]
+    fn test_missing_host_http11() {
+        assert!(make_request_with_headers(vec![]).missing_host());
+        let req = make_request_with_headers(vec![("host".into(), "example.com".into())]);
+        assert!(!req.missing_host());
+    }
 
-        assert_eq!(req.method, "POST");
-        // Content-Length: 0 should result in no body
-        assert!(req.body.is_none());
+    #[test]
+    fn test_missing_host_http10_is_fine() {
+        let req = Request {
+            http_version: HttpVersion::Http10,
+            ..make_request_with_headers(vec![])
+        };
+        assert!(!req.missing_host());
     }
 
-    #[tokio::test]
-    async fn test_from_stream_no_content_length_means_no_body() {
-        let raw = b"GET /page HTTP/1.1\r\nHost: localhost\r\n\r\n";
-        let stream = stream_from_bytes(raw).await;
+    // ── keep_alive ───────────────────────────────────────────────────
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    fn make_request_with_version(version: HttpVersion, connection: Option<&str>) -> Request {
+        let headers = connection
+            .map(|v| vec![("Connection".to_string(), v.to_string())])
+            .unwrap_or_default();
+        let mut req = make_request_with_headers(headers);
+        req.http_version = version;
+        req
+    }
 
-        assert!(req.body.is_none());
+    #[test]
+    fn test_keep_alive_http11_default() {
+        assert!(make_request_with_version(HttpVersion::Http11, None).keep_alive());
     }
 
-    #[tokio::test]
-    async fn test_from_stream_content_length_case_insensitive() {
-        let raw = b"POST /data HTTP/1.1\r\ncontent-length: 3\r\n\r\nabc";
-        let stream = stream_from_bytes(raw).await;
+    #[test]
+    fn test_keep_alive_http11_close() {
+        assert!(!make_request_with_version(HttpVersion::Http11, Some("close")).keep_alive());
+        assert!(!make_request_with_version(HttpVersion::Http11, Some("Close")).keep_alive());
+    }
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    #[test]
+    fn test_keep_alive_http10_default_closes() {
+        assert!(!make_request_with_version(HttpVersion::Http10, None).keep_alive());
+    }
 
-        assert_eq!(req.body.as_deref(), Some(b"abc".as_slice()));
+    #[test]
+    fn test_keep_alive_http10_keep_alive() {
+        assert!(make_request_with_version(HttpVersion::Http10, Some("keep-alive")).keep_alive());
+        assert!(make_request_with_version(HttpVersion::Http10, Some("Keep-Alive")).keep_alive());
     }
 
-    #[tokio::test]
-    async fn test_from_stream_header_with_colon_in_value() {
-        let raw = b"GET / HTTP/1.1\r\nHost: localhost:8080\r\n\r\n";
+    #[test]
+    fn test_keep_alive_token_lists() {
+        for (version, connection, expected) in [
+            (HttpVersion::Http11, "keep-alive, close", false),
+            (HttpVersion::Http11, "Upgrade, Close", false),
+            (HttpVersion::Http11, "Upgrade", true),
+            (HttpVersion::Http11, "keep-alive, Upgrade", true),
+            (HttpVersion::Http11, "closed", true),
+            (HttpVersion::Http10, "Upgrade, keep-alive", true),
+            (HttpVersion::Http10, "keep-alive, close", false),
+            (HttpVersion::Http10, "Upgrade", false),
+        ] {
+            let req = make_request_with_version(version, Some(connection));
+            assert_eq!(req.keep_alive(), expected, "{} {:?}", version, connection);
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_repeated_connection_headers() {
+        let header = |v: &str| ("Connection".to_string(), v.to_string());
+        let req = make_request_with_headers(vec![header("keep-alive"), header("close")]);
+        assert!(!req.keep_alive());
+
+        let mut req = make_request_with_headers(vec![header("Upgrade"), header("Keep-Alive")]);
+        req.http_version = HttpVersion::Http10;
+        assert!(req.keep_alive());
+    }
+
+    // ── split_target / query_param ───────────────────────────────────
+
+    fn make_request_with_query(query: Option<&str>) -> Request {
+        let mut req = make_request_with_headers(vec![]);
+        req.query = query.map(str::to_string);
+        req
+    }
+
+    #[test]
+    fn test_split_target_no_query() {
+        assert_eq!(split_target("/echo/x"), ("/echo/x".to_string(), None));
+    }
+
+    #[test]
+    fn test_split_target_with_query() {
+        assert_eq!(
+            split_target("/echo/x?y=z"),
+            ("/echo/x".to_string(), Some("y=z".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_split_target_empty_query() {
+        assert_eq!(
+            split_target("/foo?"),
+            ("/foo".to_string(), Some(String::new()))
+        );
+    }
+
+    #[test]
+    fn test_split_target_only_first_question_mark_splits() {
+        assert_eq!(
+            split_target("/foo?a=1?b"),
+            ("/foo".to_string(), Some("a=1?b".to_string()))
+        );
+    }
+
+    // ── split_absolute_form ──────────────────────────────────────────
+
+    #[test]
+    fn test_split_absolute_form() {
+        assert_eq!(
+            split_absolute_form("http://example.com:8080/echo/x?a=1"),
+            Some(("example.com:8080", "/echo/x?a=1".to_string()))
+        );
+        assert_eq!(
+            split_absolute_form("HTTPS://example.com"),
+            Some(("example.com", "/".to_string()))
+        );
+        assert_eq!(
+            split_absolute_form("http://example.com?q"),
+            Some(("example.com", "/?q".to_string()))
+        );
+        assert_eq!(split_absolute_form("/echo/http://x"), None);
+        assert_eq!(split_absolute_form("ftp://example.com/x"), None);
+        assert_eq!(split_absolute_form("*"), None);
+    }
+
+    // ── normalize_request_path ───────────────────────────────────────
+
+    #[test]
+    fn test_normalize_request_path_collapses_slashes() {
+        for (path, expected) in [
+            ("/echo//foo", "/echo/foo"),
+            ("//files///a.txt", "/files/a.txt"),
+            ("/files//", "/files/"),
+            ("/", "/"),
+            ("//", "/"),
+        ] {
+            assert_eq!(
+                normalize_request_path(path).as_deref(),
+                Some(expected),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_request_path_resolves_dot_segments() {
+        for (path, expected) in [
+            ("/files/./bar", "/files/bar"),
+            ("/files/a/../b", "/files/b"),
+            ("/files/a/b/..", "/files/a/"),
+            ("/files/.", "/files/"),
+            ("/./files/x", "/files/x"),
+            ("/files/..x/.y", "/files/..x/.y"),
+        ] {
+            assert_eq!(
+                normalize_request_path(path).as_deref(),
+                Some(expected),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_normalize_request_path_rejects_traversal() {
+        for path in [
+            "/..",
+            "/../etc/passwd",
+            "/files/..",
+            "/files/../secret",
+            "/files/a/../../b",
+        ] {
+            assert_eq!(normalize_request_path(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_normalize_request_path_leaves_asterisk() {
+        assert_eq!(normalize_request_path("*").as_deref(), Some("*"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_normalizes_path() {
+        let raw = b"GET /echo/%2e/a//b HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+        let (req, _stream) = Request::from_stream(stream).await.unwrap();
+        assert_eq!(req.path, "/echo/a/b");
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_keeps_escaping_path_for_the_route() {
+        let raw = b"GET /files/../secret HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+        let (req, _stream) = Request::from_stream(stream).await.unwrap();
+        assert_eq!(req.path, "/files/../secret");
+    }
+
+    // ── percent_decode ───────────────────────────────────────────────
+
+    #[test]
+    fn test_percent_decode_space() {
+        assert_eq!(percent_decode("hello%20world"), "hello world");
+    }
+
+    #[test]
+    fn test_percent_decode_no_escapes() {
+        assert_eq!(percent_decode("/echo/plain"), "/echo/plain");
+    }
+
+    #[test]
+    fn test_percent_decode_mixed_case_hex() {
+        assert_eq!(percent_decode("%2e%2E%2f"), "../");
+    }
+
+    #[test]
+    fn test_percent_decode_plus_untouched() {
+        assert_eq!(percent_decode("a+b"), "a+b");
+    }
+
+    #[test]
+    fn test_percent_decode_trailing_percent() {
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn test_percent_decode_truncated_escape() {
+        assert_eq!(percent_decode("abc%2"), "abc%2");
+    }
+
+    #[test]
+    fn test_percent_decode_invalid_hex() {
+        assert_eq!(percent_decode("%zz%20"), "%zz ");
+    }
+
+    #[test]
+    fn test_percent_decode_multibyte_utf8() {
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+    }
+
+    #[test]
+    fn test_percent_decode_invalid_utf8_is_replaced() {
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn test_query_param_basic() {
+        let req = make_request_with_query(Some("a=1&b=two"));
+        assert_eq!(req.query_param("a"), Some("1"));
+        assert_eq!(req.query_param("b"), Some("two"));
+    }
+
+    #[test]
+    fn test_query_param_missing_query() {
+        let req = make_request_with_query(None);
+        assert_eq!(req.query_param("a"), None);
+    }
+
+    #[test]
+    fn test_query_param_empty_query() {
+        let req = make_request_with_query(Some(""));
+        assert_eq!(req.query_param("a"), None);
+        assert_eq!(req.query_param(""), None);
+    }
+
+    #[test]
+    fn test_query_param_repeated_key_returns_first() {
+        let req = make_request_with_query(Some("k=first&k=second"));
+        assert_eq!(req.query_param("k"), Some("first"));
+    }
+
+    #[test]
+    fn test_query_param_flag_without_value() {
+        let req = make_request_with_query(Some("flag&x=1"));
+        assert_eq!(req.query_param("flag"), Some(""));
+        assert_eq!(req.query_param("x"), Some("1"));
+    }
+
+    #[test]
+    fn test_query_param_value_not_percent_decoded() {
+        let req = make_request_with_query(Some("q=hello%20world"));
+        assert_eq!(req.query_param("q"), Some("hello%20world"));
+    }
+
+    #[test]
+    fn test_query_param_missing_key() {
+        let req = make_request_with_query(Some("a=1"));
+        assert_eq!(req.query_param("b"), None);
+    }
+
+    // ── from_stream: valid requests ──────────────────────────────────
+
+    #[tokio::test]
+    async fn test_from_stream_simple_get() {
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _stream) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path, "/");
+        assert_eq!(req.http_version, HttpVersion::Http11);
+        assert_eq!(req.headers.len(), 1);
+        assert_eq!(req.header_value("Host"), Some("localhost"));
+        assert!(req.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_absolute_form_target() {
+        let raw = b"GET http://example.com/echo/a%20b?x=1 HTTP/1.1\r\nHost: other\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _stream) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.path, "/echo/a b");
+        assert_eq!(req.query.as_deref(), Some("x=1"));
+        // The authority wins over the Host header
+        assert_eq!(req.header_values("Host"), vec!["example.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_absolute_form_supplies_missing_host() {
+        let raw = b"GET http://localhost:4221 HTTP/1.1\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _stream) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.path, "/");
+        assert!(!req.missing_host());
+        assert_eq!(req.header_value("Host"), Some("localhost:4221"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_absolute_form_without_host_is_400() {
+        let stream = stream_from_bytes(b"GET http:///echo/x HTTP/1.1\r\n\r\n").await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_connect_is_501() {
+        let raw = b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 501);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_options_asterisk() {
+        let stream = stream_from_bytes(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "OPTIONS");
+        assert_eq!(req.path, "*");
+        assert_eq!(req.query, None);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_asterisk_only_for_options() {
+        for method in ["GET", "DELETE"] {
+            let raw = format!("{} * HTTP/1.1\r\nHost: localhost\r\n\r\n", method);
+            let stream = stream_from_bytes(raw.as_bytes()).await;
+            assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_get_with_path() {
+        let raw = b"GET /echo/hello HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path, "/echo/hello");
+        assert_eq!(req.http_version, HttpVersion::Http11);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_splits_query() {
+        let raw = b"GET /echo/x?y=z&flag HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.path, "/echo/x");
+        assert_eq!(req.query.as_deref(), Some("y=z&flag"));
+        assert_eq!(req.query_param("y"), Some("z"));
+        assert_eq!(req.query_param("flag"), Some(""));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_decodes_path_but_not_query() {
+        let raw = b"GET /echo/a%20b?q=c%20d HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.path, "/echo/a b");
+        assert_eq!(req.query.as_deref(), Some("q=c%20d"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_no_query() {
+        let raw = b"GET /echo/x HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.path, "/echo/x");
+        assert!(req.query.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_multiple_headers() {
+        let raw = b"GET / HTTP/1.1\r\n\
+                     Host: example.com\r\n\
+                     User-Agent: test/1.0\r\n\
+                     Accept: */*\r\n\
+                     X-Custom: value\r\n\
+                     \r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.headers.len(), 4);
+        assert_eq!(req.header_value("Host"), Some("example.com"));
+        assert_eq!(req.header_value("User-Agent"), Some("test/1.0"));
+        assert_eq!(req.header_value("Accept"), Some("*/*"));
+        assert_eq!(req.header_value("X-Custom"), Some("value"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_post_with_body() {
+        let body = b"hello world";
+        let raw = format!(
+            "POST /data HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            std::str::from_utf8(body).unwrap(),
+        );
+        let stream = stream_from_bytes(raw.as_bytes()).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/data");
+        assert_eq!(req.header_value("Content-Length"), Some("11"));
+        assert_eq!(req.body.as_deref(), Some(b"hello world".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_post_binary_body() {
+        let body: Vec<u8> = vec![0x00, 0xFF, 0x0A, 0x0D, 0xBE, 0xEF];
+        let mut raw = format!(
+            "POST /upload HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(&body);
+        let stream = stream_from_bytes(&raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.body.as_deref(), Some(body.as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_content_length_zero() {
+        let raw = b"POST /empty HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "POST");
+        // Content-Length: 0 should result in no body
+        assert!(req.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_no_content_length_means_no_body() {
+        let raw = b"GET /page HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert!(req.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_content_length_case_insensitive() {
+        let raw = b"POST /data HTTP/1.1\r\ncontent-length: 3\r\n\r\nabc";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.body.as_deref(), Some(b"abc".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_header_with_colon_in_value() {
+        let raw = b"GET / HTTP/1.1\r\nHost: localhost:8080\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        // split_once(':') should keep everything after first colon as the value
+        assert_eq!(req.header_value("Host"), Some("localhost:8080"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_no_headers() {
+        let raw = b"GET / HTTP/1.1\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "GET");
+        assert!(req.headers.is_empty());
+        assert!(req.body.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_lf_only_line_endings() {
+        // Some clients send LF-only instead of CRLF
+        let raw = b"GET /lf HTTP/1.1\nHost: localhost\n\n";
+        let stream = stream_from_bytes(raw).await;
+
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path, "/lf");
+        assert_eq!(req.header_value("Host"), Some("localhost"));
+    }
+
+    // ── from_stream: error cases ─────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_from_stream_empty_stream() {
+        let stream = stream_from_bytes(b"").await;
+        let result = Request::from_stream(stream).await;
+
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("connection closed"),
+            "expected 'connection closed' error, got: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_invalid_request_line_one_part() {
+        let raw = b"INVALID\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+        let result = Request::from_stream(stream).await;
+
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("invalid request line"),
+            "expected 'invalid request line' error, got: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_invalid_request_line_two_parts() {
+        let raw = b"GET /\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+        let result = Request::from_stream(stream).await;
+
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("invalid request line"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_malformed_header() {
+        let raw = b"GET / HTTP/1.1\r\nBadHeaderNoColon\r\n\r\n";
+        let stream = stream_from_bytes(raw).await;
+        let result = Request::from_stream(stream).await;
+
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("malformed header"),
+            "expected 'malformed header' error, got: {}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_malformed_header_is_400() {
+        let stream = stream_from_bytes(b"GET / HTTP/1.1\r\nBadHeaderNoColon\r\n\r\n").await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_invalid_utf8_request_line_is_400() {
+        let stream = stream_from_bytes(b"GET /caf\xe9 HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        let err = Request::from_stream(stream).await.unwrap_err();
+        assert!(err.to_string().contains("not valid UTF-8"), "{}", err);
+        assert_status(err, 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_invalid_utf8_header_is_400() {
+        for raw in [
+            &b"GET / HTTP/1.1\r\nX-Na\xffme: v\r\n\r\n"[..],
+            &b"GET / HTTP/1.1\r\nX-Name: \xc3\x28\r\n\r\n"[..],
+        ] {
+            let stream = stream_from_bytes(raw).await;
+            assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+        }
+    }
+
+    // ── Header validation ────────────────────────────────────────────
+
+    #[test]
+    fn test_is_token() {
+        assert!(is_token("Content-Type"));
+        assert!(is_token("x-custom_header.v2"));
+        assert!(is_token("!#$%&'*+-.^_`|~"));
+        assert!(!is_token(""));
+        assert!(!is_token("Bad Name"));
+        assert!(!is_token("Host "));
+        assert!(!is_token(" Host"));
+        assert!(!is_token("a(b)"));
+        assert!(!is_token("caf\u{e9}"));
+    }
+
+    #[test]
+    fn test_is_valid_header_value() {
+        assert!(is_valid_header_value(""));
+        assert!(is_valid_header_value("text/html; charset=utf-8"));
+        assert!(is_valid_header_value("a\tb"));
+        assert!(is_valid_header_value("caf\u{e9}"));
+        assert!(!is_valid_header_value("a\rb"));
+        assert!(!is_valid_header_value("a\nb"));
+        assert!(!is_valid_header_value("a\0b"));
+        assert!(!is_valid_header_value("a\x7fb"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_space_in_header_name_is_400() {
+        for raw in [
+            &b"GET / HTTP/1.1\r\nBad Name: x\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nHost : x\r\n\r\n",
+        ] {
+            let stream = stream_from_bytes(raw).await;
+            assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_embedded_newline_in_value_is_400() {
+        // A bare CR inside the value
+        let stream = stream_from_bytes(b"GET / HTTP/1.1\r\nX-A: one\rX-B: two\r\n\r\n").await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+
+        // A bare LF turns the rest into a folded continuation line
+        let stream = stream_from_bytes(b"GET / HTTP/1.1\r\nX-A: one\n two\r\n\r\n").await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_control_char_in_value_is_400() {
+        let stream = stream_from_bytes(b"GET / HTTP/1.1\r\nX-A: a\x00b\r\n\r\n").await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_tab_and_utf8_values_accepted() {
+        let raw = "GET / HTTP/1.1\r\nX-A:\ta\tb\t\r\nX-B: caf\u{e9}\r\n\r\n";
+        let stream = stream_from_bytes(raw.as_bytes()).await;
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+        assert_eq!(req.header_value("X-A"), Some("a\tb"));
+        assert_eq!(req.header_value("X-B"), Some("caf\u{e9}"));
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_truncated_body_is_400() {
+        let raw = b"POST /data HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc";
+        let stream = stream_from_bytes(raw).await;
+        let err = Request::from_stream(stream).await.unwrap_err();
+        assert!(err.to_string().contains("10-byte body"), "{}", err);
+        assert_status(err, 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_truncated_headers_is_400() {
+        for raw in [
+            &b"GET / HTTP/1.1\r\nHost: x\r\n"[..],
+            &b"GET / HTTP/1.1\r\nHost: x"[..],
+            &b"GET / HTTP/1.1\r\n"[..],
+        ] {
+            let stream = stream_from_bytes(raw).await;
+            let err = Request::from_stream(stream).await.unwrap_err();
+            assert!(err.to_string().contains("end of the headers"), "{}", err);
+            assert_status(err, 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_eof_before_request_is_clean() {
+        let stream = stream_from_bytes(b"").await;
+        let mut reader = BufReader::new(stream);
+        let request = Request::from_reader(&mut reader, None, &RequestLimits::default())
+            .await
+            .unwrap();
+        assert!(request.is_none());
+    }
+
+    /// Yields its bytes, then fails with `ConnectionReset`.
+    struct ResetAfter(&'static [u8]);
+
+    impl AsyncRead for ResetAfter {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &mut io::ReadBuf<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            if self.0.is_empty() {
+                return std::task::Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+            }
+            let n = self.0.len().min(buf.remaining());
+            buf.put_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_other_body_errors_are_not_400() {
+        let mut reader = BufReader::new(ResetAfter(
+            b"POST /data HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc",
+        ));
+        let err = Request::from_reader(&mut reader, None, &RequestLimits::default())
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<RequestError>().is_none(), "{:#}", err);
+        assert!(
+            err.to_string().contains("reading request body"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_invalid_content_length() {
+        let raw = b"POST /data HTTP/1.1\r\nContent-Length: abc\r\n\r\n";
         let stream = stream_from_bytes(raw).await;
+        let result = Request::from_stream(stream).await;
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+        assert!(result.is_err());
+        let err = result.unwrap_err().to_string();
+        assert!(
+            err.contains("Content-Length"),
+            "expected Content-Length parse error, got: {}",
+            err
+        );
+    }
 
-        // split_once(':') should keep everything after first colon as the value
-        assert_eq!(req.header_value("Host"), Some("localhost:8080"));
+    #[tokio::test]
+    async fn test_from_stream_ambiguous_content_length_is_400() {
+        for headers in [
+            "Content-Length: +5",
+            "Content-Length: -5",
+            "Content-Length: 5 5",
+            "Content-Length: ",
+            "Content-Length: 5, 6",
+            "Content-Length: 5\r\nContent-Length: 6",
+        ] {
+            let raw = format!("POST /data HTTP/1.1\r\n{}\r\n\r\nhello", headers);
+            let stream = stream_from_bytes(raw.as_bytes()).await;
+            let err = Request::from_stream(stream).await.unwrap_err();
+            let status = err.downcast_ref::<RequestError>().map(|e| e.status_code);
+            assert_eq!(status, Some(400), "{:?}: {:#}", headers, err);
+        }
     }
 
     #[tokio::test]
-    async fn test_from_stream_no_headers() {
-        let raw = b"GET / HTTP/1.1\r\n\r\n";
-        let stream = stream_from_bytes(raw).await;
+    async fn test_from_stream_repeated_equal_content_length() {
+        for headers in [
+            "Content-Length: 5, 5",
+            "Content-Length: 5\r\nContent-Length: 5",
+        ] {
+            let raw = format!("POST /data HTTP/1.1\r\n{}\r\n\r\nhello", headers);
+            let stream = stream_from_bytes(raw.as_bytes()).await;
+            let (req, _) = Request::from_stream(stream).await.unwrap();
+            assert_eq!(req.body.as_deref(), Some(&b"hello"[..]), "{:?}", headers);
+        }
+    }
 
-        let (req, _) = Request::from_stream(stream).await.unwrap();
+    #[tokio::test]
+    async fn test_from_stream_content_length_with_transfer_encoding_is_400() {
+        for headers in [
+            "Content-Length: 5\r\nTransfer-Encoding: chunked",
+            "transfer-encoding: chunked\r\ncontent-length: 5",
+            "Content-Length: 5\r\nTransfer-Encoding: gzip, chunked",
+        ] {
+            let raw = format!("POST /data HTTP/1.1\r\n{}\r\n\r\nhello", headers);
+            let stream = stream_from_bytes(raw.as_bytes()).await;
+            let err = Request::from_stream(stream).await.unwrap_err();
+            assert!(
+                err.to_string().contains("Transfer-Encoding"),
+                "{:?}: {:#}",
+                headers,
+                err
+            );
+            assert_status(err, 400);
+        }
 
-        assert_eq!(req.method, "GET");
-        assert!(req.headers.is_empty());
-        assert!(req.body.is_none());
+        // Without the Transfer-Encoding, the same request is fine
+        let raw = b"POST /data HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let (req, _) = Request::from_stream(stream_from_bytes(raw).await)
+            .await
+            .unwrap();
+        assert_eq!(req.body.as_deref(), Some(&b"hello"[..]));
     }
 
     #[tokio::test]
-    async fn test_from_stream_lf_only_line_endings() {
-        // Some clients send LF-only instead of CRLF
-        let raw = b"GET /lf HTTP/1.1\nHost: localhost\n\n";
-        let stream = stream_from_bytes(raw).await;
+    async fn test_from_stream_transfer_encoding_is_501() {
+        for coding in ["chunked", "gzip, chunked", "identity"] {
+            let raw = format!(
+                "POST /data HTTP/1.1\r\nTransfer-Encoding: {}\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+                coding
+            );
+            let stream = stream_from_bytes(raw.as_bytes()).await;
+            let err = Request::from_stream(stream).await.unwrap_err();
+            assert_status(err, 501);
+        }
+    }
+
+    // ── HttpVersion ──────────────────────────────────────────────────
+
+    #[test]
+    fn test_http_version_parse_supported() {
+        assert_eq!(HttpVersion::parse("HTTP/1.0"), Some(HttpVersion::Http10));
+        assert_eq!(HttpVersion::parse("HTTP/1.1"), Some(HttpVersion::Http11));
+    }
 
+    #[test]
+    fn test_http_version_parse_unsupported() {
+        assert_eq!(HttpVersion::parse("HTTP/0.9"), None);
+        assert_eq!(HttpVersion::parse("HTTP/2.0"), None);
+        assert_eq!(HttpVersion::parse("HTTP/3.0"), None);
+        assert_eq!(HttpVersion::parse("http/1.1"), None);
+        assert_eq!(HttpVersion::parse("FOO"), None);
+    }
+
+    #[test]
+    fn test_http_version_display_round_trip() {
+        for v in [HttpVersion::Http10, HttpVersion::Http11] {
+            assert_eq!(HttpVersion::parse(&v.to_string()), Some(v));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_http10_accepted() {
+        let stream = stream_from_bytes(b"GET / HTTP/1.0\r\n\r\n").await;
         let (req, _) = Request::from_stream(stream).await.unwrap();
+        assert_eq!(req.http_version, HttpVersion::Http10);
+    }
 
-        assert_eq!(req.method, "GET");
-        assert_eq!(req.path, "/lf");
-        assert_eq!(req.header_value("Host"), Some("localhost"));
+    #[tokio::test]
+    async fn test_from_stream_unsupported_version_is_505() {
+        for raw in [
+            &b"GET / HTTP/3.0\r\n\r\n"[..],
+            &b"GET / HTTP/0.9\r\n\r\n"[..],
+            &b"GET / HTTP/2\r\n\r\n"[..],
+        ] {
+            let stream = stream_from_bytes(raw).await;
+            let err = Request::from_stream(stream).await.unwrap_err();
+
+            let req_err = err
+                .downcast_ref::<RequestError>()
+                .expect("should be a RequestError");
+            assert_eq!(req_err.status_code, 505);
+            assert!(err.to_string().contains("unsupported HTTP version"));
+        }
     }
 
-    // ── from_stream: error cases ─────────────────────────────────────
+    // ── Header limits ────────────────────────────────────────────────
+
+    fn request_with_headers(count: usize, value_len: usize) -> Vec<u8> {
+        let mut raw = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..count {
+            raw.extend_from_slice(format!("X-H{}: {}\r\n", i, "a".repeat(value_len)).as_bytes());
+        }
+        raw.extend_from_slice(b"\r\n");
+        raw
+    }
+
+    fn assert_status(err: anyhow::Error, status: u16) {
+        let req_err = err
+            .downcast_ref::<RequestError>()
+            .unwrap_or_else(|| panic!("expected a RequestError, got: {:#}", err));
+        assert_eq!(req_err.status_code, status, "got: {}", req_err);
+    }
 
     #[tokio::test]
-    async fn test_from_stream_empty_stream() {
-        let stream = stream_from_bytes(b"").await;
-        let result = Request::from_stream(stream).await;
+    async fn test_from_stream_max_headers_accepted() {
+        let stream = stream_from_bytes(&request_with_headers(DEFAULT_MAX_HEADERS, 1)).await;
+        let (req, _) = Request::from_stream(stream).await.unwrap();
+        assert_eq!(req.headers.len(), DEFAULT_MAX_HEADERS);
+    }
 
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(
-            err.contains("connection closed"),
-            "expected 'connection closed' error, got: {}",
-            err
-        );
+    #[tokio::test]
+    async fn test_from_stream_too_many_headers_is_431() {
+        let stream = stream_from_bytes(&request_with_headers(DEFAULT_MAX_HEADERS + 1, 1)).await;
+        let err = Request::from_stream(stream).await.unwrap_err();
+        assert_status(err, 431);
     }
 
     #[tokio::test]
-    async fn test_from_stream_invalid_request_line_one_part() {
-        let raw = b"INVALID\r\n\r\n";
-        let stream = stream_from_bytes(raw).await;
-        let result = Request::from_stream(stream).await;
+    async fn test_from_stream_huge_header_value_is_431() {
+        let stream = stream_from_bytes(&request_with_headers(1, DEFAULT_MAX_HEADER_BYTES)).await;
+        let err = Request::from_stream(stream).await.unwrap_err();
+        assert_status(err, 431);
+    }
 
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
+    #[tokio::test]
+    async fn test_from_stream_oversized_body_is_413_unread() {
+        let limits = RequestLimits {
+            max_body_size: 8,
+            ..RequestLimits::default()
+        };
+        // The body isn't even sent: the declared length is enough
+        let stream = stream_from_bytes(b"POST /f HTTP/1.1\r\nContent-Length: 9\r\n\r\n").await;
+        let mut reader = BufReader::new(stream);
+        let err = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap_err();
         assert!(
-            err.contains("invalid request line"),
-            "expected 'invalid request line' error, got: {}",
+            err.to_string()
+                .contains("9-byte body exceeds the 8-byte limit"),
+            "{}",
             err
         );
+        assert_status(err, 413);
+
+        let stream =
+            stream_from_bytes(b"POST /f HTTP/1.1\r\nContent-Length: 8\r\n\r\n12345678").await;
+        let mut reader = BufReader::new(stream);
+        let req = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.body.as_deref(), Some(&b"12345678"[..]));
     }
 
     #[tokio::test]
-    async fn test_from_stream_invalid_request_line_two_parts() {
-        let raw = b"GET /\r\n\r\n";
+    async fn test_from_stream_unknown_expectation_is_417() {
+        let raw = b"POST /f HTTP/1.1\r\nExpect: 200-ok\r\nContent-Length: 1\r\n\r\nx";
         let stream = stream_from_bytes(raw).await;
-        let result = Request::from_stream(stream).await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 417);
+    }
 
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(err.contains("invalid request line"));
+    #[tokio::test]
+    async fn test_from_connection_sends_100_continue() {
+        let (mut client, server) = tokio::io::duplex(4096);
+        client
+            .write_all(b"POST /f HTTP/1.1\r\nExpect: 100-Continue\r\nContent-Length: 2\r\n\r\n")
+            .await
+            .unwrap();
+        let parser = tokio::spawn(async move {
+            let mut reader = BufReader::new(server);
+            Request::from_connection_reusing(
+                &mut reader,
+                None,
+                &RequestLimits::default(),
+                &mut Vec::new(),
+            )
+            .await
+            .unwrap()
+            .unwrap()
+        });
+
+        // The body only follows the go-ahead
+        let mut interim = [0u8; 25];
+        client.read_exact(&mut interim).await.unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+        client.write_all(b"hi").await.unwrap();
+        assert_eq!(parser.await.unwrap().body.as_deref(), Some(&b"hi"[..]));
     }
 
     #[tokio::test]
-    async fn test_from_stream_malformed_header() {
-        let raw = b"GET / HTTP/1.1\r\nBadHeaderNoColon\r\n\r\n";
+    async fn test_from_reader_custom_limits() {
+        let limits = RequestLimits {
+            max_headers: 2,
+            max_header_bytes: 64,
+            ..RequestLimits::default()
+        };
+
+        let stream = stream_from_bytes(&request_with_headers(2, 4)).await;
+        let mut reader = BufReader::new(stream);
+        let req = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.headers.len(), 2);
+
+        let stream = stream_from_bytes(&request_with_headers(3, 4)).await;
+        let mut reader = BufReader::new(stream);
+        let err = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap_err();
+        assert_status(err, 431);
+
+        // Total size counts every line, including the terminating blank line
+        let stream = stream_from_bytes(&request_with_headers(1, 64)).await;
+        let mut reader = BufReader::new(stream);
+        let err = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap_err();
+        assert_status(err, 431);
+    }
+
+    // ── from_stream vs from_reader ───────────────────────────────────
+
+    /// Parse `raw` with both entry points and return both results.
+    async fn parse_both(raw: &[u8]) -> (Request, Request) {
+        let (from_stream, _) = Request::from_stream(stream_from_bytes(raw).await)
+            .await
+            .unwrap();
+
         let stream = stream_from_bytes(raw).await;
-        let result = Request::from_stream(stream).await;
+        let peer_addr = stream.peer_addr().ok();
+        let mut reader = BufReader::new(stream);
+        let from_reader = Request::from_reader(&mut reader, peer_addr, &RequestLimits::default())
+            .await
+            .unwrap()
+            .unwrap();
 
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
-        assert!(
-            err.contains("malformed header"),
-            "expected 'malformed header' error, got: {}",
-            err
+        (from_stream, from_reader)
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_and_from_reader_agree() {
+        let cases: [&[u8]; 5] = [
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            b"GET /a%20b?x=1&y HTTP/1.0\r\nUser-Agent: t\r\n\r\n",
+            b"POST /files/f HTTP/1.1\r\nContent-Length: 5\r\nContent-Type: text/plain\r\n\r\nhello",
+            b"DELETE /x HTTP/1.1\nA: 1\nA: 2\n\n",
+            b"OPTIONS * HTTP/1.1\r\n\r\n",
+        ];
+        for raw in cases {
+            let (mut from_stream, mut from_reader) = parse_both(raw).await;
+            // Each parse used its own connection, so only the ports differ
+            assert_eq!(
+                from_stream.peer_addr.take().map(|a| a.ip()),
+                from_reader.peer_addr.take().map(|a| a.ip())
+            );
+            assert_eq!(from_stream, from_reader, "{}", String::from_utf8_lossy(raw));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_from_stream_and_from_reader_reject_alike() {
+        let stream = stream_from_bytes(b"GET / HTTP/3\r\n\r\n").await;
+        assert_status(Request::from_stream(stream).await.unwrap_err(), 505);
+
+        let stream = stream_from_bytes(b"GET / HTTP/3\r\n\r\n").await;
+        let mut reader = BufReader::new(stream);
+        let err = Request::from_reader(&mut reader, None, &RequestLimits::default())
+            .await
+            .unwrap_err();
+        assert_status(err, 505);
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_keeps_pipelined_bytes_buffered() {
+        let raw = b"GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\n\r\n";
+        let mut reader = BufReader::new(stream_from_bytes(raw).await);
+        let limits = RequestLimits::default();
+        let first = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap()
+            .unwrap();
+        let second = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.path, "/one");
+        assert_eq!(second.path, "/two");
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_body_ends_at_content_length() {
+        // The next request starts right after the body, in the same read,
+        // and looks like body bytes to a parser that reads too far
+        let raw = b"POST /one HTTP/1.1\r\nContent-Length: 10\r\n\r\n0123456789\
+                    POST /two HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc\
+                    GET /three HTTP/1.1\r\n\r\n";
+        let mut reader = BufReader::new(stream_from_bytes(raw).await);
+        let limits = RequestLimits::default();
+        let mut next = async || {
+            Request::from_reader(&mut reader, None, &limits)
+                .await
+                .unwrap()
+        };
+
+        let first = next().await.unwrap();
+        assert_eq!(first.path, "/one");
+        assert_eq!(first.body.as_deref(), Some(&b"0123456789"[..]));
+        let second = next().await.unwrap();
+        assert_eq!(second.path, "/two");
+        assert_eq!(second.body.as_deref(), Some(&b"abc"[..]));
+        let third = next().await.unwrap();
+        assert_eq!(
+            (third.method.as_str(), third.path.as_str()),
+            ("GET", "/three")
         );
+        assert_eq!(third.body, None);
+        assert!(next().await.is_none());
     }
 
+    // ── from_reader_reusing ──────────────────────────────────────────
+
     #[tokio::test]
-    async fn test_from_stream_invalid_content_length() {
-        let raw = b"POST /data HTTP/1.1\r\nContent-Length: abc\r\n\r\n";
-        let stream = stream_from_bytes(raw).await;
-        let result = Request::from_stream(stream).await;
+    async fn test_from_reader_reusing_parses_many_sequential_requests() {
+        let mut raw = Vec::new();
+        for i in 0..1000 {
+            if i % 2 == 0 {
+                raw.extend(
+                    format!(
+                        "GET /item/{}?n={} HTTP/1.1\r\nHost: h\r\nX-Seq: {}\r\n\r\n",
+                        i, i, i
+                    )
+                    .into_bytes(),
+                );
+            } else {
+                let body = format!("body-{}", i);
+                raw.extend(
+                    format!(
+                        "POST /item/{} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                        i,
+                        body.len(),
+                        body
+                    )
+                    .into_bytes(),
+                );
+            }
+        }
 
-        assert!(result.is_err());
-        let err = result.unwrap_err().to_string();
+        let stream = stream_from_bytes(&raw).await;
+        let mut reader = BufReader::new(stream);
+        let mut scratch = Vec::new();
+        let limits = RequestLimits::default();
+
+        for i in 0..1000 {
+            let req = Request::from_reader_reusing(&mut reader, None, &limits, &mut scratch)
+                .await
+                .unwrap()
+                .unwrap_or_else(|| panic!("request {} missing", i));
+            assert_eq!(req.path, format!("/item/{}", i));
+            if i % 2 == 0 {
+                assert_eq!(req.method, "GET");
+                assert_eq!(req.query, Some(format!("n={}", i)));
+                assert_eq!(req.header_value("X-Seq"), Some(i.to_string().as_str()));
+                assert_eq!(req.body, None);
+            } else {
+                assert_eq!(req.method, "POST");
+                assert_eq!(req.body, Some(format!("body-{}", i).into_bytes()));
+            }
+        }
+
+        // Clean EOF after the last request
+        let end = Request::from_reader_reusing(&mut reader, None, &limits, &mut scratch)
+            .await
+            .unwrap();
+        assert!(end.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_reusing_keeps_scratch_allocation() {
+        let raw = b"GET /a HTTP/1.1\r\nHost: h\r\n\r\n".repeat(20);
+        let stream = stream_from_bytes(&raw).await;
+        let mut reader = BufReader::new(stream);
+        let mut scratch = Vec::with_capacity(256);
+        let ptr = scratch.as_ptr();
+
+        for _ in 0..20 {
+            Request::from_reader_reusing(
+                &mut reader,
+                None,
+                &RequestLimits::default(),
+                &mut scratch,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        }
+        assert_eq!(scratch.as_ptr(), ptr, "scratch should not be reallocated");
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_reusing_matches_from_reader() {
+        let raw = b"PUT /x%20y?q HTTP/1.0\r\nA: 1\r\nA: 2\r\nContent-Length: 3\r\n\r\nabc";
+        let mut reader = BufReader::new(stream_from_bytes(raw).await);
+        let fresh = Request::from_reader(&mut reader, None, &RequestLimits::default())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Leftovers from a previous, longer line must not leak into the parse
+        let mut scratch = b"stale data that is longer than any line".to_vec();
+        let mut reader = BufReader::new(stream_from_bytes(raw).await);
+        let reused = Request::from_reader_reusing(
+            &mut reader,
+            None,
+            &RequestLimits::default(),
+            &mut scratch,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(reused.method, fresh.method);
+        assert_eq!(reused.path, fresh.path);
+        assert_eq!(reused.query, fresh.query);
+        assert_eq!(reused.http_version, fresh.http_version);
+        assert_eq!(reused.headers, fresh.headers);
+        assert_eq!(reused.body, fresh.body);
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_accepts_any_async_read() {
+        // No socket involved: parse straight from an in-memory byte slice
+        let raw: &[u8] = b"POST /upload HTTP/1.1\r\nHost: h\r\nContent-Length: 2\r\n\r\nhi";
+        let mut reader = BufReader::new(raw);
+        let req = Request::from_reader(&mut reader, None, &RequestLimits::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/upload");
+        assert_eq!(req.body, Some(b"hi".to_vec()));
+        assert_eq!(req.peer_addr, None);
+    }
+
+    // ── Request-line limit ───────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_from_stream_long_request_line_is_414() {
+        let raw = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(DEFAULT_MAX_REQUEST_LINE)
+        );
+        let stream = stream_from_bytes(raw.as_bytes()).await;
+        let err = Request::from_stream(stream).await.unwrap_err();
+        assert_status(err, 414);
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_request_line_at_limit_accepted() {
+        let raw = b"GET /abc HTTP/1.1\r\n\r\n";
+        let limits = RequestLimits {
+            max_request_line: "GET /abc HTTP/1.1\r\n".len(),
+            ..RequestLimits::default()
+        };
+
+        let mut reader = BufReader::new(stream_from_bytes(raw).await);
+        let req = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.path, "/abc");
+
+        let limits = RequestLimits {
+            max_request_line: limits.max_request_line - 1,
+            ..limits
+        };
+        let mut reader = BufReader::new(stream_from_bytes(raw).await);
+        let err = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap_err();
+        assert_status(err, 414);
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_long_line_reads_only_the_limit() {
+        // The over-long line is never buffered past the limit: what's left
+        // in the stream is still unread after the error
+        let limits = RequestLimits {
+            max_request_line: 16,
+            ..RequestLimits::default()
+        };
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(1024));
+        let mut reader = BufReader::new(stream_from_bytes(raw.as_bytes()).await);
+        let err = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap_err();
+        assert_status(err, 414);
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest.len(), raw.len() - 16);
+    }
+
+    // ── Method case ──────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_from_reader_method_case() {
+        let lenient = RequestLimits {
+            case_insensitive_methods: true,
+            ..RequestLimits::default()
+        };
+        let parse = async |raw: &[u8], limits: &RequestLimits| {
+            let mut reader = BufReader::new(stream_from_bytes(raw).await);
+            Request::from_reader(&mut reader, None, limits).await
+        };
+
+        let req = parse(b"gEt / HTTP/1.1\r\n\r\n", &lenient)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.method, "GET");
+        let req = parse(b"get / HTTP/1.1\r\n\r\n", &RequestLimits::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.method, "get");
+
+        // The method checks made while parsing see the uppercased method
+        let req = parse(b"options * HTTP/1.1\r\n\r\n", &lenient)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("OPTIONS", "*"));
+        let err = parse(b"connect example.com:443 HTTP/1.1\r\n\r\n", &lenient)
+            .await
+            .unwrap_err();
+        assert_status(err, 501);
+    }
+
+    // ── Path depth ───────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_from_reader_deep_path_is_400() {
+        let limits = RequestLimits {
+            max_path_depth: 32,
+            ..RequestLimits::default()
+        };
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a/".repeat(200));
+        let mut reader = BufReader::new(stream_from_bytes(raw.as_bytes()).await);
+        let err = Request::from_reader(&mut reader, None, &limits)
+            .await
+            .unwrap_err();
         assert!(
-            err.contains("Content-Length"),
-            "expected Content-Length parse error, got: {}",
+            err.to_string().contains("path exceeds 32 segments"),
+            "{}",
             err
         );
+        assert_status(err, 400);
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_path_depth_counts_normalized_segments() {
+        let limits = RequestLimits {
+            max_path_depth: 2,
+            ..RequestLimits::default()
+        };
+        // Empty and dot segments don't count towards the limit
+        for (path, ok) in [
+            ("/files/a.txt", true),
+            ("/files//./a.txt/", true),
+            ("/files/x/../a.txt", true),
+            ("/files/x/a.txt", false),
+        ] {
+            let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+            let mut reader = BufReader::new(stream_from_bytes(raw.as_bytes()).await);
+            let result = Request::from_reader(&mut reader, None, &limits).await;
+            assert_eq!(result.is_ok(), ok, "{}", path);
+        }
     }
 
     // ── from_stream: stream is returned for writing ──────────────────
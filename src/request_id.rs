@@ -0,0 +1,105 @@
+use std::{
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::request::Request;
+
+/// The header a request ID is read from and echoed back in.
+pub const HEADER: &str = "X-Request-Id";
+
+/// Longest client-supplied ID that's reused rather than replaced.
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+/// Start time of this process, so IDs don't repeat across restarts.
+static PREFIX: LazyLock<u64> = LazyLock::new(|| {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+});
+
+static NEXT: AtomicU64 = AtomicU64::new(1);
+
+/// A new ID, unique within this process: its start time and a counter,
+/// both in hex.
+pub fn generate() -> String {
+    format!("{:x}-{:x}", *PREFIX, NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The ID for `request`: the client's `X-Request-Id` if it sent a usable
+/// one, else a fresh one.
+///
+/// A client ID is only reused if it's at most 128 visible ASCII
+/// characters, since it ends up in log lines and response headers.
+pub fn for_request(request: &Request) -> String {
+    match request.header_value(HEADER) {
+        Some(id) if is_acceptable(id) => id.to_string(),
+        _ => generate(),
+    }
+}
+
+/// How `request` is tagged in log lines: its ID, or `-` if it has none.
+pub fn log_id(request: &Request) -> &str {
+    request.request_id.as_deref().unwrap_or("-")
+}
+
+fn is_acceptable(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_CLIENT_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::HttpVersion;
+
+    fn request_with(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: headers
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    #[test]
+    fn test_generate_is_unique() {
+        let a = generate();
+        let b = generate();
+        assert_ne!(a, b);
+        assert!(is_acceptable(&a), "{}", a);
+    }
+
+    #[test]
+    fn test_for_request_reuses_client_id() {
+        let req = request_with(&[("x-request-id", "abc-123")]);
+        assert_eq!(for_request(&req), "abc-123");
+    }
+
+    #[test]
+    fn test_for_request_generates_without_header() {
+        let id = for_request(&request_with(&[]));
+        assert!(id.contains('-'), "{}", id);
+    }
+
+    #[test]
+    fn test_for_request_replaces_unusable_ids() {
+        let long = "a".repeat(MAX_CLIENT_ID_LEN + 1);
+        for bad in ["", "has space", "tab\there", long.as_str()] {
+            let id = for_request(&request_with(&[(HEADER, bad)]));
+            assert_ne!(id, bad);
+            assert!(is_acceptable(&id), "{}", id);
+        }
+        let longest = "a".repeat(MAX_CLIENT_ID_LEN);
+        assert_eq!(for_request(&request_with(&[(HEADER, &longest)])), longest);
+    }
+}
@@ -1,8 +1,87 @@
-use std::fmt::Write as FmtWrite;
+use std::{
+    fmt,
+    io::{IoSlice, Write as IoWrite},
+};
 
-use anyhow::Result;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use anyhow::{Context, Result, bail};
+use flate2::{
+    Compression,
+    write::{GzEncoder, ZlibEncoder},
+};
+use tokio::fs::File;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::{headers, router::BoxFuture};
+
+/// Value of the `Server` header emitted unless overridden per response.
+pub const DEFAULT_SERVER_NAME: &str = concat!("http-server-rust/", env!("CARGO_PKG_VERSION"));
+
+/// Buffer size used when streaming file bodies to the client. Bounds how much
+/// of a file is held in memory per connection and how much is written per
+/// poll, so one large download can't monopolize the task.
+pub const FILE_COPY_BUF_SIZE: usize = 64 * 1024;
+
+/// Bodies at least this long are written alongside the head with a vectored
+/// write; shorter ones are cheaper to copy into the head buffer.
+pub const MIN_VECTORED_BODY: usize = 4 * 1024;
+
+/// Bodies shorter than this are sent uncompressed; encoding overhead isn't
+/// worth it.
+pub const MIN_COMPRESS_SIZE: usize = 64;
+
+/// gzip and deflate level used unless configured otherwise: zlib's own
+/// default, on a scale from 0 (store only) to 9 (smallest, slowest).
+pub const DEFAULT_COMPRESSION_LEVEL: u32 = 6;
+
+/// Highest gzip and deflate compression level.
+pub const MAX_COMPRESSION_LEVEL: u32 = 9;
+
+/// A client connection a [`Takeover`] can read from and write to.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + ?Sized> Connection for T {}
+
+/// The function type behind a [`Takeover`].
+pub type TakeoverFn =
+    dyn for<'a> FnOnce(&'a mut dyn Connection) -> BoxFuture<'a, Result<()>> + Send + Sync;
+
+/// Callback that drives the connection itself once a response head has been
+/// written, e.g. a WebSocket session after `101 Switching Protocols`.
+pub struct Takeover(Box<TakeoverFn>);
+
+impl Takeover {
+    pub(crate) fn run(self, conn: &mut dyn Connection) -> BoxFuture<'_, Result<()>> {
+        (self.0)(conn)
+    }
+}
+
+impl fmt::Debug for Takeover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Takeover(..)")
+    }
+}
+
+/// A source read for a streamed body: a file, or any other async reader.
+struct BodyReader(Box<dyn AsyncRead + Send + Sync + Unpin>);
+
+impl fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BodyReader(..)")
+    }
+}
+
+/// How much of a streamed body to send, and how the client learns where it
+/// ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyLength {
+    /// Exactly this many bytes, announced in `Content-Length`.
+    Known(u64),
+    /// Up to the end of the source, in chunked transfer coding.
+    Chunked,
+    /// Up to the end of the source, ended by closing the connection.
+    UntilClose,
+}
 
 #[derive(Debug)]
 pub struct Response {
@@ -11,9 +90,26 @@ pub struct Response {
     headers: Vec<(String, String)>,
     body: Vec<u8>,
 
-    /// When true, only write the status line and the terminating CRLF CRLF
-    /// e.g. "HTTP/1.1 404 Not Found\r\n\r\n"
+    /// Source streamed after the headers instead of `body`, so large files
+    /// (or other readers) don't have to be buffered in memory, and how much
+    /// of it to send from its current position.
+    streamed: Option<(BodyReader, BodyLength)>,
+
+    /// When true, only write the status line, any explicitly set headers and
+    /// the terminating CRLF CRLF — no body and no auto-injected headers
+    /// beyond the `Content-Length: 0` that frames statuses allowed a body,
+    /// e.g. "HTTP/1.1 204 No Content\r\n\r\n"
     status_only: bool,
+
+    /// Value for the auto-injected `Server` header; empty suppresses it.
+    server_name: String,
+
+    /// Runs on the connection after the head instead of a body being sent.
+    takeover: Option<Takeover>,
+
+    /// Released once the response has been written, so a limit on what it
+    /// holds (an open file) covers streaming it.
+    permit: Option<OwnedSemaphorePermit>,
 }
 
 impl Response {
@@ -23,7 +119,11 @@ impl Response {
             reason: reason.to_owned(),
             headers: Vec::new(),
             body: Vec::new(),
+            streamed: None,
             status_only: false,
+            server_name: DEFAULT_SERVER_NAME.to_owned(),
+            takeover: None,
+            permit: None,
         }
     }
 
@@ -35,6 +135,23 @@ impl Response {
         r
     }
 
+    /// Convenience: 200 response carrying `json`, which must already be
+    /// serialized. No charset is declared, since JSON is always UTF-8.
+    pub fn json(json: &str) -> Self {
+        let mut r = Self::new(200, "OK");
+        r.header("Content-Type", "application/json")
+            .body_bytes(json.as_bytes().to_vec());
+        r
+    }
+
+    /// Like [`ok_text`](Self::ok_text), declaring the body's `charset` in
+    /// the `Content-Type`.
+    pub fn ok_text_with_charset(body: &str, charset: &str) -> Self {
+        let mut r = Self::ok_text(body);
+        r.set_charset(charset);
+        r
+    }
+
     /// Convenience: 404 response with a plain-text body.
     pub fn not_found() -> Self {
         let mut r = Self::new(404, "Not Found");
@@ -43,20 +160,88 @@ impl Response {
         r
     }
 
+    /// Convenience: error response whose plain-text body is the reason phrase.
+    pub fn error(status_code: u16, reason: &str) -> Self {
+        let mut r = Self::new(status_code, reason);
+        r.header("Content-Type", "text/plain")
+            .body_bytes(reason.as_bytes().to_vec());
+        r
+    }
+
     /// Convenience: 201 Created with no body.
     pub fn created() -> Self {
         Self::status_only(201, "Created")
     }
 
-    /// Construct a "status only" response that will be written exactly as:
-    /// `HTTP/1.1 {status} {reason}\r\n\r\n`
+    /// Convenience: 201 Created pointing at `location`, with an empty body.
+    pub fn created_at(location: &str) -> Self {
+        let mut r = Self::new(201, "Created");
+        r.header("Location", location);
+        r
+    }
+
+    /// Convenience: redirect to `location` with an empty body.
+    ///
+    /// # Panics
+    ///
+    /// If `status_code` is not one of 301, 302, 303, 307 or 308.
+    pub fn redirect(status_code: u16, location: &str) -> Self {
+        let reason = match status_code {
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            _ => panic!("not a redirect status code: {}", status_code),
+        };
+        let mut r = Self::new(status_code, reason);
+        r.header("Location", location);
+        r
+    }
+
+    /// Convenience: 204 No Content.
+    pub fn no_content() -> Self {
+        Self::status_only(204, "No Content")
+    }
+
+    /// Convenience: 304 Not Modified, with no body.
+    pub fn not_modified() -> Self {
+        Self::status_only(304, "Not Modified")
+    }
+
+    /// Convenience: a 1xx interim response such as `100 Continue` or
+    /// `103 Early Hints`, carrying only the headers set on it. Send it with
+    /// [`write_interim`](Self::write_interim).
+    ///
+    /// # Panics
+    ///
+    /// If `status_code` is not in the 1xx range.
+    pub fn informational(status_code: u16, reason: &str) -> Self {
+        assert!(
+            (100..200).contains(&status_code),
+            "not an informational status code: {}",
+            status_code
+        );
+        let mut resp = Self::status_only(status_code, reason);
+        resp.server_name.clear();
+        resp
+    }
+
+    /// Construct a "status only" response that will be written as just the
+    /// status line and the `Server` header, plus `Content-Length: 0` for
+    /// statuses other than 1xx, 204 and 304 so the (empty) body is
+    /// unambiguously framed on a keep-alive connection.
     pub fn status_only(status_code: u16, reason: &str) -> Self {
         Self {
             status_code,
             reason: reason.to_owned(),
             headers: Vec::new(),
             body: Vec::new(),
+            streamed: None,
             status_only: true,
+            server_name: DEFAULT_SERVER_NAME.to_owned(),
+            takeover: None,
+            permit: None,
         }
     }
 
@@ -66,79 +251,430 @@ impl Response {
         self
     }
 
+    /// Replace every value of a header (case-insensitive) with `value`.
+    /// Returns `&mut Self` for chaining.
+    pub fn set_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self.header(key, value)
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status_code
+    }
+
+    /// All values set for a header (case-insensitive), in insertion order.
+    /// Useful for headers that may repeat, such as `Set-Cookie`.
+    pub fn headers_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
     /// Set the response body from raw bytes. Returns `&mut Self` for chaining.
     pub fn body_bytes(&mut self, bytes: Vec<u8>) -> &mut Self {
         self.body = bytes;
         self
     }
 
+    /// Owned form of [`header`](Self::header), for building a response in
+    /// one expression: `Response::new(200, "OK").with_header(..).with_body(..)`.
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.header(key, value);
+        self
+    }
+
+    /// Owned form of [`body_bytes`](Self::body_bytes).
+    pub fn with_body(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.body_bytes(bytes.into());
+        self
+    }
+
+    /// Replace the status code and reason phrase, e.g. to reuse a
+    /// constructor's headers under another status.
+    pub fn with_status(mut self, status_code: u16, reason: &str) -> Self {
+        self.status_code = status_code;
+        self.reason = reason.to_owned();
+        self
+    }
+
+    /// Stream `len` bytes of `file`, from its current position, as the body
+    /// and advertise them as the `Content-Length`. Seek first to send a
+    /// slice of the file. Returns `&mut Self` for chaining.
+    pub fn file_body(&mut self, file: File, len: u64) -> &mut Self {
+        self.stream_from(file, Some(len))
+    }
+
+    /// Stream `reader` as the body: `length` bytes of it announced in
+    /// `Content-Length`, or with `None`, all of it in chunked transfer coding
+    /// (ended by closing the connection instead for HTTP/1.0 clients). Nothing
+    /// is read until the response is written. Returns `&mut Self` for
+    /// chaining.
+    pub fn stream_from<R>(&mut self, reader: R, length: Option<u64>) -> &mut Self
+    where
+        R: AsyncRead + Send + Sync + Unpin + 'static,
+    {
+        self.headers.retain(|(k, _)| {
+            !k.eq_ignore_ascii_case("content-length")
+                && !k.eq_ignore_ascii_case("transfer-encoding")
+        });
+        match length {
+            Some(len) => {
+                self.streamed = Some((BodyReader(Box::new(reader)), BodyLength::Known(len)));
+                self.header("Content-Length", &len.to_string())
+            }
+            None => {
+                self.streamed = Some((BodyReader(Box::new(reader)), BodyLength::Chunked));
+                self.header("Transfer-Encoding", "chunked")
+            }
+        }
+    }
+
+    /// Keep `permit` until the response has been written (or dropped
+    /// unsent). Returns `&mut Self` for chaining.
+    pub fn hold_until_sent(&mut self, permit: OwnedSemaphorePermit) -> &mut Self {
+        self.permit = Some(permit);
+        self
+    }
+
+    /// Stream `file` to its end in chunked transfer coding, for pipes and
+    /// other files whose length isn't known up front. HTTP/1.1 only; see
+    /// [`file_body_until_close`](Self::file_body_until_close) for older
+    /// clients. Returns `&mut Self` for chaining.
+    pub fn chunked_file_body(&mut self, file: File) -> &mut Self {
+        self.stream_from(file, None)
+    }
+
+    /// Stream `file` to its end with no length given, so the connection
+    /// has to close to mark the end of the body. The fallback for HTTP/1.0
+    /// clients, which don't understand chunked coding. Returns `&mut Self`
+    /// for chaining.
+    pub fn file_body_until_close(&mut self, file: File) -> &mut Self {
+        self.streamed = Some((BodyReader(Box::new(file)), BodyLength::UntilClose));
+        self.headers
+            .retain(|(k, _)| !k.eq_ignore_ascii_case("content-length"));
+        self
+    }
+
+    /// Send a chunked body as one that ends when the connection closes, for
+    /// HTTP/1.0 clients, which don't understand chunked coding.
+    pub(crate) fn close_delimit_chunked(&mut self) -> &mut Self {
+        if let Some((_, length @ BodyLength::Chunked)) = &mut self.streamed {
+            *length = BodyLength::UntilClose;
+            self.headers
+                .retain(|(k, _)| !k.eq_ignore_ascii_case("transfer-encoding"));
+        }
+        self
+    }
+
+    /// Whether the body ends only when the connection closes, so it can't
+    /// be kept open for another request.
+    pub fn is_close_delimited(&self) -> bool {
+        matches!(self.streamed, Some((_, BodyLength::UntilClose)))
+    }
+
+    /// Append `; charset=<charset>` to a `text/*` `Content-Type` that
+    /// doesn't declare one. Other types, binary ones included, are left
+    /// alone. Returns `&mut Self` for chaining.
+    pub fn set_charset(&mut self, charset: &str) -> &mut Self {
+        for (k, v) in &mut self.headers {
+            if !k.eq_ignore_ascii_case("content-type") {
+                continue;
+            }
+            let mut params = v.split(';');
+            let is_text = params.next().is_some_and(|media_type| {
+                media_type
+                    .trim()
+                    .get(..5)
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case("text/"))
+            });
+            let has_charset = params.any(|p| {
+                p.split_once('=')
+                    .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            });
+            if is_text && !has_charset {
+                v.push_str("; charset=");
+                v.push_str(charset);
+            }
+        }
+        self
+    }
+
+    /// Drop the body but keep advertising its length (or chunked coding),
+    /// as the answer to a `HEAD` request. Returns `&mut Self` for chaining.
+    pub fn strip_body(&mut self) -> &mut Self {
+        if self.streamed.take().is_none()
+            && self.sends_body()
+            && !self.headers.iter().any(|(k, _)| {
+                k.eq_ignore_ascii_case("content-length")
+                    || k.eq_ignore_ascii_case("transfer-encoding")
+            })
+        {
+            let len = self.body.len().to_string();
+            self.header("Content-Length", &len);
+        }
+        self.body.clear();
+        self
+    }
+
+    /// Override the `Server` header value. An empty name suppresses the header.
+    /// Returns `&mut Self` for chaining.
+    pub fn server_name(&mut self, name: &str) -> &mut Self {
+        self.server_name = name.to_owned();
+        self
+    }
+
+    /// Hand the connection to `f` once the head is written, instead of
+    /// sending a body. No `Content-Length` is added, and the connection is
+    /// closed when `f` returns. Returns `&mut Self` for chaining.
+    pub fn take_over<F>(&mut self, f: F) -> &mut Self
+    where
+        F: for<'a> FnOnce(&'a mut dyn Connection) -> BoxFuture<'a, Result<()>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.takeover = Some(Takeover(Box::new(f)));
+        self
+    }
+
+    /// Remove and return the callback set by [`take_over`](Self::take_over).
+    pub(crate) fn take_takeover(&mut self) -> Option<Takeover> {
+        self.takeover.take()
+    }
+
+    /// Compress the body with the best coding the client accepts: br, gzip
+    /// or deflate, by `Accept-Encoding` q-value, preferring them in that
+    /// order when the client rates them equally.
+    ///
+    /// Does nothing for responses without a body, bodies shorter than
+    /// [`MIN_COMPRESS_SIZE`], partial content (its byte offsets refer to the
+    /// uncompressed file), responses that already carry a
+    /// `Content-Encoding`, or when `accept_encoding` lists none of them.
+    /// Any explicit `Content-Length` is dropped so the compressed length is
+    /// injected at serialization time.
+    pub fn maybe_compress(&mut self, accept_encoding: Option<&str>) -> &mut Self {
+        self.maybe_compress_at(accept_encoding, DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    /// Like [`maybe_compress`](Self::maybe_compress), with gzip and deflate
+    /// at `level` (0–9, clamped) instead of the default. Brotli keeps its
+    /// fixed quality.
+    pub fn maybe_compress_at(&mut self, accept_encoding: Option<&str>, level: u32) -> &mut Self {
+        if !self.sends_body()
+            || self.status_code == 206
+            || self.body.len() < MIN_COMPRESS_SIZE
+            || self
+                .headers
+                .iter()
+                .any(|(k, _)| k.eq_ignore_ascii_case("content-encoding"))
+        {
+            return self;
+        }
+        let Some(coding) = accept_encoding.and_then(ContentCoding::negotiate) else {
+            return self;
+        };
+
+        let level = Compression::new(level.min(MAX_COMPRESSION_LEVEL));
+        let compressed = coding.encode(&self.body, level);
+        self.headers
+            .retain(|(k, _)| !k.eq_ignore_ascii_case("content-length"));
+        self.header("Content-Encoding", coding.name())
+            .header("Vary", "Accept-Encoding")
+            .body_bytes(compressed)
+    }
+
     // ── Serialization helpers (shared logic) ─────────────────────────
 
-    /// Write the status line and headers into a pre-allocated `String`,
-    /// optionally injecting a `Content-Length` header when one is missing.
+    /// Whether the status code rules out a body (and so a `Content-Length`):
+    /// 1xx, 204 and 304.
+    fn forbids_body(&self) -> bool {
+        matches!(self.status_code, 100..=199 | 204 | 304)
+    }
+
+    /// Whether the body is written at all. A body set on a status that
+    /// forbids one is silently dropped rather than corrupting the framing.
+    fn sends_body(&self) -> bool {
+        !self.status_only && !self.forbids_body()
+    }
+
+    /// Append the status line and headers to `buf`, optionally injecting a
+    /// `Content-Length` header when one is missing.
     ///
     /// When `include_content_length` is `true` and no explicit
-    /// `Content-Length` header exists, `self.body.len()` is used.
-    fn write_head(&self, buf: &mut String, include_content_length: bool) {
-        // Status line
+    /// `Content-Length` header exists, `self.body.len()` is used, except for
+    /// statuses that can't have a body (1xx, 204, 304). A `Server`
+    /// header is injected the same way unless one was set explicitly or the
+    /// server name is empty.
+    fn write_head(&self, buf: &mut Vec<u8>, include_content_length: bool) {
+        // Status line (writing into a Vec can't fail)
         let _ = write!(buf, "HTTP/1.1 {} {}\r\n", self.status_code, self.reason);
 
-        if self.status_only {
-            buf.push_str("\r\n");
-            return;
-        }
-
-        // Headers
+        // Headers. Framing headers are left off statuses that can't have a
+        // body, whatever the handler set.
         let mut has_content_length = false;
+        let mut has_server = false;
         for (k, v) in &self.headers {
+            if self.forbids_body()
+                && (k.eq_ignore_ascii_case("content-length")
+                    || k.eq_ignore_ascii_case("transfer-encoding"))
+            {
+                continue;
+            }
             if k.eq_ignore_ascii_case("content-length") {
                 has_content_length = true;
+            } else if k.eq_ignore_ascii_case("server") {
+                has_server = true;
             }
             let _ = write!(buf, "{}: {}\r\n", k, v);
         }
 
-        if include_content_length && !has_content_length {
+        if !has_server && !self.server_name.is_empty() {
+            let _ = write!(buf, "Server: {}\r\n", self.server_name);
+        }
+
+        if self.status_only {
+            if include_content_length && !has_content_length && !self.forbids_body() {
+                buf.extend_from_slice(b"Content-Length: 0\r\n");
+            }
+            buf.extend_from_slice(b"\r\n");
+            return;
+        }
+
+        if include_content_length && !has_content_length && !self.forbids_body() {
             let _ = write!(buf, "Content-Length: {}\r\n", self.body.len());
         }
 
         // Blank line terminates headers
-        buf.push_str("\r\n");
+        buf.extend_from_slice(b"\r\n");
+    }
+
+    /// Append the serialized response to `buf`: headers plus the buffered
+    /// body. A streamed body isn't included; it is sent separately.
+    ///
+    /// Lets a connection serialize every response into one reused buffer
+    /// instead of allocating a fresh one each time.
+    pub(crate) fn write_to_buf(&self, buf: &mut Vec<u8>) {
+        buf.reserve(128 + self.headers.len() * 48 + self.body.len());
+        self.write_head(buf, true);
+        if self.sends_body() {
+            buf.extend_from_slice(&self.body);
+        }
     }
 
     /// Build full response (headers + body) as bytes.
+    #[cfg(test)]
     pub(crate) fn build_raw(&self) -> Vec<u8> {
-        // Estimate: status + headers + body
-        let estimated = 128 + self.headers.len() * 48 + self.body.len();
-        let mut head = String::with_capacity(estimated);
-        self.write_head(&mut head, true);
-
-        let mut raw = head.into_bytes();
-        if !self.status_only {
-            raw.extend_from_slice(&self.body);
-        }
+        let mut raw = Vec::new();
+        self.write_to_buf(&mut raw);
         raw
     }
 
     /// Build header-only bytes (status line + headers + blank line).
     pub(crate) fn build_headers_raw(&self) -> Vec<u8> {
-        let mut head = String::with_capacity(128 + self.headers.len() * 48);
+        let mut head = Vec::with_capacity(128 + self.headers.len() * 48);
         self.write_head(&mut head, true);
-        head.into_bytes()
+        head
     }
 
     // ── Public write methods ─────────────────────────────────────────
 
-    /// Write full response (headers + body) to the stream.
-    pub async fn write_to(&self, stream: &mut TcpStream) -> Result<()> {
-        let raw = self.build_raw();
-        stream.write_all(&raw).await?;
+    /// Write full response (headers + body) to the stream, streaming the
+    /// file or reader body when one was set. Consumes the response since a
+    /// streamed body can only be read once.
+    ///
+    /// Returns the number of body bytes written (headers not included).
+    pub async fn write_to<W: AsyncWrite + Unpin>(self, stream: &mut W) -> Result<u64> {
+        self.write_to_reusing(stream, &mut Vec::new()).await
+    }
+
+    /// [`write_to`](Self::write_to), serializing into `buf` (cleared first)
+    /// so its allocation can be reused across responses.
+    pub(crate) async fn write_to_reusing<W: AsyncWrite + Unpin>(
+        mut self,
+        stream: &mut W,
+        buf: &mut Vec<u8>,
+    ) -> Result<u64> {
+        buf.clear();
+        if self.forbids_body() {
+            self.streamed = None;
+        }
+        let Some((BodyReader(reader), len)) = self.streamed.take() else {
+            if !self.sends_body() || self.body.len() < MIN_VECTORED_BODY {
+                self.write_to_buf(buf);
+                stream.write_all(buf).await?;
+            } else {
+                // Send the head and the body side by side instead of copying
+                // the body in behind the head
+                self.write_head(buf, true);
+                write_all_vectored(stream, &mut [IoSlice::new(buf), IoSlice::new(&self.body)])
+                    .await?;
+            }
+            stream.flush().await?;
+            let body_len = if self.sends_body() {
+                self.body.len()
+            } else {
+                0
+            };
+            return Ok(body_len as u64);
+        };
+
+        // A streamed body's length is either set explicitly or not known at
+        // all
+        self.write_head(buf, false);
+        stream.write_all(buf).await?;
+
+        let written = match len {
+            BodyLength::Known(len) => {
+                let mut reader = BufReader::with_capacity(FILE_COPY_BUF_SIZE, reader.take(len));
+                io::copy_buf(&mut reader, stream).await
+            }
+            BodyLength::UntilClose => {
+                let mut reader = BufReader::with_capacity(FILE_COPY_BUF_SIZE, reader);
+                io::copy_buf(&mut reader, stream).await
+            }
+            BodyLength::Chunked => write_chunked(reader, stream).await,
+        }
+        .context("streaming body")?;
+        stream.flush().await?;
+        Ok(written)
+    }
+
+    /// Write this 1xx response ahead of the final one. Unlike
+    /// [`write_to`](Self::write_to) it doesn't end the exchange: the final
+    /// response for the same request is written to `stream` afterwards.
+    ///
+    /// Fails without writing anything if the status isn't 1xx.
+    pub async fn write_interim<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> Result<()> {
+        if !(100..200).contains(&self.status_code) {
+            bail!("interim responses must be 1xx, not {}", self.status_code);
+        }
+        // A 1xx never has a body, so never a Content-Length either
+        let mut head = Vec::with_capacity(64 + self.headers.len() * 48);
+        self.write_head(&mut head, false);
+        stream.write_all(&head).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+
+    /// Write the head of a response whose connection is being taken over:
+    /// no `Content-Length`, since what follows isn't a sized body.
+    pub(crate) async fn write_takeover_head<W: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut W,
+        buf: &mut Vec<u8>,
+    ) -> Result<()> {
+        buf.clear();
+        self.write_head(buf, false);
+        stream.write_all(buf).await?;
         stream.flush().await?;
         Ok(())
     }
 
     /// Write only the headers (status line + headers + blank line) to the stream.
     /// Useful when the body will be streamed separately (e.g. from a file).
-    pub async fn write_headers(&self, stream: &mut TcpStream) -> Result<()> {
+    pub async fn write_headers<W: AsyncWrite + Unpin>(&self, stream: &mut W) -> Result<()> {
         let raw = self.build_headers_raw();
         stream.write_all(&raw).await?;
         stream.flush().await?;
@@ -146,9 +682,134 @@ impl Response {
     }
 }
 
+/// Write every byte of `bufs`, using vectored writes when the stream
+/// supports them and falling back to one `write_all` per slice otherwise.
+async fn write_all_vectored<W>(stream: &mut W, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    if !stream.is_write_vectored() {
+        for buf in bufs.iter() {
+            stream.write_all(buf).await?;
+        }
+        return Ok(());
+    }
+
+    while !bufs.is_empty() {
+        let n = stream.write_vectored(bufs).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut bufs, n);
+    }
+    Ok(())
+}
+
+/// Copy `reader` to `stream` in chunked transfer coding (RFC 9112 §7.1),
+/// a chunk per read, flushing each so slow sources arrive as they're
+/// produced. Returns the payload bytes written, framing not included.
+async fn write_chunked<R, W>(mut reader: R, stream: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut chunk = vec![0u8; FILE_COPY_BUF_SIZE];
+    let mut written = 0;
+    loop {
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        let size = format!("{:x}\r\n", n);
+        write_all_vectored(
+            stream,
+            &mut [
+                IoSlice::new(size.as_bytes()),
+                IoSlice::new(&chunk[..n]),
+                IoSlice::new(b"\r\n"),
+            ],
+        )
+        .await?;
+        stream.flush().await?;
+        written += n as u64;
+    }
+    // The last chunk is empty, with no trailers after it
+    stream.write_all(b"0\r\n\r\n").await?;
+    Ok(written)
+}
+
+/// Content codings [`Response::maybe_compress`] can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentCoding {
+    Br,
+    Gzip,
+    Deflate,
+}
+
+impl ContentCoding {
+    /// Every coding, most preferred first.
+    const ALL: [Self; 3] = [Self::Br, Self::Gzip, Self::Deflate];
+
+    /// Brotli quality for on-the-fly compression: well short of the maximum
+    /// (11), which is far too slow per request.
+    const BROTLI_QUALITY: u32 = 5;
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Br => "br",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Pick the coding with the highest q-value in an `Accept-Encoding`
+    /// value. Ties go to the earlier coding in [`Self::ALL`]; `None` if all
+    /// are unacceptable.
+    fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut best: Option<(Self, f32)> = None;
+        for coding in Self::ALL {
+            let q = headers::coding_quality(accept_encoding, coding.name());
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((coding, q));
+            }
+        }
+        best.map(|(coding, _)| coding)
+    }
+
+    fn encode(self, body: &[u8], level: Compression) -> Vec<u8> {
+        // Writing into a Vec can't fail
+        match self {
+            Self::Br => {
+                let mut encoder =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, Self::BROTLI_QUALITY, 22);
+                encoder
+                    .write_all(body)
+                    .expect("in-memory brotli compression");
+                encoder.into_inner()
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder
+                    .write_all(body)
+                    .and_then(|_| encoder.finish())
+                    .expect("in-memory gzip compression")
+            }
+            Self::Deflate => {
+                // HTTP's "deflate" is the zlib format (RFC 9110 §8.4.1.2)
+                let mut encoder = ZlibEncoder::new(Vec::new(), level);
+                encoder
+                    .write_all(body)
+                    .and_then(|_| encoder.finish())
+                    .expect("in-memory deflate compression")
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::net::TcpStream;
 
     // ── Constructor tests ────────────────────────────────────────────
 
@@ -160,6 +821,7 @@ mod tests {
         assert!(r.headers.is_empty());
         assert!(r.body.is_empty());
         assert!(!r.status_only);
+        assert_eq!(r.server_name, DEFAULT_SERVER_NAME);
     }
 
     #[test]
@@ -177,6 +839,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_json() {
+        let body = r#"{"id":7,"tags":["a","b"],"name":"héllo"}"#;
+        let r = Response::json(body);
+        assert_eq!(r.status_code, 200);
+        assert_eq!(r.headers_all("Content-Type"), vec!["application/json"]);
+        assert_eq!(r.body, body.as_bytes());
+
+        let raw = r.build_raw();
+        let expected_len = format!("Content-Length: {}\r\n", body.len());
+        assert!(String::from_utf8_lossy(&raw).contains(&expected_len));
+        assert!(raw.ends_with(body.as_bytes()));
+    }
+
+    #[test]
+    fn test_ok_text_with_charset() {
+        let r = Response::ok_text_with_charset("héllo", "utf-8");
+        assert_eq!(
+            r.headers_all("Content-Type"),
+            vec!["text/plain; charset=utf-8"]
+        );
+        assert_eq!(r.body, "héllo".as_bytes());
+    }
+
+    #[test]
+    fn test_set_charset_only_touches_text_types() {
+        for (content_type, expected) in [
+            ("text/html", "text/html; charset=utf-8"),
+            ("Text/CSS", "Text/CSS; charset=utf-8"),
+            (
+                "text/plain; format=flowed",
+                "text/plain; format=flowed; charset=utf-8",
+            ),
+            ("text/plain; charset=latin1", "text/plain; charset=latin1"),
+            ("text/plain;Charset=ascii", "text/plain;Charset=ascii"),
+            ("application/octet-stream", "application/octet-stream"),
+            ("image/png", "image/png"),
+            ("application/json", "application/json"),
+        ] {
+            let mut r = Response::new(200, "OK");
+            r.header("Content-Type", content_type).set_charset("utf-8");
+            assert_eq!(r.headers_all("Content-Type"), vec![expected]);
+        }
+    }
+
+    #[test]
+    fn test_set_charset_without_content_type() {
+        let mut r = Response::no_content();
+        r.set_charset("utf-8");
+        assert!(r.headers_all("Content-Type").is_empty());
+    }
+
     #[test]
     fn test_ok_text_empty_body() {
         let r = Response::ok_text("");
@@ -197,6 +911,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_error() {
+        let r = Response::error(505, "HTTP Version Not Supported");
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        assert!(raw.contains("Content-Type: text/plain\r\n"));
+        assert!(raw.ends_with("\r\n\r\nHTTP Version Not Supported"));
+    }
+
     #[test]
     fn test_status_only() {
         let r = Response::status_only(404, "Not Found");
@@ -207,6 +930,55 @@ mod tests {
         assert!(r.status_only);
     }
 
+    #[test]
+    fn test_redirect_supported_codes() {
+        for (code, reason) in [
+            (301, "Moved Permanently"),
+            (302, "Found"),
+            (303, "See Other"),
+            (307, "Temporary Redirect"),
+            (308, "Permanent Redirect"),
+        ] {
+            let r = Response::redirect(code, "/index.html");
+            let raw = String::from_utf8(r.build_raw()).unwrap();
+            assert!(
+                raw.starts_with(&format!("HTTP/1.1 {} {}\r\n", code, reason)),
+                "raw: {}",
+                raw
+            );
+            assert!(raw.contains("Location: /index.html\r\n"));
+            assert!(raw.ends_with("Content-Length: 0\r\n\r\n"));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not a redirect status code: 200")]
+    fn test_redirect_rejects_non_3xx() {
+        Response::redirect(200, "/");
+    }
+
+    #[test]
+    #[should_panic(expected = "not a redirect status code: 304")]
+    fn test_redirect_rejects_304() {
+        Response::redirect(304, "/");
+    }
+
+    #[test]
+    fn test_informational_is_bare() {
+        let mut r = Response::informational(103, "Early Hints");
+        r.header("Link", "</style.css>; rel=preload; as=style");
+        assert_eq!(
+            String::from_utf8(r.build_raw()).unwrap(),
+            "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload; as=style\r\n\r\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "not an informational status code: 200")]
+    fn test_informational_rejects_non_1xx() {
+        Response::informational(200, "OK");
+    }
+
     // ── Builder chaining tests ───────────────────────────────────────
 
     #[test]
@@ -223,6 +995,16 @@ mod tests {
         assert_eq!(r.headers[1], ("X-Custom".to_owned(), "value".to_owned()));
     }
 
+    #[test]
+    fn test_set_header_replaces_existing() {
+        let mut r = Response::ok_text("x");
+        r.header("X-A", "1").set_header("content-type", "text/html");
+        assert_eq!(r.headers_all("Content-Type"), vec!["text/html"]);
+        assert_eq!(r.headers_all("X-A"), vec!["1"]);
+        r.set_header("X-B", "2");
+        assert_eq!(r.headers_all("X-B"), vec!["2"]);
+    }
+
     #[test]
     fn test_body_bytes_chaining() {
         let mut r = Response::new(200, "OK");
@@ -241,6 +1023,62 @@ mod tests {
         assert_eq!(r.body, b"second");
     }
 
+    #[test]
+    fn test_owned_builder_matches_mutable_style() {
+        let mut mutable = Response::new(200, "OK");
+        mutable
+            .header("Content-Type", "application/json")
+            .header("X-Custom", "value")
+            .body_bytes(b"{\"ok\":true}".to_vec());
+        let owned = Response::new(200, "OK")
+            .with_header("Content-Type", "application/json")
+            .with_header("X-Custom", "value")
+            .with_body("{\"ok\":true}");
+        assert_eq!(owned.build_raw(), mutable.build_raw());
+
+        // Both styles mix, in either order
+        let mut mixed = Response::new(200, "OK").with_header("Content-Type", "application/json");
+        mixed.header("X-Custom", "value");
+        let mixed = mixed.with_body(b"{\"ok\":true}".to_vec());
+        assert_eq!(mixed.build_raw(), mutable.build_raw());
+    }
+
+    #[test]
+    fn test_with_status_keeps_headers_and_body() {
+        let r = Response::ok_text("slow down").with_status(429, "Too Many Requests");
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert!(
+            raw.starts_with("HTTP/1.1 429 Too Many Requests\r\n"),
+            "{}",
+            raw
+        );
+        assert!(raw.contains("Content-Type: text/plain\r\n"));
+        assert!(raw.ends_with("\r\n\r\nslow down"));
+
+        let mut expected = Response::new(429, "Too Many Requests");
+        expected
+            .header("Content-Type", "text/plain")
+            .body_bytes(b"slow down".to_vec());
+        assert_eq!(r.build_raw(), expected.build_raw());
+    }
+
+    #[test]
+    fn test_headers_all_repeated_header() {
+        let mut r = Response::new(200, "OK");
+        r.header("Set-Cookie", "a=1")
+            .header("Content-Type", "text/plain")
+            .header("set-cookie", "b=2");
+
+        assert_eq!(r.headers_all("Set-Cookie"), vec!["a=1", "b=2"]);
+        assert_eq!(r.headers_all("content-type"), vec!["text/plain"]);
+        assert!(r.headers_all("Location").is_empty());
+
+        // Both are written out as separate header lines
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert!(raw.contains("Set-Cookie: a=1\r\n"));
+        assert!(raw.contains("set-cookie: b=2\r\n"));
+    }
+
     // ── Serialization: build_raw ─────────────────────────────────────
 
     #[test]
@@ -274,8 +1112,14 @@ mod tests {
         let raw = r.build_raw();
         let text = String::from_utf8(raw).unwrap();
 
-        // Exact output: status line + CRLF CRLF, nothing else
-        assert_eq!(text, "HTTP/1.1 404 Not Found\r\n\r\n");
+        // Exact output: status line, Server, the empty body's length, CRLF CRLF
+        assert_eq!(
+            text,
+            format!(
+                "HTTP/1.1 404 Not Found\r\nServer: {}\r\nContent-Length: 0\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
     }
 
     #[test]
@@ -283,7 +1127,13 @@ mod tests {
         let r = Response::status_only(204, "No Content");
         let raw = r.build_raw();
         let text = String::from_utf8(raw).unwrap();
-        assert_eq!(text, "HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(
+            text,
+            format!(
+                "HTTP/1.1 204 No Content\r\nServer: {}\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
     }
 
     #[test]
@@ -358,7 +1208,264 @@ mod tests {
         assert_eq!(&raw[body_start..], &binary);
     }
 
-    // ── Serialization: build_headers_raw ─────────────────────────────
+    #[test]
+    fn test_build_raw_server_header_once_with_crate_version() {
+        let expected = format!("Server: http-server-rust/{}\r\n", env!("CARGO_PKG_VERSION"));
+        for r in [
+            Response::ok_text("hi"),
+            Response::no_content(),
+            Response::not_modified(),
+        ] {
+            let text = String::from_utf8(r.build_raw()).unwrap();
+            assert!(text.contains(&expected), "missing Server header: {}", text);
+            assert_eq!(text.matches("Server:").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_build_raw_custom_server_name() {
+        let mut r = Response::ok_text("hi");
+        r.server_name("custom/9.9");
+        let text = String::from_utf8(r.build_raw()).unwrap();
+
+        assert!(text.contains("Server: custom/9.9\r\n"));
+        assert_eq!(text.matches("Server:").count(), 1);
+    }
+
+    #[test]
+    fn test_build_raw_empty_server_name_suppresses_header() {
+        let mut r = Response::ok_text("hi");
+        r.server_name("");
+        let text = String::from_utf8(r.build_raw()).unwrap();
+
+        assert!(!text.contains("Server:"));
+    }
+
+    #[test]
+    fn test_build_raw_explicit_server_header_not_duplicated() {
+        let mut r = Response::ok_text("hi");
+        r.header("Server", "explicit");
+        let text = String::from_utf8(r.build_raw()).unwrap();
+
+        assert!(text.contains("Server: explicit\r\n"));
+        assert_eq!(text.matches("Server:").count(), 1);
+    }
+
+    // ── maybe_compress ───────────────────────────────────────────────
+
+    fn gunzip(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    const LONG_TEXT: &str =
+        "the quick brown fox jumps over the lazy dog, again and again and again";
+
+    #[test]
+    fn test_maybe_compress_round_trip() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.maybe_compress(Some("gzip"));
+
+        assert!(
+            r.headers
+                .iter()
+                .any(|(k, v)| k == "Content-Encoding" && v == "gzip")
+        );
+        assert_eq!(gunzip(&r.body), LONG_TEXT.as_bytes());
+
+        let text = String::from_utf8_lossy(&r.build_raw()).into_owned();
+        assert!(text.contains(&format!("Content-Length: {}\r\n", r.body.len())));
+    }
+
+    #[test]
+    fn test_maybe_compress_skips_small_body() {
+        let mut r = Response::ok_text("short");
+        r.maybe_compress(Some("gzip"));
+
+        assert_eq!(r.body, b"short");
+        assert!(!r.headers.iter().any(|(k, _)| k == "Content-Encoding"));
+    }
+
+    #[test]
+    fn test_maybe_compress_skips_partial_content() {
+        let mut r = Response::new(206, "Partial Content");
+        r.body_bytes(vec![b'a'; MIN_COMPRESS_SIZE * 4]);
+        r.maybe_compress(Some("gzip"));
+
+        assert_eq!(r.body.len(), MIN_COMPRESS_SIZE * 4);
+        assert!(!r.headers.iter().any(|(k, _)| k == "Content-Encoding"));
+    }
+
+    #[test]
+    fn test_strip_body_keeps_content_length() {
+        let mut r = Response::ok_text("hello");
+        r.strip_body();
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert!(raw.contains("Content-Length: 5\r\n"), "{}", raw);
+        assert!(raw.ends_with("\r\n\r\n"), "{}", raw);
+        assert_eq!(raw.matches("Content-Length").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_strip_body_drops_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("f.txt");
+        std::fs::write(&path, "contents").unwrap();
+
+        let mut r = Response::new(200, "OK");
+        r.file_body(File::open(&path).await.unwrap(), 8)
+            .strip_body();
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 0);
+        let raw = String::from_utf8(out).unwrap();
+        assert!(raw.contains("Content-Length: 8\r\n"), "{}", raw);
+        assert!(raw.ends_with("\r\n\r\n"), "{}", raw);
+    }
+
+    fn inflate(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::ZlibDecoder::new(bytes)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn unbrotli(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+        let mut out = Vec::new();
+        brotli::Decompressor::new(bytes, 4096)
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    fn content_encoding(r: &Response) -> Option<&str> {
+        r.headers
+            .iter()
+            .find(|(k, _)| k == "Content-Encoding")
+            .map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn test_maybe_compress_at_level() {
+        // Compressible, but not so trivially that every level does as well
+        let body: String = (0..4000)
+            .map(|i| format!("entry {} of table {}: {}\n", i % 251, i % 7, i * 31 % 89))
+            .collect();
+        let compressed_at = |accept, level| {
+            let mut r = Response::ok_text(&body);
+            r.maybe_compress_at(Some(accept), level);
+            r.body
+        };
+
+        let fast = compressed_at("gzip", 1);
+        let best = compressed_at("gzip", 9);
+        assert_eq!(gunzip(&fast), body.as_bytes());
+        assert_eq!(gunzip(&best), body.as_bytes());
+        assert!(best.len() < fast.len(), "{} vs {}", best.len(), fast.len());
+
+        let fast = compressed_at("deflate", 1);
+        let best = compressed_at("deflate", 9);
+        assert_eq!(inflate(&fast), body.as_bytes());
+        assert_eq!(inflate(&best), body.as_bytes());
+        assert!(best.len() < fast.len(), "{} vs {}", best.len(), fast.len());
+
+        // Out-of-range levels are clamped, and the default sits between
+        assert_eq!(compressed_at("gzip", 99), compressed_at("gzip", 9));
+        let mut r = Response::ok_text(&body);
+        r.maybe_compress(Some("gzip"));
+        assert_eq!(r.body, compressed_at("gzip", DEFAULT_COMPRESSION_LEVEL));
+    }
+
+    #[test]
+    fn test_maybe_compress_deflate_round_trip() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.maybe_compress(Some("deflate"));
+        assert_eq!(content_encoding(&r), Some("deflate"));
+        assert_eq!(inflate(&r.body), LONG_TEXT.as_bytes());
+    }
+
+    #[test]
+    fn test_maybe_compress_brotli_round_trip() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.maybe_compress(Some("br"));
+        assert_eq!(content_encoding(&r), Some("br"));
+        assert_eq!(unbrotli(&r.body), LONG_TEXT.as_bytes());
+        assert_eq!(r.headers_all("Vary"), vec!["Accept-Encoding"]);
+    }
+
+    #[test]
+    fn test_maybe_compress_prefers_br_then_gzip_then_deflate() {
+        for (accept, expected) in [
+            ("gzip, deflate, br", "br"),
+            ("deflate, gzip", "gzip"),
+            ("*", "br"),
+            ("GZIP", "gzip"),
+        ] {
+            let mut r = Response::ok_text(LONG_TEXT);
+            r.maybe_compress(Some(accept));
+            assert_eq!(content_encoding(&r), Some(expected), "{}", accept);
+        }
+    }
+
+    #[test]
+    fn test_maybe_compress_negotiates_by_q_value() {
+        for (accept, expected) in [
+            ("gzip;q=0.5, br;q=1.0", "br"),
+            ("br;q=0.2, gzip;q=0.8, deflate;q=0.5", "gzip"),
+            ("deflate, *;q=0.1", "deflate"),
+            ("br;q=0, *", "gzip"),
+        ] {
+            let mut r = Response::ok_text(LONG_TEXT);
+            r.maybe_compress(Some(accept));
+            assert_eq!(content_encoding(&r), Some(expected), "{}", accept);
+        }
+    }
+
+    #[test]
+    fn test_maybe_compress_skips_unsupported_codings() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.maybe_compress(Some("compress, zstd, identity"));
+        assert_eq!(r.body, LONG_TEXT.as_bytes());
+        assert_eq!(content_encoding(&r), None);
+
+        r.maybe_compress(None);
+        assert_eq!(r.body, LONG_TEXT.as_bytes());
+    }
+
+    #[test]
+    fn test_maybe_compress_respects_q_zero() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.maybe_compress(Some("gzip;q=0, identity"));
+        assert_eq!(r.body, LONG_TEXT.as_bytes());
+    }
+
+    #[test]
+    fn test_maybe_compress_replaces_explicit_content_length() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.header("Content-Length", &LONG_TEXT.len().to_string());
+        r.maybe_compress(Some("deflate, gzip"));
+
+        let text = String::from_utf8_lossy(&r.build_raw()).into_owned();
+        assert_eq!(text.matches("Content-Length").count(), 1);
+        assert!(text.contains(&format!("Content-Length: {}\r\n", r.body.len())));
+    }
+
+    #[test]
+    fn test_maybe_compress_not_applied_twice() {
+        let mut r = Response::ok_text(LONG_TEXT);
+        r.maybe_compress(Some("gzip"));
+        let once = r.body.clone();
+        r.maybe_compress(Some("gzip"));
+        assert_eq!(r.body, once);
+    }
+
+    // ── Serialization: build_headers_raw ─────────────────────────────
 
     #[test]
     fn test_build_headers_raw_no_body_included() {
@@ -384,7 +1491,13 @@ mod tests {
         let raw = r.build_headers_raw();
         let text = String::from_utf8(raw).unwrap();
 
-        assert_eq!(text, "HTTP/1.1 301 Moved Permanently\r\n\r\n");
+        assert_eq!(
+            text,
+            format!(
+                "HTTP/1.1 301 Moved Permanently\r\nServer: {}\r\nContent-Length: 0\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
     }
 
     #[test]
@@ -419,8 +1532,277 @@ mod tests {
         );
     }
 
+    // ── write_to_buf (reused buffer) ─────────────────────────────────
+
+    /// Serialize the way `build_raw` did before the reusable buffer: a
+    /// fresh `String` for the head, then the body appended.
+    fn serialize_fresh(r: &Response) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", r.status_code, r.reason);
+        for (k, v) in &r.headers {
+            head.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        head.push_str(&format!("Server: {}\r\n", DEFAULT_SERVER_NAME));
+        if r.status_only {
+            head.push_str("\r\n");
+            return head.into_bytes();
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", r.body.len()));
+        let mut raw = head.into_bytes();
+        raw.extend_from_slice(&r.body);
+        raw
+    }
+
+    fn sample_responses() -> Vec<Response> {
+        let mut with_headers = Response::ok_text("hello world");
+        with_headers
+            .header("X-A", "1")
+            .header("Connection", "keep-alive");
+        vec![
+            Response::ok_text(&"x".repeat(4096)),
+            with_headers,
+            Response::not_found(),
+            Response::status_only(204, "No Content"),
+            Response::ok_text(""),
+        ]
+    }
+
+    #[test]
+    fn test_write_to_buf_matches_fresh_serialization() {
+        for r in sample_responses() {
+            let mut buf = Vec::new();
+            r.write_to_buf(&mut buf);
+            assert_eq!(buf, serialize_fresh(&r));
+        }
+    }
+
+    #[test]
+    fn test_write_to_buf_appends() {
+        let r = Response::ok_text("abc");
+        let mut buf = b"prefix".to_vec();
+        r.write_to_buf(&mut buf);
+        assert!(buf.starts_with(b"prefix"));
+        assert_eq!(&buf[6..], &serialize_fresh(&r)[..]);
+    }
+
+    #[test]
+    fn test_write_to_buf_reused_buffer_keeps_capacity() {
+        let mut buf = Vec::new();
+        let responses = sample_responses();
+        // The first (largest) response sizes the buffer for the rest
+        responses[0].write_to_buf(&mut buf);
+        let capacity = buf.capacity();
+        let ptr = buf.as_ptr();
+
+        for r in &responses[1..] {
+            buf.clear();
+            r.write_to_buf(&mut buf);
+            assert_eq!(buf, serialize_fresh(r));
+            assert_eq!(buf.capacity(), capacity);
+            assert_eq!(buf.as_ptr(), ptr, "buffer should not be reallocated");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_to_reusing_sends_only_the_current_response() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let writer = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            let mut expected = Vec::new();
+            for r in sample_responses() {
+                expected.extend(r.build_raw());
+                r.write_to_reusing(&mut stream, &mut buf).await.unwrap();
+            }
+            expected
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        let expected = writer.await.unwrap();
+        assert_eq!(received, expected);
+    }
+
+    // ── Vectored writes ──────────────────────────────────────────────
+
+    /// Accepts at most `max` bytes per write, optionally without vectored
+    /// support, to exercise partial writes.
+    struct ChunkedWriter {
+        out: Vec<u8>,
+        max: usize,
+        vectored: bool,
+        calls: usize,
+    }
+
+    impl ChunkedWriter {
+        fn new(max: usize, vectored: bool) -> Self {
+            Self {
+                out: Vec::new(),
+                max,
+                vectored,
+                calls: 0,
+            }
+        }
+    }
+
+    impl AsyncWrite for ChunkedWriter {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<io::Result<usize>> {
+            let n = buf.len().min(self.max);
+            self.out.extend_from_slice(&buf[..n]);
+            self.calls += 1;
+            std::task::Poll::Ready(Ok(n))
+        }
+
+        fn poll_write_vectored(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> std::task::Poll<io::Result<usize>> {
+            let mut n = 0;
+            for buf in bufs {
+                let take = buf.len().min(self.max - n);
+                self.out.extend_from_slice(&buf[..take]);
+                n += take;
+                if n == self.max {
+                    break;
+                }
+            }
+            self.calls += 1;
+            std::task::Poll::Ready(Ok(n))
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            self.vectored
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_all_vectored_single_call_when_possible() {
+        let mut w = ChunkedWriter::new(usize::MAX, true);
+        write_all_vectored(&mut w, &mut [IoSlice::new(b"head"), IoSlice::new(b"body")])
+            .await
+            .unwrap();
+        assert_eq!(w.out, b"headbody");
+        assert_eq!(w.calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_all_vectored_handles_partial_writes() {
+        let mut w = ChunkedWriter::new(3, true);
+        write_all_vectored(
+            &mut w,
+            &mut [
+                IoSlice::new(b"head"),
+                IoSlice::new(b""),
+                IoSlice::new(b"body!"),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(w.out, b"headbody!");
+        assert_eq!(w.calls, 3);
+    }
+
+    #[tokio::test]
+    async fn test_write_all_vectored_falls_back_to_sequential() {
+        let mut w = ChunkedWriter::new(usize::MAX, false);
+        write_all_vectored(&mut w, &mut [IoSlice::new(b"head"), IoSlice::new(b"body")])
+            .await
+            .unwrap();
+        assert_eq!(w.out, b"headbody");
+        assert_eq!(w.calls, 2);
+    }
+
+    #[tokio::test]
+    async fn test_write_to_vectored_body_matches_build_raw() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let body: Vec<u8> = (0..MIN_VECTORED_BODY * 40)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let writer = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut r = Response::new(200, "OK");
+            r.header("Content-Type", "application/octet-stream")
+                .body_bytes(body);
+            let expected = r.build_raw();
+            let written = r.write_to(&mut stream).await.unwrap();
+            (expected, written)
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        let (expected, written) = writer.await.unwrap();
+        assert_eq!(written, (MIN_VECTORED_BODY * 40) as u64);
+        assert!(received == expected, "bytes differ from build_raw");
+    }
+
     // ── write_to / write_headers (async, via TcpStream) ──────────────
 
+    #[tokio::test]
+    async fn test_write_interim_then_final_response() {
+        use tokio::io::AsyncReadExt;
+
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let mut hints = Response::informational(103, "Early Hints");
+        hints.header("Link", "</app.js>; rel=preload; as=script");
+        hints.write_interim(&mut server).await.unwrap();
+        Response::ok_text("done")
+            .write_to(&mut server)
+            .await
+            .unwrap();
+        drop(server);
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        let text = String::from_utf8(received).unwrap();
+        let (interim, last) = text.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            interim,
+            "HTTP/1.1 103 Early Hints\r\nLink: </app.js>; rel=preload; as=script"
+        );
+        assert!(last.starts_with("HTTP/1.1 200 OK\r\n"), "{}", last);
+        assert!(last.ends_with("\r\n\r\ndone"));
+    }
+
+    #[tokio::test]
+    async fn test_write_interim_rejects_final_status() {
+        let (_client, mut server) = tokio::io::duplex(64);
+        let err = Response::ok_text("x")
+            .write_interim(&mut server)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("must be 1xx"), "{}", err);
+    }
+
     #[tokio::test]
     async fn test_write_to_stream() {
         use tokio::io::AsyncReadExt;
@@ -432,7 +1814,8 @@ mod tests {
         let writer = tokio::spawn(async move {
             let (mut stream, _) = listener.accept().await.unwrap();
             let r = Response::ok_text("streamed");
-            r.write_to(&mut stream).await.unwrap();
+            let written = r.write_to(&mut stream).await.unwrap();
+            assert_eq!(written, "streamed".len() as u64);
         });
 
         let mut client = TcpStream::connect(addr).await.unwrap();
@@ -445,6 +1828,287 @@ mod tests {
         assert!(text.ends_with("streamed"));
     }
 
+    #[tokio::test]
+    async fn test_write_to_streams_file_body() {
+        use tokio::io::AsyncReadExt;
+        use tokio::net::TcpListener;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("body.bin");
+        let content: Vec<u8> = (0..(FILE_COPY_BUF_SIZE * 2 + 7)).map(|i| i as u8).collect();
+        std::fs::write(&path, &content).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let writer = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let file = File::open(&path).await.unwrap();
+            let mut r = Response::new(200, "OK");
+            r.header("Content-Type", "application/octet-stream")
+                .file_body(file, content.len() as u64);
+            let written = r.write_to(&mut stream).await.unwrap();
+            assert_eq!(written, content.len() as u64);
+            content
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        let content = writer.await.unwrap();
+
+        let header_end = b"\r\n\r\n";
+        let pos = buf
+            .windows(header_end.len())
+            .position(|w| w == header_end)
+            .expect("should have header terminator");
+        let head = String::from_utf8_lossy(&buf[..pos]);
+        assert!(head.contains(&format!("Content-Length: {}", content.len())));
+        assert_eq!(head.matches("Content-Length").count(), 1);
+        assert!(buf[pos + header_end.len()..] == content[..]);
+    }
+
+    /// Serialize a 200 whose body is `contents`, attached as a file by
+    /// `attach`.
+    async fn written_with_file(
+        contents: &[u8],
+        attach: impl FnOnce(&mut Response, File),
+    ) -> String {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("body.bin");
+        std::fs::write(&path, contents).unwrap();
+
+        let mut r = Response::new(200, "OK");
+        attach(&mut r, File::open(&path).await.unwrap());
+        let mut out = Vec::new();
+        r.write_to(&mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chunked_file_body_frames_chunks() {
+        let contents = vec![b'x'; FILE_COPY_BUF_SIZE + 10];
+        let text = written_with_file(&contents, |r, file| {
+            r.header("Content-Length", "3").chunked_file_body(file);
+        })
+        .await;
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked"), "{}", head);
+        assert!(!head.contains("Content-Length"), "{}", head);
+
+        let expected = format!(
+            "{:x}\r\n{}\r\na\r\n{}\r\n0\r\n\r\n",
+            FILE_COPY_BUF_SIZE,
+            "x".repeat(FILE_COPY_BUF_SIZE),
+            "x".repeat(10)
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_file_body_empty_file() {
+        let text = written_with_file(b"", |r, file| {
+            r.chunked_file_body(file);
+        })
+        .await;
+        assert!(text.ends_with("\r\n\r\n0\r\n\r\n"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_stream_from_reader_with_length() {
+        let mut r = Response::new(200, "OK");
+        r.header("Content-Type", "text/plain").stream_from(
+            std::io::Cursor::new(b"streamed from memory, not all of it".to_vec()),
+            Some(20),
+        );
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 20);
+
+        let text = String::from_utf8(out).unwrap();
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Content-Length: 20\r\n"), "{}", head);
+        assert!(!head.contains("Transfer-Encoding"), "{}", head);
+        assert_eq!(body, "streamed from memory");
+    }
+
+    #[tokio::test]
+    async fn test_stream_from_reader_without_length_is_chunked() {
+        let mut r = Response::new(200, "OK");
+        r.header("Content-Length", "99")
+            .stream_from(std::io::Cursor::new(b"hello".to_vec()), None);
+        assert!(!r.is_close_delimited());
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 5);
+
+        let text = String::from_utf8(out).unwrap();
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"), "{}", head);
+        assert!(!head.contains("Content-Length"), "{}", head);
+        assert_eq!(body, "5\r\nhello\r\n0\r\n\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_close_delimit_chunked_stream() {
+        let mut r = Response::new(200, "OK");
+        r.stream_from(std::io::Cursor::new(b"hello".to_vec()), None)
+            .close_delimit_chunked();
+        assert!(r.is_close_delimited());
+        let mut out = Vec::new();
+        r.write_to(&mut out).await.unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        assert!(!head.contains("Transfer-Encoding"), "{}", head);
+        assert!(!head.contains("Content-Length"), "{}", head);
+        assert_eq!(body, "hello");
+
+        // Bodies of known length are left alone
+        let mut r = Response::new(200, "OK");
+        r.stream_from(std::io::Cursor::new(b"hello".to_vec()), Some(5))
+            .close_delimit_chunked();
+        assert!(!r.is_close_delimited());
+    }
+
+    #[tokio::test]
+    async fn test_stream_from_dropped_for_head() {
+        let mut r = Response::new(200, "OK");
+        r.stream_from(std::io::Cursor::new(b"hello".to_vec()), Some(5))
+            .strip_body();
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 0);
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("Content-Length: 5\r\n"), "{}", text);
+        assert!(text.ends_with("\r\n\r\n"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_file_body_until_close_has_no_length() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("body.txt");
+        std::fs::write(&path, "all of it").unwrap();
+
+        let mut r = Response::new(200, "OK");
+        r.file_body_until_close(File::open(&path).await.unwrap());
+        assert!(r.is_close_delimited());
+
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 9);
+        let text = String::from_utf8(out).unwrap();
+        assert!(!text.contains("Content-Length"), "{}", text);
+        assert!(!text.contains("Transfer-Encoding"), "{}", text);
+        assert!(text.ends_with("\r\n\r\nall of it"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_write_to_file_body_sends_only_len_bytes_from_position() {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("slice.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+
+        let mut file = File::open(&path).await.unwrap();
+        file.seek(std::io::SeekFrom::Start(3)).await.unwrap();
+        let mut r = Response::new(206, "Partial Content");
+        r.file_body(file, 4);
+
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let written = r.write_to(&mut server).await.unwrap();
+        drop(server);
+        assert_eq!(written, 4);
+
+        let mut text = String::new();
+        client.read_to_string(&mut text).await.unwrap();
+        assert!(text.contains("Content-Length: 4\r\n"), "{}", text);
+        assert!(text.ends_with("\r\n\r\n3456"), "{}", text);
+    }
+
+    #[test]
+    fn test_status_only_content_length_unless_body_forbidden() {
+        for (status, framed) in [
+            (100, false),
+            (201, true),
+            (204, false),
+            (304, false),
+            (404, true),
+            (503, true),
+        ] {
+            let text = String::from_utf8(Response::status_only(status, "X").build_raw()).unwrap();
+            assert_eq!(text.contains("Content-Length: 0\r\n"), framed, "{}", text);
+        }
+        // An explicit length isn't doubled
+        let mut r = Response::status_only(404, "Not Found");
+        r.header("Content-Length", "0");
+        let text = String::from_utf8(r.build_raw()).unwrap();
+        assert_eq!(text.matches("Content-Length").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_interim_has_no_content_length() {
+        let mut out = Vec::new();
+        Response::informational(100, "Continue")
+            .write_interim(&mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+
+    #[test]
+    fn test_body_dropped_where_forbidden() {
+        for (status, reason) in [(204, "No Content"), (304, "Not Modified")] {
+            let mut r = Response::new(status, reason);
+            r.header("ETag", "\"v1\"")
+                .body_bytes(b"should not be sent".to_vec());
+            let text = String::from_utf8(r.build_raw()).unwrap();
+            let expected = format!("ETag: \"v1\"\r\nServer: {}\r\n\r\n", DEFAULT_SERVER_NAME);
+            assert!(text.ends_with(&expected), "{}", text);
+            assert!(!text.contains("Content-Length"), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_to_drops_forbidden_body() {
+        let mut r = Response::new(304, "Not Modified");
+        r.body_bytes(vec![b'x'; 2 * MIN_VECTORED_BODY])
+            .maybe_compress(Some("gzip"));
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 0);
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.ends_with("\r\n\r\n"), "{}", text);
+        assert!(!text.contains("Content-Length"), "{}", text);
+        assert!(!text.contains("Content-Encoding"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_write_to_drops_forbidden_file_body() {
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, b"file bytes").unwrap();
+        let mut r = Response::new(304, "Not Modified");
+        r.file_body(File::from_std(file), 10);
+        let mut out = Vec::new();
+        assert_eq!(r.write_to(&mut out).await.unwrap(), 0);
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.ends_with("\r\n\r\n"), "{}", text);
+        assert!(!text.contains("file bytes"), "{}", text);
+        assert!(!text.contains("Content-Length"), "{}", text);
+    }
+
+    #[test]
+    fn test_status_only_keeps_explicit_headers() {
+        let mut r = Response::status_only(204, "No Content");
+        r.header("Connection", "close");
+        let text = String::from_utf8(r.build_raw()).unwrap();
+
+        // Explicit headers are kept, and only Server is added
+        assert_eq!(
+            text,
+            format!(
+                "HTTP/1.1 204 No Content\r\nConnection: close\r\nServer: {}\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_write_headers_stream() {
         use tokio::io::AsyncReadExt;
@@ -474,11 +2138,54 @@ mod tests {
         assert!(text.ends_with("\r\n\r\n"));
     }
 
+    #[test]
+    fn test_created_at() {
+        let r = Response::created_at("/files/a.txt");
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert!(raw.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(raw.contains("Location: /files/a.txt\r\n"));
+        assert!(raw.contains("Content-Length: 0\r\n"));
+        assert!(raw.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_no_content() {
+        let r = Response::no_content();
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert_eq!(
+            raw,
+            format!(
+                "HTTP/1.1 204 No Content\r\nServer: {}\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let mut r = Response::not_modified();
+        r.header("ETag", "\"abc\"");
+        let raw = String::from_utf8(r.build_raw()).unwrap();
+        assert_eq!(
+            raw,
+            format!(
+                "HTTP/1.1 304 Not Modified\r\nETag: \"abc\"\r\nServer: {}\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
+    }
+
     #[test]
     fn test_created() {
         let r = Response::created();
         let raw = String::from_utf8(r.build_raw()).unwrap();
-        // Exact format: status line + CRLF CRLF, no headers, no body
-        assert_eq!(raw, "HTTP/1.1 201 Created\r\n\r\n");
+        // Exact format: status line, Server, the empty body's length, CRLF CRLF
+        assert_eq!(
+            raw,
+            format!(
+                "HTTP/1.1 201 Created\r\nServer: {}\r\nContent-Length: 0\r\n\r\n",
+                DEFAULT_SERVER_NAME
+            )
+        );
     }
 }
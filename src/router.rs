@@ -0,0 +1,664 @@
+use std::{future::Future, pin::Pin};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    config::ServerConfig,
+    handlers::percent_encode_path,
+    middleware::{Middleware, Next},
+    request::Request,
+    response::Response,
+};
+
+/// A heap-allocated, type-erased future, as returned by route handlers.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A route handler: an async function from the request, the parameters
+/// bound by its route pattern and the server config to a response.
+pub type Handler = Box<
+    dyn for<'a> Fn(&'a Request, &'a Params, &'a ServerConfig) -> BoxFuture<'a, Result<Response>>
+        + Send
+        + Sync,
+>;
+
+/// Values bound by a route pattern: one per `:name` segment, plus `*` for
+/// whatever a trailing wildcard matched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// The value bound to `name` (without the leading `:`), if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn insert(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_owned(), value.to_owned()));
+    }
+}
+
+struct Route {
+    method: String,
+    pattern: String,
+    handler: Handler,
+}
+
+/// Outcome of looking up a request in a [`Router`].
+pub enum RouteMatch<'r> {
+    /// A route matched both the path and the method.
+    Found(&'r Handler, Params),
+    /// The path matched, but not for this method. Carries the `Allow`
+    /// header value for a 405 response.
+    MethodNotAllowed(String),
+    /// No route matches the path.
+    NotFound,
+}
+
+/// Table of `(method, pattern, handler)` routes.
+///
+/// Patterns are matched segment by segment on `/`:
+///
+/// - a literal segment must match exactly (`/user-agent`);
+/// - `:name` matches any non-empty segment and binds it (`/users/:id`);
+/// - a final segment ending in `*` matches any segment starting with what
+///   precedes the `*`, plus everything after it, binding the remainder to
+///   `*` (`/files/*`).
+///
+/// Without a wildcard the segment counts must be equal. When several
+/// patterns match a path, the one registered first wins, so register exact
+/// routes before overlapping wildcards.
+///
+/// A `HEAD` request is handled by the path's `GET` route unless it has a
+/// `HEAD` route of its own; the body is dropped when the response is sent.
+///
+/// Requests pass through the [`Middleware`] stack, outermost first, before
+/// being matched.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+/// Match `path` against `pattern`, returning the bound parameters.
+fn match_pattern(pattern: &str, path: &str) -> Option<Params> {
+    let mut params = Params::default();
+    let mut rest = Some(path.strip_prefix('/')?);
+
+    for segment in pattern.strip_prefix('/')?.split('/') {
+        let remaining = rest?;
+        if let Some(prefix) = segment.strip_suffix('*') {
+            params.insert("*", remaining.strip_prefix(prefix)?);
+            return Some(params);
+        }
+
+        let (actual, after) = match remaining.split_once('/') {
+            Some((actual, after)) => (actual, Some(after)),
+            None => (remaining, None),
+        };
+        rest = after;
+
+        match segment.strip_prefix(':') {
+            Some(name) if !actual.is_empty() => params.insert(name, actual),
+            Some(_) => return None,
+            None if segment == actual => {}
+            None => return None,
+        }
+    }
+
+    // Every path segment must have been consumed
+    rest.is_none().then_some(params)
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `handler` for `method` requests whose path matches
+    /// `pattern`. Returns `&mut Self` for chaining.
+    pub fn route<F>(&mut self, method: &str, pattern: &str, handler: F) -> &mut Self
+    where
+        F: for<'a> Fn(&'a Request, &'a Params, &'a ServerConfig) -> BoxFuture<'a, Result<Response>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.routes.push(Route {
+            method: method.to_owned(),
+            pattern: pattern.to_owned(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Wrap request handling in `middleware`. Middleware added first runs
+    /// first (outermost). Returns `&mut Self` for chaining.
+    pub fn layer(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Handle `request`: run it through the middleware stack, then the
+    /// matching route.
+    pub async fn dispatch(&self, request: &Request, config: &ServerConfig) -> Result<Response> {
+        Next::new(&self.middleware, self, config).run(request).await
+    }
+
+    /// Route the request to the matching handler, without middleware.
+    ///
+    /// `OPTIONS` is answered for every routed path, and `TRACE` for every
+    /// path when enabled in the config; other methods a path doesn't support
+    /// get 405 with an `Allow` header. A method the server has never heard
+    /// of gets 501, whatever the path.
+    pub(crate) async fn route_request(
+        &self,
+        request: &Request,
+        config: &ServerConfig,
+    ) -> Result<Response> {
+        if !self.is_known_method(&request.method) {
+            debug!("unknown method: {}", request.method);
+            return Ok(Response::error(501, "Not Implemented"));
+        }
+        if request.method == "OPTIONS" {
+            return Ok(self.options(request, config));
+        }
+        if request.method == "TRACE" && config.trace {
+            return Ok(trace(request));
+        }
+
+        match self.match_route(&request.method, &request.path) {
+            RouteMatch::Found(handler, params) => handler(request, &params, config).await,
+            RouteMatch::MethodNotAllowed(allow) => {
+                debug!("{} not allowed for {}", request.method, request.path);
+                let mut resp = Response::error(405, "Method Not Allowed");
+                resp.header("Allow", &allow);
+                Ok(resp)
+            }
+            RouteMatch::NotFound => {
+                debug!("unknown path: {}", request.path);
+                Ok(Response::not_found())
+            }
+        }
+    }
+
+    /// Whether `method` is a standard method or one some route handles.
+    fn is_known_method(&self, method: &str) -> bool {
+        KNOWN_METHODS.contains(&method) || self.routes.iter().any(|r| r.method == method)
+    }
+
+    /// Answers `OPTIONS` with 204 and an `Allow` header listing the methods
+    /// the target supports. CORS preflights from allowed origins also get the
+    /// `Access-Control-Allow-*` headers.
+    fn options(&self, request: &Request, config: &ServerConfig) -> Response {
+        let Some(allow) = self.allowed_methods(&request.path) else {
+            debug!("OPTIONS for unknown path: {}", request.path);
+            return Response::not_found();
+        };
+        let mut resp = Response::no_content();
+        resp.header("Allow", &allow);
+        config.cors.apply_preflight(request, &mut resp, &allow);
+        resp
+    }
+
+    /// Routes registered under the first pattern that matches `path`, with
+    /// the parameters that pattern binds.
+    fn routes_for<'r>(&'r self, path: &str) -> Option<(Params, impl Iterator<Item = &'r Route>)> {
+        let (pattern, params) = self
+            .routes
+            .iter()
+            .find_map(|r| Some((r.pattern.as_str(), match_pattern(&r.pattern, path)?)))?;
+        let routes = self.routes.iter().filter(move |r| r.pattern == pattern);
+        Some((params, routes))
+    }
+
+    /// Find the handler for `method` and `path`.
+    pub fn match_route(&self, method: &str, path: &str) -> RouteMatch<'_> {
+        let Some((params, routes)) = self.routes_for(path) else {
+            return RouteMatch::NotFound;
+        };
+        let routes: Vec<&Route> = routes.collect();
+        let route = routes.iter().find(|r| r.method == method).or_else(|| {
+            routes
+                .iter()
+                .find(|r| method == "HEAD" && r.method == "GET")
+        });
+        if let Some(route) = route {
+            return RouteMatch::Found(&route.handler, params);
+        }
+        match self.allowed_methods(path) {
+            Some(allow) => RouteMatch::MethodNotAllowed(allow),
+            None => RouteMatch::NotFound,
+        }
+    }
+
+    /// Methods supported for `path`, plus `HEAD` wherever `GET` is and
+    /// `OPTIONS`, formatted for an `Allow` header. `*` means the server as
+    /// a whole. `None` if no route matches.
+    pub fn allowed_methods(&self, path: &str) -> Option<String> {
+        let mut methods: Vec<&str> = Vec::new();
+        let routes: Box<dyn Iterator<Item = &Route>> = if path == "*" {
+            Box::new(self.routes.iter())
+        } else {
+            Box::new(self.routes_for(path)?.1)
+        };
+        for route in routes {
+            if !methods.contains(&route.method.as_str()) {
+                methods.push(&route.method);
+            }
+        }
+        if methods.is_empty() {
+            return None;
+        }
+        if let Some(get) = methods.iter().position(|&m| m == "GET")
+            && !methods.contains(&"HEAD")
+        {
+            methods.insert(get + 1, "HEAD");
+        }
+        methods.push("OPTIONS");
+        Some(methods.join(", "))
+    }
+}
+
+/// Methods recognised on any path, routed or not: those of RFC 9110 §9
+/// plus `PATCH` (RFC 5789). Anything else is 501 unless a route claims it.
+const KNOWN_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Request headers never reflected by `TRACE`, so a traced request can't
+/// be used to read the client's credentials.
+const UNTRACED_HEADERS: [&str; 3] = ["Authorization", "Proxy-Authorization", "Cookie"];
+
+/// Answers `TRACE` with the request line and headers as received, as a
+/// `message/http` body (RFC 9110 §9.3.8), minus credentials.
+fn trace(request: &Request) -> Response {
+    let mut target = percent_encode_path(&request.path);
+    if let Some(query) = &request.query {
+        target.push('?');
+        target.push_str(query);
+    }
+    let mut body = format!("TRACE {} {}\r\n", target, request.http_version);
+    for (name, value) in &request.headers {
+        if !UNTRACED_HEADERS
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(name))
+        {
+            body.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+    body.push_str("\r\n");
+
+    let mut resp = Response::new(200, "OK");
+    resp.header("Content-Type", "message/http")
+        .body_bytes(body.into_bytes());
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::HttpVersion;
+
+    fn request(method: &str, path: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: Vec::new(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    /// A router whose handlers reply with a fixed body naming the route.
+    fn test_router() -> Router {
+        let mut router = Router::new();
+        router
+            .route("GET", "/", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("root")) })
+            })
+            .route("GET", "/files/", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("files root")) })
+            })
+            .route("GET", "/files/*", |req, _, _| {
+                Box::pin(async move { Ok(Response::ok_text(&format!("get {}", req.path))) })
+            })
+            .route("PUT", "/files/*", |_, _, _| {
+                Box::pin(async { Ok(Response::ok_text("put")) })
+            })
+            .route("GET", "/users/:id/posts/:pid", |_, params, _| {
+                let body = format!(
+                    "user {} post {}",
+                    params.get("id").unwrap(),
+                    params.get("pid").unwrap()
+                );
+                Box::pin(async move { Ok(Response::ok_text(&body)) })
+            });
+        router
+    }
+
+    async fn dispatch(router: &Router, method: &str, path: &str) -> Option<String> {
+        let req = request(method, path);
+        match router.match_route(method, path) {
+            RouteMatch::Found(handler, params) => {
+                let resp = handler(&req, &params, &ServerConfig::default())
+                    .await
+                    .unwrap();
+                let raw = String::from_utf8(resp.build_raw()).unwrap();
+                Some(raw.split("\r\n\r\n").nth(1).unwrap().to_string())
+            }
+            _ => None,
+        }
+    }
+
+    // ── match_pattern ────────────────────────────────────────────────
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        Params(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_pattern_exact() {
+        assert_eq!(
+            match_pattern("/user-agent", "/user-agent"),
+            Some(params(&[]))
+        );
+        assert_eq!(match_pattern("/user-agent", "/user-agent/x"), None);
+        assert_eq!(match_pattern("/", "/"), Some(params(&[])));
+        assert_eq!(match_pattern("/", "/x"), None);
+        assert_eq!(match_pattern("/files/", "/files"), None);
+    }
+
+    #[test]
+    fn test_pattern_wildcard() {
+        assert_eq!(
+            match_pattern("/files/*", "/files/a.txt"),
+            Some(params(&[("*", "a.txt")]))
+        );
+        assert_eq!(
+            match_pattern("/files/*", "/files/nested/a.txt"),
+            Some(params(&[("*", "nested/a.txt")]))
+        );
+        assert_eq!(
+            match_pattern("/files/*", "/files/"),
+            Some(params(&[("*", "")]))
+        );
+        assert_eq!(match_pattern("/files/*", "/files"), None);
+        assert_eq!(match_pattern("/files/*", "/filesystem"), None);
+    }
+
+    #[test]
+    fn test_pattern_wildcard_with_segment_prefix() {
+        assert_eq!(
+            match_pattern("/user-agent*", "/user-agent"),
+            Some(params(&[("*", "")]))
+        );
+        assert_eq!(
+            match_pattern("/user-agent*", "/user-agent/x"),
+            Some(params(&[("*", "/x")]))
+        );
+        assert_eq!(match_pattern("/user-agent*", "/user"), None);
+    }
+
+    #[test]
+    fn test_pattern_params() {
+        assert_eq!(
+            match_pattern("/users/:id/posts/:pid", "/users/42/posts/7"),
+            Some(params(&[("id", "42"), ("pid", "7")]))
+        );
+        assert_eq!(
+            match_pattern("/files/:name", "/files/a.txt"),
+            Some(params(&[("name", "a.txt")]))
+        );
+    }
+
+    #[test]
+    fn test_pattern_params_segment_count_must_match() {
+        assert_eq!(
+            match_pattern("/users/:id/posts/:pid", "/users/42/posts"),
+            None
+        );
+        assert_eq!(
+            match_pattern("/users/:id/posts/:pid", "/users/42/posts/7/x"),
+            None
+        );
+        assert_eq!(match_pattern("/users/:id", "/users/42/"), None);
+        assert_eq!(match_pattern("/users/:id", "/users/"), None);
+    }
+
+    #[test]
+    fn test_pattern_params_with_wildcard() {
+        assert_eq!(
+            match_pattern("/users/:id/*", "/users/42/a/b"),
+            Some(params(&[("id", "42"), ("*", "a/b")]))
+        );
+    }
+
+    #[test]
+    fn test_params_get() {
+        let p = params(&[("id", "42")]);
+        assert_eq!(p.get("id"), Some("42"));
+        assert_eq!(p.get("pid"), None);
+    }
+
+    // ── match_route ──────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_match_exact() {
+        let router = test_router();
+        assert_eq!(dispatch(&router, "GET", "/").await.as_deref(), Some("root"));
+    }
+
+    #[tokio::test]
+    async fn test_match_wildcard() {
+        let router = test_router();
+        assert_eq!(
+            dispatch(&router, "GET", "/files/a/b.txt").await.as_deref(),
+            Some("get /files/a/b.txt")
+        );
+        assert_eq!(
+            dispatch(&router, "PUT", "/files/a.txt").await.as_deref(),
+            Some("put")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_match_binds_params() {
+        let router = test_router();
+        assert_eq!(
+            dispatch(&router, "GET", "/users/42/posts/7")
+                .await
+                .as_deref(),
+            Some("user 42 post 7")
+        );
+        assert!(matches!(
+            router.match_route("GET", "/users/42/posts"),
+            RouteMatch::NotFound
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_match_first_registered_pattern_wins() {
+        let router = test_router();
+        assert_eq!(
+            dispatch(&router, "GET", "/files/").await.as_deref(),
+            Some("files root")
+        );
+        // The exact route shadows the wildcard's PUT
+        assert!(matches!(
+            router.match_route("PUT", "/files/"),
+            RouteMatch::MethodNotAllowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_match_method_mismatch_is_405() {
+        let router = test_router();
+        match router.match_route("DELETE", "/files/a.txt") {
+            RouteMatch::MethodNotAllowed(allow) => assert_eq!(allow, "GET, HEAD, PUT, OPTIONS"),
+            _ => panic!("expected MethodNotAllowed"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_match_head_uses_get_route() {
+        let router = test_router();
+        assert_eq!(
+            dispatch(&router, "HEAD", "/files/a.txt").await.as_deref(),
+            Some("get /files/a.txt")
+        );
+        // An explicit HEAD route takes precedence over GET
+        let mut router = test_router();
+        router.route("HEAD", "/files/*", |_, _, _| {
+            Box::pin(async { Ok(Response::ok_text("head")) })
+        });
+        assert_eq!(
+            dispatch(&router, "HEAD", "/files/a.txt").await.as_deref(),
+            Some("head")
+        );
+    }
+
+    #[test]
+    fn test_match_unknown_path_is_404() {
+        let router = test_router();
+        assert!(matches!(
+            router.match_route("GET", "/nope"),
+            RouteMatch::NotFound
+        ));
+    }
+
+    // ── allowed_methods ──────────────────────────────────────────────
+
+    #[test]
+    fn test_allowed_methods() {
+        let router = test_router();
+        assert_eq!(
+            router.allowed_methods("/").as_deref(),
+            Some("GET, HEAD, OPTIONS")
+        );
+        assert_eq!(
+            router.allowed_methods("/files/x").as_deref(),
+            Some("GET, HEAD, PUT, OPTIONS")
+        );
+        assert_eq!(
+            router.allowed_methods("*").as_deref(),
+            Some("GET, HEAD, PUT, OPTIONS")
+        );
+        assert_eq!(router.allowed_methods("/nope"), None);
+    }
+
+    // ── TRACE ────────────────────────────────────────────────────────
+
+    fn trace_request() -> Request {
+        Request {
+            query: Some("q=1".to_string()),
+            headers: vec![
+                ("Host".to_string(), "example.com".to_string()),
+                ("X-Custom".to_string(), "kept".to_string()),
+                ("Authorization".to_string(), "Basic c2VjcmV0".to_string()),
+                ("cookie".to_string(), "session=abc".to_string()),
+            ],
+            ..request("TRACE", "/a b")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trace_echoes_request_without_credentials() {
+        let config = ServerConfig {
+            trace: true,
+            ..ServerConfig::default()
+        };
+        let resp = test_router()
+            .dispatch(&trace_request(), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 200);
+        assert_eq!(resp.headers_all("Content-Type"), vec!["message/http"]);
+
+        let raw = String::from_utf8(resp.build_raw()).unwrap();
+        let body = raw.split_once("\r\n\r\n").unwrap().1;
+        assert!(
+            body.starts_with("TRACE /a%20b?q=1 HTTP/1.1\r\n"),
+            "{}",
+            body
+        );
+        assert!(body.contains("Host: example.com\r\n"));
+        assert!(body.contains("X-Custom: kept\r\n"));
+        assert!(!body.contains("Authorization"), "{}", body);
+        assert!(!body.contains("c2VjcmV0"));
+        assert!(!body.contains("session=abc"));
+        assert!(body.ends_with("\r\n\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_trace_disabled_by_default() {
+        let resp = test_router()
+            .dispatch(&trace_request(), &ServerConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 404);
+
+        let resp = test_router()
+            .dispatch(&request("TRACE", "/"), &ServerConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 405);
+    }
+
+    // ── Unknown methods ──────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_unknown_method_is_501() {
+        let router = test_router();
+        let config = ServerConfig::default();
+        for path in ["/", "/files/a.txt", "/nope"] {
+            let resp = router
+                .dispatch(&request("FROBNICATE", path), &config)
+                .await
+                .unwrap();
+            assert_eq!(resp.status_code(), 501, "{}", path);
+        }
+        // Known but unrouted methods keep their 404 / 405
+        let resp = router
+            .dispatch(&request("DELETE", "/files/a.txt"), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 405);
+        let resp = router
+            .dispatch(&request("PATCH", "/nope"), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_custom_routed_method_is_known() {
+        let mut router = test_router();
+        router.route("PURGE", "/files/*", |_, _, _| {
+            Box::pin(async { Ok(Response::ok_text("purged")) })
+        });
+        let config = ServerConfig::default();
+        let resp = router
+            .dispatch(&request("PURGE", "/files/a.txt"), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 200);
+        // Routed somewhere, so elsewhere it's merely not allowed
+        let resp = router
+            .dispatch(&request("PURGE", "/"), &config)
+            .await
+            .unwrap();
+        assert_eq!(resp.status_code(), 405);
+    }
+}
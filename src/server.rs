@@ -1,30 +1,197 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::Result;
-use log::{error, info};
-use tokio::net::TcpListener;
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufReader},
+    net::{TcpListener, TcpStream, UnixListener},
+    sync::Semaphore,
+};
 
-use crate::handlers;
+use crate::{
+    config::{OverloadPolicy, ServerConfig},
+    handlers,
+    metrics::Metrics,
+    response::Response,
+};
+
+pub async fn run(config: ServerConfig) -> Result<()> {
+    config.prepare_directory()?;
+
+    if let Some(path) = config.unix_socket.clone() {
+        return run_unix(&path, config).await;
+    }
+
+    let addr = config.socket_addr()?;
 
-pub async fn run(addr: &str, files_dir: PathBuf) -> Result<()> {
     info!("Binding to {}", addr);
-    let listener = TcpListener::bind(addr).await?;
+    let listener =
+        bind_tcp(addr, config.listen_backlog).with_context(|| format!("binding to {}", addr))?;
 
     info!("Server listening on {}", addr);
+    serve(listener, config).await
+}
+
+/// Bind a TCP listener on `addr` that queues up to `backlog` unaccepted
+/// connections. The IPv6 wildcard (`[::]`) is bound dual-stack, so one
+/// listener accepts both IPv6 and IPv4 clients.
+pub fn bind_tcp(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    // Same as `TcpListener::bind`: allow quick restarts on the same port
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    // The kernel caps this at its own maximum (`somaxconn` on Linux)
+    socket.listen(backlog.try_into().unwrap_or(i32::MAX))?;
+    TcpListener::from_std(socket.into())
+}
 
-    // Share directory path with connection tasks
-    let files_dir = Arc::new(files_dir);
+/// Serve on a Unix domain socket at `path` until Ctrl-C, then remove the
+/// socket file.
+async fn run_unix(path: &Path, config: ServerConfig) -> Result<()> {
+    info!("Binding to unix:{}", path.display());
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("binding to unix socket {}", path.display()))?;
+    let _socket_file = SocketFile(path.to_path_buf());
+
+    info!("Server listening on unix:{}", path.display());
+    tokio::select! {
+        result = serve_unix(listener, config) => result,
+        signal = tokio::signal::ctrl_c() => {
+            info!("Shutting down");
+            signal.context("waiting for shutdown signal")
+        }
+    }
+}
+
+/// Removes a bound socket file when dropped, so the next bind to the same
+/// path doesn't fail with "address in use".
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("removing socket file {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// A source of incoming connections.
+trait Listener {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Wait for the next connection and the client's address, if the
+    /// transport has one.
+    async fn accept(&self) -> io::Result<(Self::Stream, Option<SocketAddr>)>;
+
+    /// Apply per-connection socket options from `config`.
+    fn configure(_stream: &Self::Stream, _config: &ServerConfig) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, Option<SocketAddr>)> {
+        let (stream, peer) = TcpListener::accept(self).await?;
+        Ok((stream, Some(peer)))
+    }
+
+    fn configure(stream: &Self::Stream, config: &ServerConfig) -> io::Result<()> {
+        configure_tcp(stream, config)
+    }
+}
+
+/// Set `TCP_NODELAY` and TCP keepalive on an accepted connection as
+/// `config` asks.
+fn configure_tcp(stream: &TcpStream, config: &ServerConfig) -> io::Result<()> {
+    stream.set_nodelay(config.tcp_nodelay)?;
+    if let Some(idle) = config.tcp_keepalive {
+        SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+    }
+    Ok(())
+}
+
+impl Listener for UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    async fn accept(&self) -> io::Result<(Self::Stream, Option<SocketAddr>)> {
+        let (stream, _) = UnixListener::accept(self).await?;
+        Ok((stream, None))
+    }
+}
+
+/// Accept connections on `listener` forever, handling each in its own task
+/// and keeping at most `config.max_connections` open at once.
+pub async fn serve(listener: TcpListener, config: ServerConfig) -> Result<()> {
+    serve_on(listener, config).await
+}
+
+/// Like [`serve`], for a Unix domain socket. Requests carry no peer
+/// address, so per-client rate limiting doesn't apply.
+pub async fn serve_unix(listener: UnixListener, config: ServerConfig) -> Result<()> {
+    serve_on(listener, config).await
+}
+
+async fn serve_on<L: Listener>(listener: L, config: ServerConfig) -> Result<()> {
+    // Share config and routes with connection tasks
+    let config = Arc::new(config);
+    let metrics = Arc::new(Metrics::new());
+    let router = Arc::new(handlers::app_router(&config, &metrics));
+    let limit = config.max_connections.map(|n| Arc::new(Semaphore::new(n)));
 
     loop {
+        // When queueing, don't accept until a slot is free; new clients wait
+        // in the listen backlog meanwhile
+        let mut permit = None;
+        if let (Some(limit), OverloadPolicy::Queue) = (&limit, config.overload) {
+            permit = Some(
+                limit
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .context("connection limit closed")?,
+            );
+        }
+
         match listener.accept().await {
-            Ok((stream, _peer)) => {
-                let dir = files_dir.clone();
+            Ok((stream, peer)) => {
+                // Not worth dropping the connection over
+                if let Err(e) = L::configure(&stream, &config) {
+                    warn!("setting socket options: {}", e);
+                }
+
+                if let (Some(limit), None) = (&limit, &permit) {
+                    let Ok(acquired) = limit.clone().try_acquire_owned() else {
+                        tokio::spawn(reject_overloaded(stream, config.clone(), metrics.clone()));
+                        continue;
+                    };
+                    permit = Some(acquired);
+                }
+
+                let config = config.clone();
+                let router = router.clone();
+                let metrics = metrics.clone();
 
                 // Spawn an independent task per connection
                 tokio::spawn(async move {
-                    if let Err(e) = handlers::handle_request(stream, dir).await {
+                    if let Err(e) =
+                        handlers::handle_request(stream, peer, config, router, metrics).await
+                    {
                         error!("request handling error: {:?}", e);
                     }
+                    // Free the slot only once the connection is done
+                    drop(permit);
                 });
             }
             Err(e) => {
@@ -33,3 +200,319 @@ pub async fn run(addr: &str, files_dir: PathBuf) -> Result<()> {
         }
     }
 }
+
+/// Turn away a connection over the limit with `503` and close it, draining
+/// what the client already sent so the close doesn't reset the connection
+/// before the 503 is read.
+async fn reject_overloaded<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    config: Arc<ServerConfig>,
+    metrics: Arc<Metrics>,
+) {
+    warn!("connection limit reached, rejecting connection");
+    let mut resp = Response::error(503, "Service Unavailable");
+    config.error_pages.apply(&mut resp);
+    resp.header("Connection", "close");
+    let mut reader = BufReader::new(stream);
+    match resp.write_to(reader.get_mut()).await {
+        Ok(bytes) => metrics.record(503, bytes as usize),
+        Err(e) => {
+            debug!("writing 503 response: {}", e);
+            return;
+        }
+    }
+    handlers::lingering_close(&mut reader).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+    };
+
+    use super::*;
+    use crate::config::DEFAULT_LISTEN_BACKLOG;
+
+    async fn start(config: ServerConfig) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, config));
+        addr
+    }
+
+    async fn start_limited(max_connections: usize, overload: OverloadPolicy) -> SocketAddr {
+        start(ServerConfig {
+            max_connections: Some(max_connections),
+            overload,
+            ..ServerConfig::default()
+        })
+        .await
+    }
+
+    /// Open a keep-alive connection and wait for one response, so the
+    /// server is known to be holding it.
+    async fn hold_connection(addr: SocketAddr) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 200 OK\r\n"));
+        stream
+    }
+
+    async fn get_close(stream: &mut TcpStream) -> String {
+        stream
+            .write_all(b"GET /echo/hi HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // A rejected connection may be reset once its 503 is sent, since
+        // the request is never read; keep whatever arrived
+        let mut buf = Vec::new();
+        let _ = stream.read_to_end(&mut buf).await;
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reject_policy_answers_503_over_limit() {
+        let addr = start_limited(1, OverloadPolicy::Reject).await;
+        let _held = hold_connection(addr).await;
+
+        let mut extra = TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        extra.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(
+            resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            resp
+        );
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_reject_policy_503_survives_unread_request() {
+        let addr = start_limited(1, OverloadPolicy::Reject).await;
+        let _held = hold_connection(addr).await;
+
+        // The server never reads this request; it must still be drained so
+        // the close doesn't reset the connection under the 503
+        let mut extra = TcpStream::connect(addr).await.unwrap();
+        let body = vec![b'x'; 64 * 1024];
+        let head = format!(
+            "POST /files/x HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        extra.write_all(head.as_bytes()).await.unwrap();
+        extra.write_all(&body).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut buf = Vec::new();
+        extra.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(
+            resp.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+            "{}",
+            resp
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_policy_frees_slot_when_connection_closes() {
+        let addr = start_limited(1, OverloadPolicy::Reject).await;
+        drop(hold_connection(addr).await);
+
+        // The handler notices the close and releases its permit shortly after
+        let mut resp = String::new();
+        for _ in 0..50 {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            resp = get_close(&mut stream).await;
+            if !resp.starts_with("HTTP/1.1 503") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+    }
+
+    #[tokio::test]
+    async fn test_queue_policy_waits_for_a_free_slot() {
+        let addr = start_limited(1, OverloadPolicy::Queue).await;
+        let held = hold_connection(addr).await;
+
+        let queued = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            get_close(&mut stream).await
+        });
+
+        // Still waiting while the first connection is open
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!queued.is_finished());
+
+        drop(held);
+        let resp = tokio::time::timeout(Duration::from_secs(5), queued)
+            .await
+            .expect("queued connection should be served")
+            .unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_by_default() {
+        let addr = start(ServerConfig::default()).await;
+        let _a = hold_connection(addr).await;
+        let _b = hold_connection(addr).await;
+        let _c = hold_connection(addr).await;
+    }
+
+    // ── Binding ──────────────────────────────────────────────────────
+
+    async fn get_echo(addr: SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        get_close(&mut stream).await
+    }
+
+    #[tokio::test]
+    async fn test_bind_tcp_ipv6_loopback() {
+        let listener = bind_tcp("[::1]:0".parse().unwrap(), DEFAULT_LISTEN_BACKLOG).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert!(addr.is_ipv6());
+        tokio::spawn(serve(listener, ServerConfig::default()));
+
+        let resp = get_echo(addr).await;
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_tcp_wildcard_is_dual_stack() {
+        let listener = bind_tcp("[::]:0".parse().unwrap(), DEFAULT_LISTEN_BACKLOG).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(listener, ServerConfig::default()));
+
+        for client in ["[::1]", "127.0.0.1"] {
+            let resp = get_echo(format!("{}:{}", client, port).parse().unwrap()).await;
+            assert!(
+                resp.starts_with("HTTP/1.1 200 OK\r\n"),
+                "{}: {}",
+                client,
+                resp
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bind_tcp_ipv4() {
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), DEFAULT_LISTEN_BACKLOG).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, ServerConfig::default()));
+        assert!(get_echo(addr).await.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_bind_tcp_small_backlog_still_accepts() {
+        let listener = bind_tcp("127.0.0.1:0".parse().unwrap(), 4).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Connect before anything accepts, so the clients wait in the backlog
+        let mut waiting = Vec::new();
+        for _ in 0..3 {
+            waiting.push(TcpStream::connect(addr).await.unwrap());
+        }
+        tokio::spawn(serve(listener, ServerConfig::default()));
+
+        for mut stream in waiting {
+            let resp = get_close(&mut stream).await;
+            assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        }
+    }
+
+    // ── Socket options ───────────────────────────────────────────────
+
+    /// A connected pair: the client end and the server's accepted end.
+    async fn accepted_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        (client, accepted)
+    }
+
+    #[tokio::test]
+    async fn test_configure_tcp_sets_nodelay_by_default() {
+        let (_client, accepted) = accepted_pair().await;
+        configure_tcp(&accepted, &ServerConfig::default()).unwrap();
+        assert!(accepted.nodelay().unwrap());
+        assert!(!SockRef::from(&accepted).keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_configure_tcp_nodelay_off() {
+        let (_client, accepted) = accepted_pair().await;
+        accepted.set_nodelay(true).unwrap();
+        let config = ServerConfig {
+            tcp_nodelay: false,
+            ..ServerConfig::default()
+        };
+        configure_tcp(&accepted, &config).unwrap();
+        assert!(!accepted.nodelay().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_configure_tcp_keepalive() {
+        let (_client, accepted) = accepted_pair().await;
+        let config = ServerConfig {
+            tcp_keepalive: Some(Duration::from_secs(45)),
+            ..ServerConfig::default()
+        };
+        configure_tcp(&accepted, &config).unwrap();
+        let socket = SockRef::from(&accepted);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(45)
+        );
+    }
+
+    // ── Unix domain sockets ──────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_serve_unix_answers_over_unix_stream() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("http.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(serve_unix(listener, ServerConfig::default()));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /echo/local HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        let resp = String::from_utf8(buf).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", resp);
+        assert!(resp.ends_with("\r\n\r\nlocal"));
+    }
+
+    #[test]
+    fn test_socket_file_removed_on_drop() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("http.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        assert!(path.exists());
+
+        drop(SocketFile(path.clone()));
+        assert!(!path.exists());
+        // The path can be bound again straight away
+        drop(listener);
+        std::os::unix::net::UnixListener::bind(&path).unwrap();
+    }
+}
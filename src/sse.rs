@@ -0,0 +1,112 @@
+use anyhow::Result;
+use tokio::io::{self, AsyncWriteExt};
+
+use crate::{
+    response::{Connection, Response},
+    router::BoxFuture,
+};
+
+/// Writes Server-Sent Events to a client, flushing each one so it arrives
+/// straight away.
+pub struct EventStream<'a> {
+    conn: &'a mut dyn Connection,
+}
+
+impl<'a> EventStream<'a> {
+    pub fn new(conn: &'a mut dyn Connection) -> Self {
+        Self { conn }
+    }
+
+    /// Send an unnamed event. Each line of `data` becomes its own `data:`
+    /// field, so multi-line payloads arrive intact.
+    pub async fn send(&mut self, data: &str) -> io::Result<()> {
+        self.write_event(None, data).await
+    }
+
+    /// Send an event the client can listen for by `name`.
+    pub async fn send_event(&mut self, name: &str, data: &str) -> io::Result<()> {
+        self.write_event(Some(name), data).await
+    }
+
+    async fn write_event(&mut self, name: Option<&str>, data: &str) -> io::Result<()> {
+        let mut frame = String::with_capacity(data.len() + 16);
+        if let Some(name) = name {
+            frame.push_str("event: ");
+            frame.push_str(name);
+            frame.push('\n');
+        }
+        for line in data.split('\n') {
+            frame.push_str("data: ");
+            frame.push_str(line);
+            frame.push('\n');
+        }
+        // A blank line ends the event
+        frame.push('\n');
+        self.conn.write_all(frame.as_bytes()).await?;
+        self.conn.flush().await
+    }
+}
+
+/// A `200` response that streams events from `f` until it returns, then
+/// closes the connection.
+///
+/// Sets `Content-Type: text/event-stream` and turns off caching and proxy
+/// buffering, so events reach the client as they're sent.
+pub fn response<F>(f: F) -> Response
+where
+    F: for<'a> FnOnce(EventStream<'a>) -> BoxFuture<'a, Result<()>> + Send + Sync + 'static,
+{
+    let mut resp = Response::new(200, "OK");
+    resp.header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .header("X-Accel-Buffering", "no")
+        .take_over(move |conn| f(EventStream::new(conn)));
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    async fn frames_of<F>(f: F) -> String
+    where
+        F: for<'a> FnOnce(&'a mut EventStream<'_>) -> BoxFuture<'a, io::Result<()>>,
+    {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let mut events = EventStream::new(&mut server);
+        f(&mut events).await.unwrap();
+        drop(server);
+
+        let mut out = String::new();
+        client.read_to_string(&mut out).await.unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn test_send_frames_data() {
+        let out = frames_of(|events| Box::pin(events.send("hello"))).await;
+        assert_eq!(out, "data: hello\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_send_splits_lines() {
+        let out = frames_of(|events| Box::pin(events.send("one\ntwo"))).await;
+        assert_eq!(out, "data: one\ndata: two\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_send_event_names_the_event() {
+        let out = frames_of(|events| Box::pin(events.send_event("tick", "1"))).await;
+        assert_eq!(out, "event: tick\ndata: 1\n\n");
+    }
+
+    #[test]
+    fn test_response_headers() {
+        let resp = response(|_| Box::pin(async { Ok(()) }));
+        assert_eq!(resp.status_code(), 200);
+        assert_eq!(resp.headers_all("Content-Type"), vec!["text/event-stream"]);
+        assert_eq!(resp.headers_all("Cache-Control"), vec!["no-cache"]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{config::ServerConfig, server};
+
+/// Start a server on an ephemeral localhost port, serving `files_dir` under
+/// `/files/` with every other setting at its default. It keeps accepting
+/// connections in a background task for as long as the runtime runs.
+///
+/// # Panics
+///
+/// If no localhost port can be bound.
+///
+/// # Examples
+///
+/// ```
+/// use http_server_rust::testutil::{raw_roundtrip, spawn_test_server};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let addr = spawn_test_server(".").await;
+/// let resp = raw_roundtrip(addr, b"GET /echo/hi HTTP/1.1\r\nHost: test\r\n\r\n").await;
+/// assert!(resp.starts_with(b"HTTP/1.1 200 OK\r\n"));
+/// assert!(resp.ends_with(b"\r\n\r\nhi"));
+/// # }
+/// ```
+pub async fn spawn_test_server(files_dir: impl Into<PathBuf>) -> SocketAddr {
+    spawn_test_server_with(ServerConfig {
+        directory: files_dir.into(),
+        ..ServerConfig::default()
+    })
+    .await
+}
+
+/// Like [`spawn_test_server`], with a caller-supplied config. Its address
+/// and port are ignored in favour of an ephemeral localhost port.
+///
+/// # Panics
+///
+/// If no localhost port can be bound.
+pub async fn spawn_test_server_with(config: ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("binding a test server port");
+    let addr = listener.local_addr().expect("test server address");
+    tokio::spawn(server::serve(listener, config));
+    addr
+}
+
+/// Send `raw` to `addr` on a fresh connection, exactly as given, then
+/// half-close it and return every byte the server sends back before it
+/// closes the connection.
+///
+/// # Panics
+///
+/// If connecting, writing or reading fails.
+pub async fn raw_roundtrip(addr: SocketAddr, raw: &[u8]) -> Vec<u8> {
+    let mut client = TcpStream::connect(addr)
+        .await
+        .expect("connecting to test server");
+    client.write_all(raw).await.expect("sending request");
+    client.shutdown().await.expect("half-closing connection");
+
+    let mut buf = Vec::new();
+    client
+        .read_to_end(&mut buf)
+        .await
+        .expect("reading response");
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::DEFAULT_SERVER_NAME;
+
+    #[tokio::test]
+    async fn test_server_serves_files_over_many_connections() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(tmp.path().join("a.txt"), "alpha").unwrap();
+        let addr = spawn_test_server(tmp.path()).await;
+
+        for _ in 0..2 {
+            let resp =
+                raw_roundtrip(addr, b"GET /files/a.txt HTTP/1.1\r\nHost: test\r\n\r\n").await;
+            let text = String::from_utf8(resp).unwrap();
+            assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+            assert!(text.ends_with("\r\n\r\nalpha"), "{}", text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_roundtrip_is_byte_exact() {
+        let addr = spawn_test_server(".").await;
+        // A fixed request ID leaves nothing in the response to vary
+        let resp = raw_roundtrip(
+            addr,
+            b"GET /echo/x HTTP/1.1\r\nHost: test\r\nX-Request-Id: abc\r\n\r\n",
+        )
+        .await;
+        let expected = format!(
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain\r\n\
+             Vary: Accept\r\n\
+             X-Request-Id: abc\r\n\
+             Connection: keep-alive\r\n\
+             Server: {}\r\n\
+             Content-Length: 1\r\n\
+             \r\n\
+             x",
+            DEFAULT_SERVER_NAME
+        );
+        assert_eq!(String::from_utf8(resp).unwrap(), expected);
+    }
+}
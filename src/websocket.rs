@@ -0,0 +1,245 @@
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1_smol::Sha1;
+
+use crate::{
+    headers::contains_token,
+    request::Request,
+    response::{Connection, Response},
+    router::BoxFuture,
+};
+
+/// Appended to the client's key before hashing (RFC 6455 §1.3).
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The only WebSocket protocol version in use (RFC 6455 §4.1).
+const VERSION: &str = "13";
+
+/// The `Sec-WebSocket-Accept` value answering a client's
+/// `Sec-WebSocket-Key`: base64 of the SHA-1 of the key and a fixed GUID.
+pub fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(ACCEPT_GUID.as_bytes());
+    STANDARD.encode(sha.digest().bytes())
+}
+
+/// Whether `request` asks to switch to WebSocket: a `GET` with
+/// `Upgrade: websocket` and `Connection: Upgrade`.
+pub fn is_upgrade_request(request: &Request) -> bool {
+    request.method == "GET"
+        && request
+            .header_values("Upgrade")
+            .iter()
+            .any(|v| contains_token(v, "websocket"))
+        && request
+            .header_values("Connection")
+            .iter()
+            .any(|v| contains_token(v, "upgrade"))
+}
+
+/// Answer a WebSocket opening handshake.
+///
+/// On success this is `101 Switching Protocols`, and `on_open` gets the raw
+/// connection once the response is sent; framing is up to it. Otherwise the
+/// response says what's wrong: `426 Upgrade Required` for a request that
+/// isn't a version 13 upgrade, `400 Bad Request` for a missing or malformed
+/// `Sec-WebSocket-Key`.
+pub fn upgrade<F>(request: &Request, on_open: F) -> Response
+where
+    F: for<'a> FnOnce(&'a mut dyn Connection) -> BoxFuture<'a, Result<()>> + Send + Sync + 'static,
+{
+    if !is_upgrade_request(request) {
+        let mut resp = Response::error(426, "Upgrade Required");
+        resp.header("Upgrade", "websocket");
+        return resp;
+    }
+    if request.header_value("Sec-WebSocket-Version") != Some(VERSION) {
+        let mut resp = Response::error(426, "Upgrade Required");
+        resp.header("Sec-WebSocket-Version", VERSION);
+        return resp;
+    }
+    // The key must be a base64-encoded 16-byte nonce
+    let Some(key) = request
+        .header_value("Sec-WebSocket-Key")
+        .filter(|k| STANDARD.decode(k).is_ok_and(|nonce| nonce.len() == 16))
+    else {
+        return Response::error(400, "Bad Request");
+    };
+
+    let mut resp = Response::new(101, "Switching Protocols");
+    resp.header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &accept_key(key))
+        .take_over(on_open);
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        config::ServerConfig, handlers::handle_request, metrics::Metrics, request::HttpVersion,
+        router::Router,
+    };
+
+    /// The sample key from RFC 6455 §1.3.
+    const RFC_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    fn handshake_request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: "/ws".to_string(),
+            query: None,
+            http_version: HttpVersion::Http11,
+            headers: headers
+                .iter()
+                .map(|&(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+            peer_addr: None,
+            request_id: None,
+        }
+    }
+
+    fn valid_headers() -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("Host", "localhost"),
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Key", RFC_KEY),
+            ("Sec-WebSocket-Version", "13"),
+        ]
+    }
+
+    fn ignore_connection(_: &mut dyn Connection) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    // ── Handshake ────────────────────────────────────────────────────
+
+    #[test]
+    fn test_accept_key_rfc_example() {
+        assert_eq!(accept_key(RFC_KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_is_upgrade_request() {
+        assert!(is_upgrade_request(&handshake_request(&valid_headers())));
+        assert!(is_upgrade_request(&handshake_request(&[
+            ("upgrade", "WebSocket"),
+            ("connection", "upgrade"),
+        ])));
+        assert!(!is_upgrade_request(&handshake_request(&[(
+            "Upgrade",
+            "websocket"
+        )])));
+        assert!(!is_upgrade_request(&handshake_request(&[(
+            "Connection",
+            "Upgrade"
+        )])));
+    }
+
+    #[test]
+    fn test_upgrade_answers_101() {
+        let resp = upgrade(&handshake_request(&valid_headers()), ignore_connection);
+        assert_eq!(resp.status_code(), 101);
+        assert_eq!(
+            resp.headers_all("Sec-WebSocket-Accept"),
+            vec!["s3pPLMBiTxaQ9kYGzzhZRbK+xOo="]
+        );
+        assert_eq!(resp.headers_all("Upgrade"), vec!["websocket"]);
+        assert_eq!(resp.headers_all("Connection"), vec!["Upgrade"]);
+    }
+
+    #[test]
+    fn test_upgrade_without_upgrade_headers_is_426() {
+        let resp = upgrade(
+            &handshake_request(&[("Sec-WebSocket-Key", RFC_KEY)]),
+            ignore_connection,
+        );
+        assert_eq!(resp.status_code(), 426);
+        assert_eq!(resp.headers_all("Upgrade"), vec!["websocket"]);
+    }
+
+    #[test]
+    fn test_upgrade_wrong_version_is_426() {
+        let mut headers = valid_headers();
+        headers.retain(|(k, _)| *k != "Sec-WebSocket-Version");
+        headers.push(("Sec-WebSocket-Version", "8"));
+        let resp = upgrade(&handshake_request(&headers), ignore_connection);
+        assert_eq!(resp.status_code(), 426);
+        assert_eq!(resp.headers_all("Sec-WebSocket-Version"), vec!["13"]);
+    }
+
+    #[test]
+    fn test_upgrade_bad_key_is_400() {
+        for key in [None, Some("not base64!"), Some("c2hvcnQ=")] {
+            let mut headers = valid_headers();
+            headers.retain(|(k, _)| *k != "Sec-WebSocket-Key");
+            if let Some(key) = key {
+                headers.push(("Sec-WebSocket-Key", key));
+            }
+            let resp = upgrade(&handshake_request(&headers), ignore_connection);
+            assert_eq!(resp.status_code(), 400, "{:?}", key);
+        }
+    }
+
+    // ── Integration ──────────────────────────────────────────────────
+
+    #[tokio::test]
+    async fn test_handshake_hands_over_the_connection() {
+        let mut router = Router::new();
+        router.route("GET", "/ws", |req, _, _| {
+            Box::pin(async move {
+                Ok(upgrade(req, |conn| {
+                    Box::pin(async move {
+                        conn.write_all(b"hello ").await?;
+                        let mut ping = [0u8; 4];
+                        conn.read_exact(&mut ping).await?;
+                        conn.write_all(b"got ").await?;
+                        conn.write_all(&ping).await?;
+                        Ok(())
+                    })
+                }))
+            })
+        });
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handler = tokio::spawn(handle_request(
+            server,
+            None,
+            Arc::new(ServerConfig::default()),
+            Arc::new(router),
+            Arc::new(Metrics::new()),
+        ));
+
+        // Send data right behind the handshake: it must reach the callback
+        // even though the request parser has already buffered it
+        let mut raw = b"GET /ws HTTP/1.1\r\n".to_vec();
+        for (k, v) in valid_headers() {
+            raw.extend_from_slice(format!("{}: {}\r\n", k, v).as_bytes());
+        }
+        raw.extend_from_slice(b"\r\nping");
+        client.write_all(&raw).await.unwrap();
+
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        handler.await.unwrap().unwrap();
+
+        let text = String::from_utf8(received).unwrap();
+        let (head, rest) = text.split_once("\r\n\r\n").unwrap();
+        assert!(
+            head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+            "{}",
+            head
+        );
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        assert!(head.contains("Connection: Upgrade"));
+        assert!(!head.contains("Content-Length"), "{}", head);
+        assert_eq!(rest, "hello got ping");
+    }
+}